@@ -0,0 +1,33 @@
+use super::users::page_range;
+use crate::auth::audit::{list_audit_log, AuditEntry};
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::{Query, State};
+use axum::Json;
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogQuery {
+    /// Only the entries about this record, like `user:jane`.
+    target: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+/// List the audit trail of the admin writes, the most recent first.
+pub async fn api_list_audit_log(
+    State(state): State<RouterState>,
+    Query(query): Query<AuditLogQuery>,
+) -> ApiResult<Json<Vec<AuditEntry>>> {
+    let (page, per_page) = page_range(query.page, query.per_page)?;
+    let target = query
+        .target
+        .map(|target| target.parse::<Thing>())
+        .transpose()
+        .map_err(|_| {
+            BackendError::ValidationFailed("The target must be a record id".to_string())
+        })?;
+    Ok(Json(
+        list_audit_log(&state, target, (page - 1) * per_page, per_page).await?,
+    ))
+}
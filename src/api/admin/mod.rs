@@ -0,0 +1,47 @@
+use crate::auth::cookie_jwt::cookie_jwt_bearer_resolver;
+use crate::auth::guard::GuardLayer;
+use crate::auth::rbac::RequirePermission;
+use crate::RouterState;
+use axum::routing::{get, patch, post};
+use axum::Router;
+use tower_cookies::CookieManagerLayer;
+
+mod audit_log;
+mod users;
+
+/// Routes managing the users, reading needing the `users:read` permission and writing the
+/// `users:write` one, both granted by the `admin` role.
+pub fn create_admin_router(state: RouterState) -> Router {
+    let read_routes = Router::new()
+        .route("/admin/users", get(users::api_list_users))
+        .route("/admin/users/:id", get(users::api_get_user))
+        .route("/admin/audit-log", get(audit_log::api_list_audit_log))
+        .route_layer(GuardLayer::new(&state, RequirePermission("users:read")));
+    let write_routes = Router::new()
+        .route("/admin/users", post(users::api_create_user))
+        .route(
+            "/admin/users/:id",
+            patch(users::api_update_user).delete(users::api_delete_user),
+        )
+        .route("/admin/users/:id/disable", post(users::api_disable_user))
+        .route("/admin/users/:id/enable", post(users::api_enable_user))
+        .route(
+            "/admin/users/:id/reset-password",
+            post(users::api_force_password_reset),
+        )
+        .route("/admin/users/:id/unlock", post(users::api_unlock_user))
+        .route(
+            "/admin/users/:id/revoke-sessions",
+            post(users::api_revoke_user_sessions),
+        )
+        .route_layer(GuardLayer::new(&state, RequirePermission("users:write")));
+
+    read_routes
+        .merge(write_routes)
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            cookie_jwt_bearer_resolver,
+        ))
+        .layer(CookieManagerLayer::new())
+        .with_state(state)
+}
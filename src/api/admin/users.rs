@@ -0,0 +1,560 @@
+use super::super::password_reset::send_reset_email;
+use super::super::validation::{validate_email, validate_password, validate_username};
+use crate::auth::audit::{audited, Actor};
+use crate::auth::client_info::ClientInfo;
+use crate::auth::password::hash_password;
+use crate::auth::principal::Principal;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use surrealdb::sql::{Id, Thing};
+
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+/// The fields of a user shown to admins, never its secrets.
+const USER_FIELDS: &str = "id, username, email, verified, disabled, <option<string>> array::max(select value locked_until from login_failure where user=$parent.id and locked_until > time::now()) as locked_until, totp_enabled, ->has_role->role.name as roles";
+
+/// Whether a client is locked out of the user after too many failed logins.
+const LOCKED: &str =
+    "count(select id from login_failure where user=$parent.id and locked_until > time::now()) > 0";
+const NOT_LOCKED: &str =
+    "count(select id from login_failure where user=$parent.id and locked_until > time::now()) = 0";
+
+/// Log a user out everywhere: its tokens, sessions and refresh tokens stop working.
+const REVOKE_SESSIONS: &str = "update $user_id set security_stamp=rand::uuid();
+    update session set revoked_at=time::now() where user=$user_id and revoked_at=none;
+    update refresh_token set revoked_at=time::now() where user=$user_id and revoked_at=none";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminUser {
+    pub id: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub verified: bool,
+    pub disabled: bool,
+    /// Until when a client is locked out of the user after too many failed logins, the latest.
+    pub locked_until: Option<String>,
+    pub totp_enabled: bool,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBAdminUser {
+    id: Thing,
+    username: String,
+    email: Option<String>,
+    #[serde(default)]
+    verified: bool,
+    #[serde(default)]
+    disabled: bool,
+    locked_until: Option<String>,
+    #[serde(default)]
+    totp_enabled: bool,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+impl From<DBAdminUser> for AdminUser {
+    fn from(user: DBAdminUser) -> Self {
+        Self {
+            id: user.id.to_string(),
+            username: user.username,
+            email: user.email,
+            verified: user.verified,
+            disabled: user.disabled,
+            locked_until: user.locked_until,
+            totp_enabled: user.totp_enabled,
+            roles: user.roles,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserPage {
+    pub users: Vec<AdminUser>,
+    pub page: usize,
+    pub per_page: usize,
+    /// How many users match the filters, over all the pages.
+    pub total: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListUsersQuery {
+    page: Option<usize>,
+    per_page: Option<usize>,
+    /// A part of the username or the email, whatever the case.
+    q: Option<String>,
+    disabled: Option<bool>,
+    verified: Option<bool>,
+    locked: Option<bool>,
+    /// The name of a role the users have.
+    role: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserPayload {
+    username: String,
+    email: String,
+    /// The user sets its password with `/password/forgot` without it.
+    password: Option<String>,
+    #[serde(default)]
+    verified: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateUserPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verified: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBTaken {
+    username_taken: bool,
+    email_taken: bool,
+}
+
+/// Check a 1-based page of `per_page` items, 20 by default and 100 at most.
+pub(super) fn page_range(
+    page: Option<usize>,
+    per_page: Option<usize>,
+) -> ApiResult<(usize, usize)> {
+    let page = page.unwrap_or(1);
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if page == 0 || !(1..=MAX_PER_PAGE).contains(&per_page) {
+        return Err(BackendError::ValidationFailed(format!(
+            "The page starts at 1 and has between 1 and {MAX_PER_PAGE} items"
+        )));
+    }
+    Ok((page, per_page))
+}
+
+fn parse_user_id(user_id: &str) -> ApiResult<Thing> {
+    let user_id = match user_id.starts_with("user:") {
+        true => user_id.to_string(),
+        false => format!("user:{user_id}"),
+    };
+    user_id
+        .parse::<Thing>()
+        .ok()
+        .filter(|user_id| user_id.tb == "user")
+        .ok_or(BackendError::NotFound)
+}
+
+/// Refuse an admin acting on its own account, which could lock every admin out.
+fn ensure_not_self(actor: &Actor, user_id: &Thing) -> ApiResult<()> {
+    if &actor.id == user_id {
+        Err(BackendError::ValidationFailed(
+            "You can't do this to your own account".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Refuse to remove the only owner of an organization, which would leave it without one.
+async fn ensure_not_last_owner(state: &RouterState, user_id: &Thing) -> ApiResult<()> {
+    let mut result = state
+        .db
+        .query("count(select id from membership where in=$user_id and role=\"owner\" and count(select id from membership where out=$parent.out and role=\"owner\") = 1) > 0")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let last_owner: Option<bool> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if last_owner.unwrap_or_default() {
+        Err(BackendError::ValidationFailed(
+            "The user is the last owner of an organization".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+async fn load_admin_user(state: &RouterState, user_id: &Thing) -> ApiResult<AdminUser> {
+    let mut result = state
+        .db
+        .query(format!("select {USER_FIELDS} from $user_id"))
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user: Option<DBAdminUser> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    user.map(Into::into).ok_or(BackendError::NotFound)
+}
+
+/// Refuse a username or an email another user already has.
+async fn ensure_available(
+    state: &RouterState,
+    username: Option<String>,
+    email: Option<String>,
+    user_id: Option<Thing>,
+) -> ApiResult<()> {
+    let mut result = state
+        .db
+        .query("return { username_taken: $username != none and count(select id from user where username=$username and id!=$user_id) > 0, email_taken: $email != none and count(select id from user where email=$email and id!=$user_id) > 0 }")
+        .bind(("username", username))
+        .bind(("email", email))
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let taken: Option<DBTaken> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    match taken {
+        Some(DBTaken {
+            username_taken: true,
+            ..
+        }) => Err(BackendError::UsernameTaken),
+        Some(DBTaken {
+            email_taken: true, ..
+        }) => Err(BackendError::EmailTaken),
+        Some(_) => Ok(()),
+        None => Err(BackendError::SomethingWentWrong),
+    }
+}
+
+/// List the users matching the filters, by username.
+pub async fn api_list_users(
+    State(state): State<RouterState>,
+    Query(query): Query<ListUsersQuery>,
+) -> ApiResult<Json<UserPage>> {
+    let (page, per_page) = page_range(query.page, query.per_page)?;
+    let mut conditions = vec!["true"];
+    if query.q.is_some() {
+        conditions.push("(string::contains(string::lowercase(username), $q) or string::contains(string::lowercase(email ?? \"\"), $q))");
+    }
+    if query.disabled.is_some() {
+        conditions.push("disabled=$disabled");
+    }
+    if query.verified.is_some() {
+        conditions.push("verified=$verified");
+    }
+    match query.locked {
+        Some(true) => conditions.push(LOCKED),
+        Some(false) => conditions.push(NOT_LOCKED),
+        None => {}
+    }
+    if query.role.is_some() {
+        conditions.push("$role inside ->has_role->role.name");
+    }
+    let conditions = conditions.join(" and ");
+
+    let mut result = state
+        .db
+        .query(format!(
+            "select {USER_FIELDS} from user where {conditions} order by username limit $limit start $start;
+            count(select id from user where {conditions});"
+        ))
+        .bind(("q", query.q.map(|q| q.to_lowercase())))
+        .bind(("disabled", query.disabled))
+        .bind(("verified", query.verified))
+        .bind(("role", query.role))
+        .bind(("limit", per_page))
+        .bind(("start", (page - 1) * per_page))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let users: Vec<DBAdminUser> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let total: Option<usize> = result
+        .take(1)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(Json(UserPage {
+        users: users.into_iter().map(Into::into).collect(),
+        page,
+        per_page,
+        total: total.unwrap_or_default(),
+    }))
+}
+
+pub async fn api_get_user(
+    State(state): State<RouterState>,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<AdminUser>> {
+    let user_id = parse_user_id(&user_id)?;
+    Ok(Json(load_admin_user(&state, &user_id).await?))
+}
+
+/// Create a user, with a password or letting it choose one with `/password/forgot`.
+pub async fn api_create_user(
+    State(state): State<RouterState>,
+    principal: Principal,
+    client: ClientInfo,
+    Json(payload): Json<CreateUserPayload>,
+) -> ApiResult<Json<AdminUser>> {
+    let actor = Actor::new(&principal, client)?;
+    validate_username(&payload.username)?;
+    validate_email(&payload.email)?;
+    let password_hash = match payload.password {
+        Some(password) => {
+            validate_password(&password)?;
+            Some(hash_password(password).await?)
+        }
+        None => None,
+    };
+    ensure_available(
+        &state,
+        Some(payload.username.clone()),
+        Some(payload.email.clone()),
+        None,
+    )
+    .await?;
+
+    let user_id = Thing::from(("user", Id::rand()));
+    let details = json!({
+        "username": payload.username,
+        "email": payload.email,
+        "verified": payload.verified,
+    });
+    audited(
+        &state,
+        &actor,
+        "user.create",
+        &user_id,
+        Some(details),
+        "create $user_id set username=$username, email=$email, password_hash=$password_hash, verified=$verified",
+        |query| {
+            query
+                .bind(("user_id", user_id.clone()))
+                .bind(("username", payload.username))
+                .bind(("email", payload.email))
+                .bind(("password_hash", password_hash))
+                .bind(("verified", payload.verified))
+        },
+    )
+    .await?;
+    Ok(Json(load_admin_user(&state, &user_id).await?))
+}
+
+/// Change the username, the email or the verification of a user.
+pub async fn api_update_user(
+    State(state): State<RouterState>,
+    principal: Principal,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+    Json(payload): Json<UpdateUserPayload>,
+) -> ApiResult<Json<AdminUser>> {
+    let actor = Actor::new(&principal, client)?;
+    let user_id = parse_user_id(&user_id)?;
+    load_admin_user(&state, &user_id).await?;
+    if let Some(username) = &payload.username {
+        validate_username(username)?;
+    }
+    if let Some(email) = &payload.email {
+        validate_email(email)?;
+    }
+    ensure_available(
+        &state,
+        payload.username.clone(),
+        payload.email.clone(),
+        Some(user_id.clone()),
+    )
+    .await?;
+
+    let details = serde_json::to_value(&payload).map_err(|_| BackendError::SerializationFailed)?;
+    audited(
+        &state,
+        &actor,
+        "user.update",
+        &user_id,
+        Some(details),
+        "update $user_id set username=$username ?? username, email=$email ?? email, verified=$verified ?? verified",
+        |query| {
+            query
+                .bind(("user_id", user_id.clone()))
+                .bind(("username", payload.username))
+                .bind(("email", payload.email))
+                .bind(("verified", payload.verified))
+        },
+    )
+    .await?;
+    Ok(Json(load_admin_user(&state, &user_id).await?))
+}
+
+/// Run an audited write on an existing user, then return it.
+async fn write_user(
+    state: &RouterState,
+    actor: &Actor,
+    user_id: &Thing,
+    action: &str,
+    statements: &str,
+) -> ApiResult<AdminUser> {
+    load_admin_user(state, user_id).await?;
+    audited(state, actor, action, user_id, None, statements, |query| {
+        query.bind(("user_id", user_id.clone()))
+    })
+    .await?;
+    load_admin_user(state, user_id).await
+}
+
+/// Disable a user, which can't log in anymore and is logged out everywhere.
+pub async fn api_disable_user(
+    State(state): State<RouterState>,
+    principal: Principal,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<AdminUser>> {
+    let actor = Actor::new(&principal, client)?;
+    let user_id = parse_user_id(&user_id)?;
+    ensure_not_self(&actor, &user_id)?;
+    let statements = format!("update $user_id set disabled=true; {REVOKE_SESSIONS}");
+    Ok(Json(
+        write_user(&state, &actor, &user_id, "user.disable", &statements).await?,
+    ))
+}
+
+pub async fn api_enable_user(
+    State(state): State<RouterState>,
+    principal: Principal,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<AdminUser>> {
+    let actor = Actor::new(&principal, client)?;
+    let user_id = parse_user_id(&user_id)?;
+    let statements = "update $user_id set disabled=false";
+    Ok(Json(
+        write_user(&state, &actor, &user_id, "user.enable", statements).await?,
+    ))
+}
+
+/// Remove the password of a user, logging it out everywhere, and email it a reset link.
+pub async fn api_force_password_reset(
+    State(state): State<RouterState>,
+    principal: Principal,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<AdminUser>> {
+    let actor = Actor::new(&principal, client)?;
+    let user_id = parse_user_id(&user_id)?;
+    let email = load_admin_user(&state, &user_id)
+        .await?
+        .email
+        .ok_or_else(|| {
+            BackendError::ValidationFailed(
+                "The user has no email to send a reset link to".to_string(),
+            )
+        })?;
+    let statements = format!("update $user_id set password_hash=none; {REVOKE_SESSIONS}");
+    let user = write_user(&state, &actor, &user_id, "user.reset_password", &statements).await?;
+    if let Err(err) = send_reset_email(&state, email).await {
+        tracing::warn!("Failed to send the password reset email of `{user_id}`: {err:?}");
+    }
+    Ok(Json(user))
+}
+
+/// Lift the lockouts of every client from a user after too many failed logins.
+pub async fn api_unlock_user(
+    State(state): State<RouterState>,
+    principal: Principal,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<AdminUser>> {
+    let actor = Actor::new(&principal, client)?;
+    let user_id = parse_user_id(&user_id)?;
+    let statements = "delete login_failure where user=$user_id";
+    Ok(Json(
+        write_user(&state, &actor, &user_id, "user.unlock", statements).await?,
+    ))
+}
+
+/// Log a user out of every session.
+pub async fn api_revoke_user_sessions(
+    State(state): State<RouterState>,
+    principal: Principal,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<AdminUser>> {
+    let actor = Actor::new(&principal, client)?;
+    let user_id = parse_user_id(&user_id)?;
+    Ok(Json(
+        write_user(
+            &state,
+            &actor,
+            &user_id,
+            "user.revoke_sessions",
+            REVOKE_SESSIONS,
+        )
+        .await?,
+    ))
+}
+
+/// Delete a user along with its credentials, sessions, tokens, relations and the invitations it sent.
+///
+/// The last owner of an organization can't be deleted, another owner must be added first.
+pub async fn api_delete_user(
+    State(state): State<RouterState>,
+    principal: Principal,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<Value>> {
+    let actor = Actor::new(&principal, client)?;
+    let user_id = parse_user_id(&user_id)?;
+    ensure_not_self(&actor, &user_id)?;
+    let user = load_admin_user(&state, &user_id).await?;
+    ensure_not_last_owner(&state, &user_id).await?;
+
+    let mut details = Map::new();
+    details.insert("username".to_string(), json!(user.username));
+    details.insert("email".to_string(), json!(user.email));
+    let owned_tables = [
+        "session",
+        "refresh_token",
+        "api_key",
+        "credential",
+        "webauthn_challenge",
+        "recovery_code",
+        "mfa_challenge",
+        "magic_link",
+        "password_reset",
+        "email_verification",
+        "identity",
+        "oauth_consent",
+        "oauth_code",
+        "login_failure",
+    ];
+    let edge_tables = [
+        "has_role",
+        "member_of",
+        "owner",
+        "editor",
+        "viewer",
+        "membership",
+    ];
+    let statements = owned_tables
+        .iter()
+        .map(|table| format!("delete {table} where user=$user_id;"))
+        .chain(
+            edge_tables
+                .iter()
+                .map(|table| format!("delete {table} where in=$user_id;")),
+        )
+        .chain([
+            "delete invitation where invited_by=$user_id;".to_string(),
+            "update invitation set accepted_by=none where accepted_by=$user_id;".to_string(),
+            "delete $user_id".to_string(),
+        ])
+        .collect::<Vec<_>>()
+        .join("\n");
+    audited(
+        &state,
+        &actor,
+        "user.delete",
+        &user_id,
+        Some(Value::Object(details)),
+        &statements,
+        |query| query.bind(("user_id", user_id.clone())),
+    )
+    .await?;
+    Ok(Json(json!({
+        "value": "The user has been deleted",
+    })))
+}
@@ -0,0 +1,13 @@
+use crate::auth::api_key::BearerOrApiKeyClaims;
+use crate::ApiResult;
+use axum::Json;
+use serde_json::{json, Value};
+
+/// A page for the users allowed to see the other users, guarded by `RequirePermission`.
+pub async fn admin_bearer_content(
+    BearerOrApiKeyClaims(bearer): BearerOrApiKeyClaims,
+) -> ApiResult<Json<Value>> {
+    Ok(Json(json!({
+        "value": format!("nice admin page here! Oh btw your id is: `{}`", bearer.sub),
+    })))
+}
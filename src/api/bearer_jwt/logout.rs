@@ -0,0 +1,40 @@
+use crate::auth::bearer_jwt::BearerJWTClaims;
+use crate::auth::refresh_token::revoke_refresh_token;
+use crate::auth::session::revoke_session;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutPayload {
+    /// The refresh token of the session, revoked along with the access token.
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+pub async fn logout_bearer(
+    State(state): State<RouterState>,
+    bearer: BearerJWTClaims,
+    payload: Json<LogoutPayload>,
+) -> ApiResult<Json<Value>> {
+    state
+        .revocations
+        .revoke(&state.db, &bearer.jti, bearer.exp)
+        .await?;
+    if let Some(refresh_token) = &payload.refresh_token {
+        revoke_refresh_token(&state, refresh_token, &bearer.sub).await?;
+    }
+    if let Some(sid) = &bearer.sid {
+        revoke_session(&state, &bearer.sub, sid)
+            .await
+            .or_else(|err| match err {
+                BackendError::NotFound => Ok(()),
+                err => Err(err),
+            })?;
+    }
+    Ok(Json(json!({
+        "value": "You're now disconnected",
+    })))
+}
@@ -1,10 +1,17 @@
+use crate::auth::guard::GuardLayer;
+use crate::auth::policy::RequirePolicy;
+use crate::auth::rbac::RequirePermission;
 use crate::RouterState;
 use axum::routing::{get, post};
 use axum::Router;
 use serde::{Deserialize, Serialize};
 
+mod admin_content;
 mod login;
+mod logout;
 mod protected_content;
+mod refresh;
+mod report;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
@@ -17,6 +24,18 @@ pub fn create_bearer_jwt_router(state: RouterState) -> Router {
             "/bearer/page",
             get(protected_content::protected_bearer_content),
         )
+        .route(
+            "/bearer/admin",
+            get(admin_content::admin_bearer_content)
+                .route_layer(GuardLayer::new(&state, RequirePermission("users:read"))),
+        )
+        .route(
+            "/bearer/reports/:report_id",
+            get(report::api_report)
+                .route_layer(GuardLayer::new(&state, RequirePolicy("reports:read"))),
+        )
         .route("/bearer/login", post(login::api_login_cookie_jwt))
+        .route("/bearer/refresh", post(refresh::api_refresh_bearer_jwt))
+        .route("/bearer/logout", post(logout::logout_bearer))
         .with_state(state)
 }
@@ -1,10 +1,21 @@
 use super::User;
-use crate::auth::bearer_jwt::{deserialize_bearer_claims, BearerJWTClaims};
+use crate::auth::api_key::BearerOrApiKeyClaims;
+use crate::auth::bearer_jwt::deserialize_bearer_claims;
+use crate::auth::service_account::Service;
 use crate::ApiResult;
 use axum::Json;
 use serde_json::{json, Value};
 
-pub async fn protected_bearer_content(bearer: BearerJWTClaims) -> ApiResult<Json<Value>> {
+/// A page for bearer tokens, which also accepts the API keys of the users.
+pub async fn protected_bearer_content(
+    BearerOrApiKeyClaims(bearer): BearerOrApiKeyClaims,
+) -> ApiResult<Json<Value>> {
+    if bearer.is_service() {
+        let service: Service = deserialize_bearer_claims(bearer)?;
+        return Ok(Json(json!({
+            "value": format!("nice secret page here! Oh btw your service is: `{}`", service.client_id),
+        })));
+    }
     let bearer: User = deserialize_bearer_claims(bearer)?;
     Ok(Json(json!({
         "value": format!("nice secret page here! Oh btw your user id is: `{}`", bearer.user_id),
@@ -0,0 +1,32 @@
+use super::super::ResponseBearer;
+use super::User;
+use crate::auth::bearer_jwt::encode_required_jwt_bearer_claims;
+use crate::auth::refresh_token::rotate_refresh_token;
+use crate::{ApiResult, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshPayload {
+    refresh_token: String,
+}
+
+pub async fn api_refresh_bearer_jwt(
+    State(state): State<RouterState>,
+    payload: Json<RefreshPayload>,
+) -> ApiResult<Json<ResponseBearer>> {
+    let (subject, refresh_token) = rotate_refresh_token(&state, &payload.refresh_token).await?;
+
+    let bearer = encode_required_jwt_bearer_claims(
+        &subject,
+        User {
+            user_id: subject.user_id.clone(),
+        },
+    )?;
+
+    Ok(Json(ResponseBearer {
+        bearer,
+        refresh_token: Some(refresh_token),
+    }))
+}
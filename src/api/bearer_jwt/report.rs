@@ -0,0 +1,15 @@
+use crate::auth::principal::Principal;
+use crate::ApiResult;
+use axum::extract::Path;
+use axum::Json;
+use serde_json::{json, Value};
+
+/// A report, guarded by the `reports:read` policies.
+pub async fn api_report(
+    principal: Principal,
+    Path(report_id): Path<String>,
+) -> ApiResult<Json<Value>> {
+    Ok(Json(json!({
+        "value": format!("report `{report_id}`, read by `{}`", principal.sub),
+    })))
+}
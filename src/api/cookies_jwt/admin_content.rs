@@ -0,0 +1,11 @@
+use crate::auth::cookie_jwt::CookieJWTClaims;
+use crate::ApiResult;
+use axum::Json;
+use serde_json::{json, Value};
+
+/// A page for the users allowed to see the other users, guarded by `RequirePermission`.
+pub async fn admin_cookie_content(session: CookieJWTClaims) -> ApiResult<Json<Value>> {
+    Ok(Json(json!({
+        "value": format!("nice admin page here! Oh btw your user id is: `{}`", session.sub),
+    })))
+}
@@ -1,11 +1,14 @@
-use super::super::ResponseBearer;
+use super::super::credentials::{check_credentials, ensure_email_verified};
+use super::super::{ResponseBearer, ResponseLogin, ResponseMfaPending};
 use super::User;
-use crate::auth::cookie_jwt::encode_cookie_jwt_bearer_claims;
-use crate::{ApiResult, BackendError, RouterState};
+use crate::auth::client_info::ClientInfo;
+use crate::auth::cookie_session::start_cookie_session;
+use crate::auth::mfa::create_mfa_challenge;
+use crate::auth::session::SessionKind;
+use crate::{ApiResult, RouterState};
 use axum::extract::State;
 use axum::Json;
-use serde::{Deserialize, Serialize};
-use surrealdb::sql::Thing;
+use serde::Deserialize;
 use tower_cookies::Cookies;
 
 #[derive(Debug, Deserialize)]
@@ -14,42 +17,37 @@ pub struct LoginPayload {
     password: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct DBUser {
-    user_id: Thing,
-    username: String,
-    password: String,
-}
-
 pub async fn api_login_cookie_jwt(
     cookies: Cookies,
+    client: ClientInfo,
     State(state): State<RouterState>,
     payload: Json<LoginPayload>,
-) -> ApiResult<Json<ResponseBearer>> {
-    let db = &state.db;
-
-    let mut result = db.query("select id as user_id, username, password from user where username=$username and password=$password")
-        .bind(("username", payload.username.clone()))
-        .bind(("password", payload.password.clone()))
-        .await
-        .map_err(|_| BackendError::SomethingWentWrong)?;
-
-    let result: Vec<DBUser> = result
-        .take(0)
-        .map_err(|_| BackendError::SomethingWentWrong)?;
-
-    let user = result.first().ok_or(BackendError::InvalidCredentials)?;
-
-    if user.username != payload.password.as_str() || user.password != payload.username.as_str() {
-        return Err(BackendError::InvalidCredentials);
+) -> ApiResult<Json<ResponseLogin>> {
+    let user = check_credentials(&state, &client, &payload.username, &payload.password).await?;
+    ensure_email_verified(&user)?;
+    let methods = user.mfa_methods();
+    if !methods.is_empty() {
+        let mfa_pending =
+            create_mfa_challenge(&state, &user.token_subject(), SessionKind::Cookie).await?;
+        return Ok(Json(ResponseLogin::MfaPending(ResponseMfaPending {
+            mfa_pending,
+            methods,
+        })));
     }
 
-    let bearer = encode_cookie_jwt_bearer_claims(
+    let bearer = start_cookie_session(
+        &state,
         cookies,
+        &user.token_subject(),
         User {
-            user_id: "user_id:big_looser".to_string(),
+            user_id: user.user_id.to_string(),
         },
-    )?;
-
-    Ok(Json(ResponseBearer { bearer }))
+        &client,
+    )
+    .await?;
+
+    Ok(Json(ResponseLogin::Bearer(ResponseBearer {
+        bearer,
+        refresh_token: None,
+    })))
 }
@@ -1,11 +1,18 @@
-use crate::auth::cookie_jwt::remove_cookie_jwt_bearer_claims;
-use crate::ApiResult;
+use crate::auth::cookie_jwt::CookieJWTClaims;
+use crate::auth::cookie_session::end_cookie_session;
+use crate::{ApiResult, RouterState};
+use axum::extract::State;
 use axum::Json;
 use serde_json::{json, Value};
 use tower_cookies::Cookies;
 
-pub async fn logout_cookie(cookies: Cookies, _: Json<Value>) -> ApiResult<Json<Value>> {
-    remove_cookie_jwt_bearer_claims(cookies);
+pub async fn logout_cookie(
+    cookies: Cookies,
+    State(state): State<RouterState>,
+    claims: Option<CookieJWTClaims>,
+    _: Json<Value>,
+) -> ApiResult<Json<Value>> {
+    end_cookie_session(&state, cookies, claims.as_ref()).await?;
     Ok(Json(json!({
         "value": "You're now disconnected",
     })))
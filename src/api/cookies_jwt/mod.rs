@@ -1,10 +1,13 @@
 use crate::auth::cookie_jwt::{cookie_jwt_bearer_auth, cookie_jwt_bearer_resolver};
+use crate::auth::guard::GuardLayer;
+use crate::auth::rbac::RequirePermission;
 use crate::RouterState;
 use axum::routing::{get, post};
 use axum::Router;
 use serde::{Deserialize, Serialize};
 use tower_cookies::CookieManagerLayer;
 
+mod admin_content;
 mod login;
 mod logout;
 mod protected_content;
@@ -20,10 +23,18 @@ pub fn create_cookie_jwt_router(state: RouterState) -> Router {
             "/cookie/page",
             get(protected_content::protected_cookie_content),
         )
+        .route(
+            "/cookie/admin",
+            get(admin_content::admin_cookie_content)
+                .route_layer(GuardLayer::new(&state, RequirePermission("users:read"))),
+        )
         .layer(axum::middleware::from_fn(cookie_jwt_bearer_auth))
         .route("/cookie/logout", post(logout::logout_cookie))
         .route("/cookie/login", post(login::api_login_cookie_jwt))
-        .layer(axum::middleware::from_fn(cookie_jwt_bearer_resolver))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            cookie_jwt_bearer_resolver,
+        ))
         .layer(CookieManagerLayer::new())
         .with_state(state)
 }
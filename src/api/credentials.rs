@@ -0,0 +1,248 @@
+use crate::auth::client_info::ClientInfo;
+use crate::auth::mfa::MfaMethod;
+use crate::auth::password::{
+    dummy_password_hash, hash_password, verify_password, PasswordVerification,
+};
+use crate::auth::security_stamp::TokenSubject;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+
+/// How many failed logins in a row lock a client out of a user, for `LOCKOUT_DURATION`.
+const MAX_FAILED_LOGINS: u32 = 5;
+const LOCKOUT_DURATION: &str = "15m";
+
+#[derive(Debug, Deserialize)]
+pub struct DBUser {
+    pub user_id: Thing,
+    /// Missing for users created by an OpenID Connect login, until they reset their password.
+    pub password_hash: Option<String>,
+    pub verified: bool,
+    pub security_stamp: String,
+    /// Whether logins need a TOTP code after the password.
+    #[serde(default)]
+    pub totp_enabled: bool,
+    #[serde(default)]
+    pub passkey_enabled: bool,
+    /// Disabled by an admin, the user can't log in anymore.
+    #[serde(default)]
+    pub disabled: bool,
+    /// Whether the client logging in is locked out after too many failed logins.
+    #[serde(default)]
+    pub locked: bool,
+}
+
+impl DBUser {
+    pub fn token_subject(&self) -> TokenSubject {
+        TokenSubject::new(&self.user_id, self.security_stamp.clone())
+    }
+
+    /// The second factors the user must log in with after its password, if any.
+    pub fn mfa_methods(&self) -> Vec<MfaMethod> {
+        let mut methods = Vec::new();
+        if self.totp_enabled {
+            methods.push(MfaMethod::Totp);
+        }
+        if self.passkey_enabled {
+            methods.push(MfaMethod::Passkey);
+        }
+        methods
+    }
+}
+
+/// Look up a user by its username and verify its password.
+///
+/// `MAX_FAILED_LOGINS` wrong passwords in a row from the same IP address lock that client out of
+/// the user, which an admin can undo. A locked out client gets `InvalidCredentials`, after its
+/// password is verified like for an unknown user, so the lockout doesn't reveal which users exist.
+pub async fn check_credentials(
+    state: &RouterState,
+    client: &ClientInfo,
+    username: &str,
+    password: &str,
+) -> ApiResult<DBUser> {
+    let ip = client.ip.clone().unwrap_or_else(|| "unknown".to_string());
+    let mut result = state
+        .db
+        .query("select id as user_id, password_hash, verified, security_stamp, totp_enabled, count(select id from credential where user=$parent.id) > 0 as passkey_enabled, disabled, type::thing('login_failure', [id, $ip]).locked_until > time::now() as locked from user where username=$username")
+        .bind(("username", username.to_string()))
+        .bind(("ip", ip.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+
+    let user: Option<DBUser> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+
+    let user_id = user.as_ref().map(|user| user.user_id.clone());
+    match verify_user_password(state, user, password).await {
+        Ok(user) if user.locked => Err(BackendError::InvalidCredentials),
+        Ok(user) => {
+            reset_failed_logins(state, &user.user_id, ip).await?;
+            ensure_enabled(&user)?;
+            Ok(user)
+        }
+        Err(BackendError::InvalidCredentials) => {
+            if let Some(user_id) = user_id {
+                record_failed_login(state, &user_id, ip).await?;
+            }
+            Err(BackendError::InvalidCredentials)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Count a failed login of the client, locking it out at `MAX_FAILED_LOGINS`.
+///
+/// Failures while locked out are counted too, keeping the client locked out as long as it guesses.
+async fn record_failed_login(state: &RouterState, user_id: &Thing, ip: String) -> ApiResult<()> {
+    state
+        .db
+        .query(format!(
+            "let $failure = type::thing('login_failure', [$user_id, $ip]);
+            upsert $failure set user=$user_id, ip=$ip, count+=1;
+            update $failure set count=0, locked_until=time::now() + {LOCKOUT_DURATION} where count>={MAX_FAILED_LOGINS};"
+        ))
+        .bind(("user_id", user_id.clone()))
+        .bind(("ip", ip))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(())
+}
+
+async fn reset_failed_logins(state: &RouterState, user_id: &Thing, ip: String) -> ApiResult<()> {
+    state
+        .db
+        .query("delete type::thing('login_failure', [$user_id, $ip])")
+        .bind(("user_id", user_id.clone()))
+        .bind(("ip", ip))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(())
+}
+
+/// Load a user authenticated by other means than its password, refusing a disabled user.
+pub async fn load_user(state: &RouterState, user_id: &Thing) -> ApiResult<DBUser> {
+    let user = find_user(state, user_id)
+        .await?
+        .ok_or(BackendError::InvalidToken)?;
+    ensure_enabled(&user)?;
+    Ok(user)
+}
+
+/// Verify the password of an already authenticated user.
+pub async fn check_user_password(
+    state: &RouterState,
+    user_id: &Thing,
+    password: &str,
+) -> ApiResult<DBUser> {
+    let user = find_user(state, user_id).await?;
+    verify_user_password(state, user, password).await
+}
+
+async fn find_user(state: &RouterState, user_id: &Thing) -> ApiResult<Option<DBUser>> {
+    let mut result = state
+        .db
+        .query("select id as user_id, password_hash, verified, security_stamp, totp_enabled, count(select id from credential where user=$parent.id) > 0 as passkey_enabled, disabled from $user_id")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+
+    result.take(0).map_err(|_| BackendError::SomethingWentWrong)
+}
+
+async fn verify_user_password(
+    state: &RouterState,
+    user: Option<DBUser>,
+    password: &str,
+) -> ApiResult<DBUser> {
+    let Some((user, password_hash)) =
+        user.and_then(|user| user.password_hash.clone().map(|hash| (user, hash)))
+    else {
+        let dummy_hash = dummy_password_hash().await?;
+        verify_password(password.to_string(), dummy_hash.clone()).await?;
+        return Err(BackendError::InvalidCredentials);
+    };
+
+    match verify_password(password.to_string(), password_hash).await? {
+        PasswordVerification::Invalid => return Err(BackendError::InvalidCredentials),
+        PasswordVerification::Valid => {}
+        PasswordVerification::Outdated => {
+            if let Err(err) = rehash_password(state, &user, password).await {
+                tracing::warn!(
+                    "Failed to rehash the password of `{}`: {:?}",
+                    user.user_id,
+                    err
+                );
+            }
+        }
+    }
+
+    Ok(user)
+}
+
+/// Refuse the users disabled by an admin.
+fn ensure_enabled(user: &DBUser) -> ApiResult<()> {
+    if user.disabled {
+        Err(BackendError::AccountDisabled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Refuse unverified accounts when `REQUIRE_EMAIL_VERIFICATION` is enabled.
+pub fn ensure_email_verified(user: &DBUser) -> ApiResult<()> {
+    if env_config().require_email_verification && !user.verified {
+        Err(BackendError::EmailNotVerified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Replace a legacy or outdated credential with a hash using the current parameters.
+async fn rehash_password(state: &RouterState, user: &DBUser, password: &str) -> ApiResult<()> {
+    let password_hash = hash_password(password.to_string()).await?;
+    state
+        .db
+        .query("update $user_id set password_hash=$password_hash")
+        .bind(("user_id", user.user_id.clone()))
+        .bind(("password_hash", password_hash))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(())
+}
+
+/// Replace the password of a user.
+///
+/// This rotates its security stamp, which invalidates all of its tokens, and drops its pending
+/// password reset tokens. Returns the new subject to issue tokens with.
+pub async fn update_password(
+    state: &RouterState,
+    user_id: &Thing,
+    password_hash: String,
+) -> ApiResult<TokenSubject> {
+    let mut result = state
+        .db
+        .query(
+            "begin transaction;
+            delete password_reset where user=$user_id;
+            update $user_id set password_hash=$password_hash, security_stamp=rand::uuid() return value security_stamp;
+            commit transaction;",
+        )
+        .bind(("user_id", user_id.clone()))
+        .bind(("password_hash", password_hash))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let last = result.num_statements() - 1;
+    let security_stamp: Option<String> = result
+        .take(last)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let security_stamp = security_stamp.ok_or(BackendError::SomethingWentWrong)?;
+    Ok(TokenSubject::new(user_id, security_stamp))
+}
@@ -0,0 +1,211 @@
+use crate::auth::cookie_jwt::cookie_jwt_bearer_resolver;
+use crate::auth::rebac::{
+    delete_tuple, expand, write_tuple, Authorized, CanEdit, CanView, Doc, Folder, IsOwner,
+    Relation, UsersetTree,
+};
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::{Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+use tower_cookies::CookieManagerLayer;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocResponse {
+    pub id: String,
+    pub title: String,
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBDoc {
+    id: Thing,
+    title: String,
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateDocPayload {
+    title: Option<String>,
+    content: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderResponse {
+    pub id: String,
+    pub name: String,
+    /// The folders and documents in the folder.
+    pub children: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBFolder {
+    id: Thing,
+    name: String,
+    children: Vec<Thing>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessQuery {
+    #[serde(default = "default_relation")]
+    relation: Relation,
+}
+
+fn default_relation() -> Relation {
+    Relation::Viewer
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessResponse {
+    /// Every user with the relation, however they get it.
+    pub users: Vec<String>,
+    pub tree: UsersetTree,
+}
+
+/// A relation tuple `subject->relation->doc` to write or delete.
+#[derive(Debug, Deserialize)]
+pub struct SharePayload {
+    /// A user or a group.
+    subject: String,
+    relation: Relation,
+}
+
+/// Routes on shared documents, authorized by the relations of the user to them.
+pub fn create_docs_router(state: RouterState) -> Router {
+    Router::new()
+        .route("/docs/:doc_id", get(api_get_doc).put(api_update_doc))
+        .route("/docs/:doc_id/access", get(api_doc_access))
+        .route(
+            "/docs/:doc_id/share",
+            post(api_share_doc).delete(api_unshare_doc),
+        )
+        .route("/folders/:folder_id", get(api_get_folder))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            cookie_jwt_bearer_resolver,
+        ))
+        .layer(CookieManagerLayer::new())
+        .with_state(state)
+}
+
+async fn load_doc(state: &RouterState, doc_id: &Thing) -> ApiResult<DocResponse> {
+    let mut result = state
+        .db
+        .query("select id, title, content from $doc_id")
+        .bind(("doc_id", doc_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let doc: Option<DBDoc> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let doc = doc.ok_or(BackendError::NotFound)?;
+    Ok(DocResponse {
+        id: doc.id.to_string(),
+        title: doc.title,
+        content: doc.content,
+    })
+}
+
+pub async fn api_get_doc(
+    State(state): State<RouterState>,
+    access: Authorized<CanView, Doc>,
+) -> ApiResult<Json<DocResponse>> {
+    Ok(Json(load_doc(&state, &access.object).await?))
+}
+
+pub async fn api_update_doc(
+    State(state): State<RouterState>,
+    access: Authorized<CanEdit, Doc>,
+    Json(payload): Json<UpdateDocPayload>,
+) -> ApiResult<Json<DocResponse>> {
+    state
+        .db
+        .query("update $doc_id set title=$title ?? title, content=$content ?? content, updated_by=$user_id, updated_at=time::now()")
+        .bind(("doc_id", access.object.clone()))
+        .bind(("user_id", access.subject))
+        .bind(("title", payload.title))
+        .bind(("content", payload.content))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(Json(load_doc(&state, &access.object).await?))
+}
+
+/// Expand who has a relation to a document, for its owners.
+pub async fn api_doc_access(
+    State(state): State<RouterState>,
+    access: Authorized<IsOwner, Doc>,
+    Query(query): Query<AccessQuery>,
+) -> ApiResult<Json<AccessResponse>> {
+    let tree = expand(&state, query.relation, &access.object).await?;
+    Ok(Json(AccessResponse {
+        users: tree.users(),
+        tree,
+    }))
+}
+
+fn share_subject(payload: &SharePayload) -> ApiResult<Thing> {
+    if payload.relation == Relation::Member {
+        return Err(BackendError::ValidationFailed(
+            "A document can't have members".to_string(),
+        ));
+    }
+    payload
+        .subject
+        .parse::<Thing>()
+        .ok()
+        .filter(|subject| subject.tb == "user" || subject.tb == "group")
+        .ok_or_else(|| {
+            BackendError::ValidationFailed("The subject must be a user or a group".to_string())
+        })
+}
+
+/// Give a user or a group a relation to a document, for its owners.
+pub async fn api_share_doc(
+    State(state): State<RouterState>,
+    access: Authorized<IsOwner, Doc>,
+    Json(payload): Json<SharePayload>,
+) -> ApiResult<Json<Value>> {
+    let subject = share_subject(&payload)?;
+    write_tuple(&state, &subject, payload.relation, &access.object).await?;
+    Ok(Json(json!({
+        "value": "The document has been shared",
+    })))
+}
+
+/// Remove a relation of a user or a group to a document, for its owners.
+pub async fn api_unshare_doc(
+    State(state): State<RouterState>,
+    access: Authorized<IsOwner, Doc>,
+    Query(payload): Query<SharePayload>,
+) -> ApiResult<Json<Value>> {
+    let subject = share_subject(&payload)?;
+    delete_tuple(&state, &subject, payload.relation, &access.object).await?;
+    Ok(Json(json!({
+        "value": "The document is no longer shared",
+    })))
+}
+
+pub async fn api_get_folder(
+    State(state): State<RouterState>,
+    access: Authorized<CanView, Folder>,
+) -> ApiResult<Json<FolderResponse>> {
+    let mut result = state
+        .db
+        .query("select id, name, ->parent.out as children from $folder_id")
+        .bind(("folder_id", access.object.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let folder: Option<DBFolder> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let folder = folder.ok_or(BackendError::NotFound)?;
+    Ok(Json(FolderResponse {
+        id: folder.id.to_string(),
+        name: folder.name,
+        children: folder.children.iter().map(Thing::to_string).collect(),
+    }))
+}
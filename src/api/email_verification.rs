@@ -0,0 +1,76 @@
+use crate::auth::token::{generate_token, hash_token};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+
+const VERIFICATION_TTL: &str = "24h";
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailPayload {
+    token: String,
+}
+
+pub fn create_email_verification_router(state: RouterState) -> Router {
+    Router::new()
+        .route("/verify-email", post(api_verify_email))
+        .with_state(state)
+}
+
+/// Store a new single-use verification token for the user and email its link.
+pub async fn send_verification_email(
+    state: &RouterState,
+    user_id: &Thing,
+    email: &str,
+) -> ApiResult<()> {
+    let token = generate_token();
+    state
+        .db
+        .query(format!("create email_verification set user=$user_id, token_hash=$token_hash, expires_at=time::now() + {VERIFICATION_TTL}"))
+        .bind(("user_id", user_id.clone()))
+        .bind(("token_hash", hash_token(&token)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+
+    let link = format!("{}/verify-email?token={token}", env_config().public_url);
+    state
+        .mailer
+        .send(
+            email,
+            "Verify your email address",
+            format!("Welcome! Confirm your email address by opening this link within {VERIFICATION_TTL}:\n\n{link}\n"),
+        )
+        .await
+}
+
+pub async fn api_verify_email(
+    State(state): State<RouterState>,
+    payload: Json<VerifyEmailPayload>,
+) -> ApiResult<Json<Value>> {
+    let mut result = state
+        .db
+        .query(
+            "begin transaction;
+            let $user_id = (update email_verification set used_at=time::now() where token_hash=$token_hash and used_at=none and expires_at>time::now() return value user)[0];
+            if $user_id != none { (update $user_id set verified=true return value id)[0] };
+            commit transaction;",
+        )
+        .bind(("token_hash", hash_token(&payload.token)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+
+    let last = result.num_statements() - 1;
+    let user_id: Option<Thing> = result
+        .take(last)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    user_id.ok_or(BackendError::InvalidToken)?;
+
+    Ok(Json(json!({
+        "value": "Your email is now verified",
+    })))
+}
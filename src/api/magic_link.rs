@@ -0,0 +1,126 @@
+use super::credentials::load_user;
+use super::register::LoginMode;
+use super::{start_login, ResponseLogin, ResponseMfaPending};
+use crate::auth::client_info::ClientInfo;
+use crate::auth::magic_link::{add_magic_link_nonce, consume_magic_link, issue_magic_link};
+use crate::auth::mfa::create_mfa_challenge;
+use crate::auth::security_stamp::TokenSubject;
+use crate::auth::session::SessionKind;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::extract::{Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+use tower_cookies::{CookieManagerLayer, Cookies};
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkPayload {
+    email: String,
+    login: LoginMode,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConsumeMagicLinkQuery {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBMagicLinkUser {
+    user_id: Thing,
+    email: String,
+    security_stamp: String,
+}
+
+pub fn create_magic_link_router(state: RouterState) -> Router {
+    Router::new()
+        .route("/login/magic-link", post(api_request_magic_link))
+        .route("/login/magic-link/consume", get(api_consume_magic_link))
+        .layer(CookieManagerLayer::new())
+        .with_state(state)
+}
+
+/// Email a login link if an account uses this address, bound to the browser making the request.
+///
+/// Like `/password/forgot`, the answer is always the same and the email is sent in the background.
+pub async fn api_request_magic_link(
+    cookies: Cookies,
+    State(state): State<RouterState>,
+    payload: Json<MagicLinkPayload>,
+) -> ApiResult<Json<Value>> {
+    let nonce_hash = add_magic_link_nonce(&cookies);
+    let email = payload.email.clone();
+    let kind = payload.login.into();
+    tokio::spawn(async move {
+        if let Err(err) = send_magic_link(&state, email, kind, nonce_hash).await {
+            tracing::warn!("Failed to send a magic link: {err:?}");
+        }
+    });
+
+    Ok(Json(json!({
+        "value": "If an account uses this email, a login link has been sent to it",
+    })))
+}
+
+async fn send_magic_link(
+    state: &RouterState,
+    email: String,
+    kind: SessionKind,
+    nonce_hash: String,
+) -> ApiResult<()> {
+    let mut result = state
+        .db
+        .query("select id as user_id, email, security_stamp from user where email=$email")
+        .bind(("email", email))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user: Option<DBMagicLinkUser> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let Some(user) = user else {
+        return Ok(());
+    };
+
+    let subject = TokenSubject::new(&user.user_id, user.security_stamp);
+    let token = issue_magic_link(state, &subject, kind, nonce_hash).await?;
+    let link = format!(
+        "{}/api/login/magic-link/consume?token={token}",
+        env_config().public_url
+    );
+    state
+        .mailer
+        .send(
+            &user.email,
+            "Your login link",
+            format!("Open this link within 15 minutes, in the browser you asked for it with, to log in:\n\n{link}\n\nIf you didn't ask for it, you can ignore this email.\n"),
+        )
+        .await
+}
+
+/// Log in with a magic link, the second factor being still required when the user has one.
+pub async fn api_consume_magic_link(
+    cookies: Cookies,
+    client: ClientInfo,
+    State(state): State<RouterState>,
+    Query(query): Query<ConsumeMagicLinkQuery>,
+) -> ApiResult<Json<ResponseLogin>> {
+    let (subject, kind) = consume_magic_link(&state, &cookies, &query.token).await?;
+    let user_id = subject
+        .user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+
+    let methods = load_user(&state, &user_id).await?.mfa_methods();
+    if !methods.is_empty() {
+        let mfa_pending = create_mfa_challenge(&state, &subject, kind).await?;
+        return Ok(Json(ResponseLogin::MfaPending(ResponseMfaPending {
+            mfa_pending,
+            methods,
+        })));
+    }
+
+    Ok(Json(ResponseLogin::Bearer(
+        start_login(&state, cookies, &client, &subject, kind).await?,
+    )))
+}
@@ -0,0 +1,85 @@
+use super::super::validation::{validate_api_key_expiry, validate_name, validate_scopes};
+use crate::auth::api_key::{create_api_key, list_api_keys, revoke_api_key, ApiKeyInfo};
+use crate::auth::claims::AnyJWTClaims;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::{Path, State};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyPayload {
+    name: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+    /// The key never expires without it.
+    #[serde(default)]
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiKeyResponse {
+    /// The key itself, which can't be shown again.
+    pub key: String,
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+}
+
+fn user_id(claims: &AnyJWTClaims) -> ApiResult<Thing> {
+    claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)
+}
+
+/// List the API keys of the user that can still be used.
+pub async fn api_list_api_keys(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+) -> ApiResult<Json<Vec<ApiKeyInfo>>> {
+    Ok(Json(list_api_keys(&state, &user_id(&claims)?).await?))
+}
+
+/// Create an API key, which is only shown in this response.
+pub async fn api_create_api_key(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    Json(payload): Json<CreateApiKeyPayload>,
+) -> ApiResult<Json<CreateApiKeyResponse>> {
+    let name = payload.name.trim().to_string();
+    validate_name(&name)?;
+    validate_scopes(&payload.scopes)?;
+    validate_api_key_expiry(payload.expires_in_days)?;
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in payload.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    let (key, info) = create_api_key(
+        &state,
+        &user_id(&claims)?,
+        name,
+        scopes,
+        payload.expires_in_days,
+    )
+    .await?;
+    Ok(Json(CreateApiKeyResponse { key, info }))
+}
+
+/// Revoke one of the API keys of the user.
+pub async fn api_revoke_api_key(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    Path(key_id): Path<String>,
+) -> ApiResult<Json<Value>> {
+    let key_id = match key_id.starts_with("api_key:") {
+        true => key_id,
+        false => format!("api_key:{key_id}"),
+    };
+    revoke_api_key(&state, &user_id(&claims)?, &key_id).await?;
+    Ok(Json(json!({
+        "value": "The API key has been revoked",
+    })))
+}
@@ -0,0 +1,29 @@
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::cookie_jwt::remove_cookie_jwt_bearer_claims;
+use crate::auth::security_stamp::rotate_security_stamp;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+use tower_cookies::Cookies;
+
+/// Log the user out of every session, whatever the mode it was opened with.
+pub async fn api_logout_all(
+    cookies: Cookies,
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    _: Json<Value>,
+) -> ApiResult<Json<Value>> {
+    let user_id = claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    rotate_security_stamp(&state, &user_id).await?;
+    if let AnyJWTClaims::Cookie(_) = claims {
+        remove_cookie_jwt_bearer_claims(cookies);
+    }
+    Ok(Json(json!({
+        "value": "You're now disconnected from every session",
+    })))
+}
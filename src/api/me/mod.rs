@@ -0,0 +1,47 @@
+use crate::auth::cookie_jwt::cookie_jwt_bearer_resolver;
+use crate::RouterState;
+use axum::routing::{delete, get, post};
+use axum::Router;
+use tower_cookies::CookieManagerLayer;
+
+mod api_keys;
+mod logout_all;
+mod passkeys;
+mod password;
+mod profile;
+mod recovery_codes;
+mod sessions;
+mod totp;
+
+/// Routes acting on the authenticated user, with either a bearer token or a jwt cookie.
+pub fn create_me_router(state: RouterState) -> Router {
+    Router::new()
+        .route("/me", get(profile::api_profile))
+        .route("/me/password", post(password::api_change_password))
+        .route("/me/sessions", get(sessions::api_list_sessions))
+        .route("/me/sessions/:id", delete(sessions::api_revoke_session))
+        .route(
+            "/me/passkeys",
+            get(passkeys::api_list_passkeys).post(passkeys::api_register_passkey),
+        )
+        .route("/me/passkeys/options", post(passkeys::api_passkey_options))
+        .route("/me/passkeys/:id", delete(passkeys::api_delete_passkey))
+        .route(
+            "/me/api-keys",
+            get(api_keys::api_list_api_keys).post(api_keys::api_create_api_key),
+        )
+        .route("/me/api-keys/:id", delete(api_keys::api_revoke_api_key))
+        .route("/me/totp", post(totp::api_enroll_totp))
+        .route("/me/totp/confirm", post(totp::api_confirm_totp))
+        .route(
+            "/me/recovery-codes",
+            post(recovery_codes::api_regenerate_recovery_codes),
+        )
+        .route("/logout-all", post(logout_all::api_logout_all))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            cookie_jwt_bearer_resolver,
+        ))
+        .layer(CookieManagerLayer::new())
+        .with_state(state)
+}
@@ -0,0 +1,86 @@
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::recovery_code::ensure_recovery_codes;
+use crate::auth::webauthn::{
+    delete_passkey, finish_passkey_registration, list_passkeys, start_passkey_registration,
+    CreationOptions, PasskeyInfo, RegistrationCredential,
+};
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::{Path, State};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterPasskeyPayload {
+    /// A name to tell the passkeys of the user apart.
+    #[serde(default)]
+    name: Option<String>,
+    credential: RegistrationCredential,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegisterPasskeyResponse {
+    #[serde(flatten)]
+    pub passkey: PasskeyInfo,
+    /// The recovery codes of the user, when this is its first second factor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+fn user_id(claims: &AnyJWTClaims) -> ApiResult<Thing> {
+    claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)
+}
+
+/// Start the registration of a new passkey.
+pub async fn api_passkey_options(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    _: Json<Value>,
+) -> ApiResult<Json<CreationOptions>> {
+    Ok(Json(
+        start_passkey_registration(&state, &user_id(&claims)?).await?,
+    ))
+}
+
+/// Register the passkey created for the challenge of `/me/passkeys/options`.
+pub async fn api_register_passkey(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    payload: Json<RegisterPasskeyPayload>,
+) -> ApiResult<Json<RegisterPasskeyResponse>> {
+    let payload = payload.0;
+    let user_id = user_id(&claims)?;
+    let passkey =
+        finish_passkey_registration(&state, &user_id, payload.name, &payload.credential).await?;
+    let recovery_codes = ensure_recovery_codes(&state, &user_id).await?;
+    Ok(Json(RegisterPasskeyResponse {
+        passkey,
+        recovery_codes,
+    }))
+}
+
+pub async fn api_list_passkeys(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+) -> ApiResult<Json<Vec<PasskeyInfo>>> {
+    Ok(Json(list_passkeys(&state, &user_id(&claims)?).await?))
+}
+
+pub async fn api_delete_passkey(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    Path(passkey_id): Path<String>,
+) -> ApiResult<Json<Value>> {
+    let passkey_id = match passkey_id.starts_with("credential:") {
+        true => passkey_id,
+        false => format!("credential:{passkey_id}"),
+    };
+    delete_passkey(&state, &user_id(&claims)?, &passkey_id).await?;
+    Ok(Json(json!({
+        "value": "The passkey has been removed",
+    })))
+}
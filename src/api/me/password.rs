@@ -0,0 +1,104 @@
+use super::super::bearer_jwt::User as BearerUser;
+use super::super::cookies_jwt::User as CookieUser;
+use super::super::credentials::{check_user_password, update_password};
+use super::super::validation::validate_password;
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::client_info::ClientInfo;
+use crate::auth::cookie_jwt::remove_cookie_jwt_bearer_claims;
+use crate::auth::cookie_session::start_cookie_session;
+use crate::auth::password::hash_password;
+use crate::auth::session::start_bearer_session;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+use tower_cookies::Cookies;
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordPayload {
+    current_password: String,
+    new_password: String,
+    /// Keep the session making the request logged in, every other one being revoked.
+    #[serde(default = "default_keep_session")]
+    keep_session: bool,
+}
+
+fn default_keep_session() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangePasswordResponse {
+    pub value: String,
+    /// The token replacing the one used for the request, when the session is kept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bearer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+pub async fn api_change_password(
+    cookies: Cookies,
+    client: ClientInfo,
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    payload: Json<ChangePasswordPayload>,
+) -> ApiResult<Json<ChangePasswordResponse>> {
+    let user_id = claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    check_user_password(&state, &user_id, &payload.current_password).await?;
+    validate_password(&payload.new_password)?;
+    if payload.current_password == payload.new_password {
+        return Err(BackendError::ValidationFailed(
+            "The new password must be different from the current one".to_string(),
+        ));
+    }
+
+    let password_hash = hash_password(payload.new_password.clone()).await?;
+    let mut subject = update_password(&state, &user_id, password_hash).await?;
+    if let Some(sid) = claims.sid() {
+        subject = subject.with_session(sid);
+    }
+
+    let (bearer, refresh_token) = match (&claims, payload.keep_session) {
+        (AnyJWTClaims::Bearer(_), true) => {
+            let (bearer, refresh_token) = start_bearer_session(
+                &state,
+                &subject,
+                BearerUser {
+                    user_id: user_id.to_string(),
+                },
+                &client,
+            )
+            .await?;
+            (Some(bearer), Some(refresh_token))
+        }
+        (AnyJWTClaims::Cookie(_), true) => {
+            let bearer = start_cookie_session(
+                &state,
+                cookies,
+                &subject,
+                CookieUser {
+                    user_id: user_id.to_string(),
+                },
+                &client,
+            )
+            .await?;
+            (Some(bearer), None)
+        }
+        (AnyJWTClaims::Cookie(_), false) => {
+            remove_cookie_jwt_bearer_claims(cookies);
+            (None, None)
+        }
+        (AnyJWTClaims::Bearer(_), false) => (None, None),
+    };
+
+    Ok(Json(ChangePasswordResponse {
+        value: "Your password has been changed".to_string(),
+        bearer,
+        refresh_token,
+    }))
+}
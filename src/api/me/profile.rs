@@ -0,0 +1,56 @@
+use super::super::credentials::load_user;
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::mfa::MfaMethod;
+use crate::auth::recovery_code::count_recovery_codes;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileResponse {
+    pub user_id: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub verified: bool,
+    /// The second factors the user logs in with after its password.
+    pub mfa_methods: Vec<MfaMethod>,
+    pub recovery_codes_remaining: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBProfile {
+    username: String,
+    email: Option<String>,
+}
+
+pub async fn api_profile(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+) -> ApiResult<Json<ProfileResponse>> {
+    let user_id = claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let mut result = state
+        .db
+        .query("select username, email from $user_id")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let profile: Option<DBProfile> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let profile = profile.ok_or(BackendError::InvalidToken)?;
+    let user = load_user(&state, &user_id).await?;
+
+    Ok(Json(ProfileResponse {
+        user_id: user_id.to_string(),
+        username: profile.username,
+        email: profile.email,
+        verified: user.verified,
+        mfa_methods: user.mfa_methods(),
+        recovery_codes_remaining: count_recovery_codes(&state, &user_id).await?,
+    }))
+}
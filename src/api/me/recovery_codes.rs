@@ -0,0 +1,32 @@
+use super::super::credentials::load_user;
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::recovery_code::generate_recovery_codes;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Replace the recovery codes of the user, the unused ones no longer being accepted.
+pub async fn api_regenerate_recovery_codes(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    _: Json<Value>,
+) -> ApiResult<Json<RecoveryCodesResponse>> {
+    let user_id = claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    if load_user(&state, &user_id).await?.mfa_methods().is_empty() {
+        return Err(BackendError::MfaNotEnabled);
+    }
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes: generate_recovery_codes(&state, &user_id).await?,
+    }))
+}
@@ -0,0 +1,48 @@
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::session::{list_sessions, revoke_session, SessionInfo};
+use crate::{ApiResult, RouterState};
+use axum::extract::{Path, State};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionResponse {
+    #[serde(flatten)]
+    pub session: SessionInfo,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+/// List the active sessions of the user, the most recently used first.
+pub async fn api_list_sessions(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+) -> ApiResult<Json<Vec<SessionResponse>>> {
+    let sessions = list_sessions(&state, claims.sub()).await?;
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|session| SessionResponse {
+                current: claims.sid() == Some(session.id.as_str()),
+                session,
+            })
+            .collect(),
+    ))
+}
+
+/// Revoke one of the sessions of the user, along with its refresh tokens.
+pub async fn api_revoke_session(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    Path(session_id): Path<String>,
+) -> ApiResult<Json<Value>> {
+    let session_id = match session_id.starts_with("session:") {
+        true => session_id,
+        false => format!("session:{session_id}"),
+    };
+    revoke_session(&state, claims.sub(), &session_id).await?;
+    Ok(Json(json!({
+        "value": "The session has been revoked",
+    })))
+}
@@ -0,0 +1,53 @@
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::recovery_code::ensure_recovery_codes;
+use crate::auth::totp::{confirm_totp, enroll_totp, TotpEnrollment};
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmTotpPayload {
+    code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfirmTotpResponse {
+    pub value: String,
+    /// The recovery codes of the user, when this is its first second factor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+/// Generate the TOTP secret of the user, to add to its authenticator app.
+pub async fn api_enroll_totp(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    _: Json<Value>,
+) -> ApiResult<Json<TotpEnrollment>> {
+    let user_id = claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    Ok(Json(enroll_totp(&state, &user_id).await?))
+}
+
+/// Enable two-factor authentication with a first code from the authenticator app.
+pub async fn api_confirm_totp(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    payload: Json<ConfirmTotpPayload>,
+) -> ApiResult<Json<ConfirmTotpResponse>> {
+    let user_id = claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    confirm_totp(&state, &user_id, &payload.code).await?;
+    let recovery_codes = ensure_recovery_codes(&state, &user_id).await?;
+    Ok(Json(ConfirmTotpResponse {
+        value: "Two-factor authentication is now enabled".to_string(),
+        recovery_codes,
+    }))
+}
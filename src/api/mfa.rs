@@ -0,0 +1,85 @@
+use super::start_login;
+use super::ResponseBearer;
+use crate::auth::client_info::ClientInfo;
+use crate::auth::mfa::{attempt_mfa_challenge, complete_mfa_challenge, find_mfa_challenge};
+use crate::auth::recovery_code::use_recovery_code;
+use crate::auth::totp::verify_totp;
+use crate::auth::webauthn::{
+    start_passkey_authentication, verify_passkey_assertion, AuthenticationCredential,
+    RequestOptions,
+};
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::Deserialize;
+use tower_cookies::{CookieManagerLayer, Cookies};
+
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginPayload {
+    mfa_pending: String,
+    /// The code of the user's authenticator app.
+    #[serde(default)]
+    code: Option<String>,
+    /// The answer of one of the user's passkeys to the challenge of `/login/mfa/options`.
+    #[serde(default)]
+    credential: Option<AuthenticationCredential>,
+    /// One of the user's recovery codes, when its second factors are lost.
+    #[serde(default)]
+    recovery_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaOptionsPayload {
+    mfa_pending: String,
+}
+
+pub fn create_mfa_router(state: RouterState) -> Router {
+    Router::new()
+        .route("/login/mfa", post(api_login_mfa))
+        .route("/login/mfa/options", post(api_mfa_passkey_options))
+        .layer(CookieManagerLayer::new())
+        .with_state(state)
+}
+
+/// Start a passkey challenge for the second step of a login.
+pub async fn api_mfa_passkey_options(
+    State(state): State<RouterState>,
+    payload: Json<MfaOptionsPayload>,
+) -> ApiResult<Json<RequestOptions>> {
+    let challenge = find_mfa_challenge(&state, &payload.mfa_pending).await?;
+    Ok(Json(
+        start_passkey_authentication(&state, Some(&challenge.user), false).await?,
+    ))
+}
+
+/// Finish a login with a second factor, a TOTP code, a passkey or a recovery code, logging the user in the way
+/// the first step was made, with a cookie or a bearer token.
+pub async fn api_login_mfa(
+    cookies: Cookies,
+    client: ClientInfo,
+    State(state): State<RouterState>,
+    payload: Json<MfaLoginPayload>,
+) -> ApiResult<Json<ResponseBearer>> {
+    let challenge = attempt_mfa_challenge(&state, &payload.mfa_pending).await?;
+    match (&payload.code, &payload.credential, &payload.recovery_code) {
+        (Some(code), None, None) => verify_totp(&state, &challenge.user, code).await?,
+        (None, Some(credential), None) => {
+            verify_passkey_assertion(&state, credential, Some(&challenge.user), false).await?;
+        }
+        (None, None, Some(recovery_code)) => {
+            use_recovery_code(&state, &challenge.user, recovery_code, &client).await?
+        }
+        _ => {
+            return Err(BackendError::ValidationFailed(
+                "Exactly one of a code, a passkey credential or a recovery code is required"
+                    .to_string(),
+            ))
+        }
+    }
+    let subject = complete_mfa_challenge(&state, &challenge).await?;
+
+    Ok(Json(
+        start_login(&state, cookies, &client, &subject, challenge.kind).await?,
+    ))
+}
@@ -1,28 +1,137 @@
+mod admin;
 mod bearer_jwt;
 mod cookies_jwt;
+mod credentials;
+mod docs;
+mod email_verification;
+mod magic_link;
+mod me;
+mod mfa;
+mod oauth;
+mod oidc;
+mod orgs;
+mod passkey;
+mod password_reset;
+mod register;
+mod validation;
 
-use crate::RouterState;
+use crate::auth::client_info::ClientInfo;
+use crate::auth::cookie_session::start_cookie_session;
+use crate::auth::mfa::MfaMethod;
+use crate::auth::security_stamp::TokenSubject;
+use crate::auth::session::{start_bearer_session, SessionKind};
+use crate::{ApiResult, RouterState};
+use admin::create_admin_router;
 use axum::Router;
 use bearer_jwt::create_bearer_jwt_router;
 use cookies_jwt::create_cookie_jwt_router;
+use docs::create_docs_router;
+use email_verification::create_email_verification_router;
+use magic_link::create_magic_link_router;
+use me::create_me_router;
+use mfa::create_mfa_router;
+use oauth::create_oauth_router;
+use oidc::create_oidc_router;
+use orgs::create_orgs_router;
+use passkey::create_passkey_router;
+use password_reset::create_password_reset_router;
+use register::create_register_router;
 use serde::{Deserialize, Serialize};
+use tower_cookies::Cookies;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResponseBearer {
     pub bearer: String,
+    /// The refresh token of a bearer login, to renew the access token at `/bearer/refresh`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+/// The first step of a login for a user with two-factor authentication.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseMfaPending {
+    /// The short-lived token to send along with the code at `/login/mfa`.
+    pub mfa_pending: String,
+    /// The second factors the user can answer with.
+    pub methods: Vec<MfaMethod>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResponseLogin {
+    Bearer(ResponseBearer),
+    MfaPending(ResponseMfaPending),
+}
+
+/// Log a user in with a cookie or a bearer token, once all of its factors are checked.
+async fn start_login(
+    state: &RouterState,
+    cookies: Cookies,
+    client: &ClientInfo,
+    subject: &TokenSubject,
+    kind: SessionKind,
+) -> ApiResult<ResponseBearer> {
+    let user_id = subject.user_id.clone();
+    match kind {
+        SessionKind::Cookie => {
+            let data = cookies_jwt::User { user_id };
+            let bearer = start_cookie_session(state, cookies, subject, data, client).await?;
+            Ok(ResponseBearer {
+                bearer,
+                refresh_token: None,
+            })
+        }
+        SessionKind::Bearer => {
+            let data = bearer_jwt::User { user_id };
+            let (bearer, refresh_token) =
+                start_bearer_session(state, subject, data, client).await?;
+            Ok(ResponseBearer {
+                bearer,
+                refresh_token: Some(refresh_token),
+            })
+        }
+    }
 }
 
 pub fn create_test_router(state: RouterState) -> Router {
     Router::new()
         .merge(create_cookie_jwt_router(state.clone()))
         .merge(create_bearer_jwt_router(state.clone()))
+        .merge(create_register_router(state.clone()))
+        .merge(create_email_verification_router(state.clone()))
+        .merge(create_password_reset_router(state.clone()))
+        .merge(create_me_router(state.clone()))
+        .merge(create_mfa_router(state.clone()))
+        .merge(create_passkey_router(state.clone()))
+        .merge(create_magic_link_router(state.clone()))
+        .merge(create_oidc_router(state.clone()))
+        .merge(create_oauth_router(state.clone()))
+        .merge(create_docs_router(state.clone()))
+        .merge(create_orgs_router(state.clone()))
+        .merge(create_admin_router(state.clone()))
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::api::register::RegisterResponse;
     use crate::api::ResponseBearer;
+    use crate::auth::cookie_session::resolve_cookie_session;
+    use crate::auth::invitation::{issue_invitation, list_invitations};
+    use crate::auth::magic_link::issue_magic_link;
+    use crate::auth::oidc::OidcClient;
+    use crate::auth::organization::{create_organization, OrgRole, TenantDatabases};
+    use crate::auth::policy::PolicyStore;
+    use crate::auth::revocation::RevocationStore;
+    use crate::auth::security_stamp::load_token_subject;
+    use crate::auth::session::SessionKind;
+    use crate::auth::token::hash_token;
+    use crate::config::CookieMode;
+    use crate::mailer::Mailer;
+    use crate::surreal::connect_db;
+    use crate::{env_config, RouterState};
     use axum::http::StatusCode;
     use serde_json::json;
+    use surrealdb::sql::Thing;
 
     #[tokio::test]
     async fn login_with_jwt_cookie() -> anyhow::Result<()> {
@@ -151,7 +260,7 @@ mod tests {
                     .await?;
                 assert_eq!(result.status(), StatusCode::OK, "The status should be OK");
             }
-            Err(_) => assert!(false, "Bearer token should exist in the response"),
+            Err(_) => panic!("Bearer token should exist in the response"),
         };
 
         Ok(())
@@ -184,4 +293,2396 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn register_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+        let email = format!("{username}@example.com");
+
+        let weak_password = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": email,
+                    "password": "short"
+                }),
+            )
+            .await?;
+        assert_eq!(
+            weak_password.status(),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Shouldn't accept a weak password"
+        );
+
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": email,
+                    "password": "correct horse battery staple",
+                    "login": "bearer"
+                }),
+            )
+            .await?;
+        assert_eq!(
+            register_post.status(),
+            StatusCode::OK,
+            "Should be registered"
+        );
+
+        let registered = register_post.json_body_as::<RegisterResponse>()?;
+        let bearer = registered.bearer.expect("Should be logged in");
+        let result = hc
+            .reqwest_client()
+            .request(
+                reqwest::Method::GET,
+                "http://localhost:3000/api/bearer/page",
+            )
+            .bearer_auth(bearer)
+            .send()
+            .await?;
+        assert_eq!(result.status(), StatusCode::OK, "The status should be OK");
+
+        let duplicated = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": format!("other-{email}"),
+                    "password": "correct horse battery staple"
+                }),
+            )
+            .await?;
+        assert_eq!(
+            duplicated.status(),
+            StatusCode::CONFLICT,
+            "Shouldn't register the same username twice"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rehash_legacy_password_on_login() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let logins = login_root_and_new_user(&hc).await?;
+        let user_id = logins
+            .user_id
+            .parse::<Thing>()
+            .map_err(|_| anyhow::anyhow!("Invalid user id"))?;
+        let password = "correct horse battery staple";
+
+        let state = server_state().await?;
+        state
+            .db
+            .query("update $user_id set password_hash=$password_hash")
+            .bind(("user_id", user_id.clone()))
+            .bind(("password_hash", bcrypt::hash(password, 4)?))
+            .await?
+            .check()?;
+
+        bearer_login(&hc, &logins.username, password).await?;
+        let password_hash: Option<String> = state
+            .db
+            .query("select value password_hash from only $user_id")
+            .bind(("user_id", user_id))
+            .await?
+            .take(0)?;
+        assert!(
+            password_hash.is_some_and(|hash| hash.starts_with("$argon2id$")),
+            "Should replace the bcrypt hash after logging in"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_email_verification() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+
+        let verify_post = hc
+            .do_post("/verify-email", json!({ "token": "not-a-real-token" }))
+            .await?;
+        assert_eq!(
+            verify_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't verify an unknown token"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forgot_and_failed_reset_password() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+
+        let forgot_post = hc
+            .do_post("/password/forgot", json!({ "email": "nobody@example.com" }))
+            .await?;
+        assert_eq!(
+            forgot_post.status(),
+            StatusCode::OK,
+            "Shouldn't tell whether the account exists"
+        );
+
+        let reset_post = hc
+            .do_post(
+                "/password/reset",
+                json!({
+                    "token": "not-a-real-token",
+                    "password": "correct horse battery staple"
+                }),
+            )
+            .await?;
+        assert_eq!(
+            reset_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't reset with an unknown token"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn change_password_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": format!("{username}@example.com"),
+                    "password": "correct horse battery staple",
+                    "login": "bearer"
+                }),
+            )
+            .await?;
+        let old_bearer = register_post
+            .json_body_as::<RegisterResponse>()?
+            .bearer
+            .expect("Should be logged in");
+
+        let change_post = client
+            .post("http://localhost:3000/api/me/password")
+            .bearer_auth(&old_bearer)
+            .json(&json!({
+                "current_password": "correct horse battery staple",
+                "new_password": "another horse battery staple"
+            }))
+            .send()
+            .await?;
+        assert_eq!(
+            change_post.status(),
+            StatusCode::OK,
+            "Should change the password"
+        );
+        let new_bearer = change_post.json::<serde_json::Value>().await?["bearer"]
+            .as_str()
+            .expect("Should keep the session")
+            .to_string();
+
+        let old_page = client
+            .get("http://localhost:3000/api/bearer/page")
+            .bearer_auth(old_bearer)
+            .send()
+            .await?;
+        assert_eq!(
+            old_page.status(),
+            StatusCode::UNAUTHORIZED,
+            "The previous token should be revoked"
+        );
+
+        let new_page = client
+            .get("http://localhost:3000/api/bearer/page")
+            .bearer_auth(new_bearer)
+            .send()
+            .await?;
+        assert_eq!(
+            new_page.status(),
+            StatusCode::OK,
+            "The new token should work"
+        );
+
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({
+                    "username": username,
+                    "password": "another horse battery staple"
+                }),
+            )
+            .await?;
+        assert_eq!(
+            login_post.status(),
+            StatusCode::OK,
+            "Should log in with the new password"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refresh_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({
+                    "username": "root",
+                    "password": "root"
+                }),
+            )
+            .await?;
+        let first = login_post.json_body_as::<ResponseBearer>()?;
+        let first_refresh = first.refresh_token.expect("Should have a refresh token");
+
+        let refresh_post = hc
+            .do_post("/bearer/refresh", json!({ "refresh_token": first_refresh }))
+            .await?;
+        assert_eq!(refresh_post.status(), StatusCode::OK, "Should be refreshed");
+        let second = refresh_post.json_body_as::<ResponseBearer>()?;
+        let second_refresh = second
+            .refresh_token
+            .expect("Should rotate the refresh token");
+
+        let reused_post = hc
+            .do_post("/bearer/refresh", json!({ "refresh_token": first_refresh }))
+            .await?;
+        assert_eq!(
+            reused_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "A refresh token should only be used once"
+        );
+
+        let revoked_post = hc
+            .do_post(
+                "/bearer/refresh",
+                json!({ "refresh_token": second_refresh }),
+            )
+            .await?;
+        assert_eq!(
+            revoked_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "Reusing a refresh token should revoke its whole family"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoked_jwt_cookie() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+
+        let login_post = hc
+            .do_post(
+                "/cookie/login",
+                json!({
+                    "username": "root",
+                    "password": "root"
+                }),
+            )
+            .await?;
+        let bearer = login_post.json_body_as::<ResponseBearer>()?.bearer;
+
+        let logout_post = hc.do_post("/cookie/logout", json!({})).await?;
+        assert_eq!(logout_post.status(), StatusCode::OK, "Should be logged out");
+
+        let replayed = hc
+            .reqwest_client()
+            .get("http://localhost:3000/api/cookie/page")
+            .header(reqwest::header::COOKIE, format!("session={bearer}"))
+            .send()
+            .await?;
+        assert_eq!(
+            replayed.status(),
+            StatusCode::UNAUTHORIZED,
+            "A logged out cookie shouldn't be accepted anymore"
+        );
+
+        let state = server_state().await?;
+        let jti = uuid::Uuid::new_v4().to_string();
+        let exp = (chrono::Utc::now().timestamp() + 60) as usize;
+        for _ in 0..2 {
+            state
+                .revocations
+                .revoke(&state.db, &jti, exp)
+                .await
+                .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        }
+        let rows: Option<usize> = state
+            .db
+            .query("count(select id from revoked_token where jti=$jti)")
+            .bind(("jti", jti))
+            .await?
+            .take(0)?;
+        assert_eq!(rows, Some(1), "Revoking a token twice should keep one row");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs COOKIE_MODE=session for both the server and the test"]
+    async fn session_cookie_login_and_logout() -> anyhow::Result<()> {
+        assert_eq!(
+            env_config().cookie_mode,
+            CookieMode::Session,
+            "Should run with COOKIE_MODE=session"
+        );
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+
+        let login_post = hc
+            .reqwest_client()
+            .post("http://localhost:3000/api/cookie/login")
+            .header(reqwest::header::USER_AGENT, "session-cookie-test")
+            .json(&json!({ "username": "root", "password": "root" }))
+            .send()
+            .await?;
+        assert_eq!(login_post.status(), StatusCode::OK, "Should be logged in");
+        let token = login_post.json::<ResponseBearer>().await?.bearer;
+        assert!(
+            !token.contains('.'),
+            "The cookie should hold an opaque session id, not a jwt"
+        );
+
+        let state = server_state().await?;
+        let claims = resolve_cookie_session(&state, &token)
+            .await
+            .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        assert_eq!(claims.sub, "user:root");
+        let session: serde_json::Value = state
+            .db
+            .query("select ip, user_agent, kind, revoked_at from only session where token_hash=$token_hash limit 1")
+            .bind(("token_hash", hash_token(&token)))
+            .await?
+            .take::<Option<serde_json::Value>>(0)?
+            .unwrap_or_default();
+        assert!(session["ip"].is_string(), "Should record the IP address");
+        assert_eq!(session["user_agent"], "session-cookie-test");
+        assert_eq!(session["kind"], "cookie");
+        assert!(session["revoked_at"].is_null());
+
+        let page = hc.do_get("/cookie/page").await?;
+        assert_eq!(page.status(), StatusCode::OK, "Should resolve the session");
+        let logout_post = hc.do_post("/cookie/logout", json!({})).await?;
+        assert_eq!(logout_post.status(), StatusCode::OK, "Should be logged out");
+
+        assert!(
+            resolve_cookie_session(&state, &token).await.is_err(),
+            "The cookie shouldn't resolve after logging out"
+        );
+        let revoked_at: Option<String> = state
+            .db
+            .query("select value <string> revoked_at from only session where token_hash=$token_hash limit 1")
+            .bind(("token_hash", hash_token(&token)))
+            .await?
+            .take(0)?;
+        assert!(revoked_at.is_some(), "Should end the session for good");
+        let replayed = hc
+            .reqwest_client()
+            .get("http://localhost:3000/api/cookie/page")
+            .header(reqwest::header::COOKIE, format!("session={token}"))
+            .send()
+            .await?;
+        assert_eq!(replayed.status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logout_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+
+        hc.do_post(
+            "/register",
+            json!({
+                "username": username,
+                "email": format!("{username}@example.com"),
+                "password": "correct horse battery staple"
+            }),
+        )
+        .await?;
+        let mut bearers = Vec::new();
+        for _ in 0..3 {
+            let login_post = hc
+                .do_post(
+                    "/bearer/login",
+                    json!({
+                        "username": username,
+                        "password": "correct horse battery staple"
+                    }),
+                )
+                .await?;
+            bearers.push(login_post.json_body_as::<ResponseBearer>()?.bearer);
+        }
+
+        let logout_post = client
+            .post("http://localhost:3000/api/bearer/logout")
+            .bearer_auth(&bearers[0])
+            .json(&json!({}))
+            .send()
+            .await?;
+        assert_eq!(logout_post.status(), StatusCode::OK, "Should be logged out");
+
+        let page_statuses = |bearers: Vec<String>| {
+            let client = client.clone();
+            async move {
+                let mut statuses = Vec::new();
+                for bearer in bearers {
+                    let page = client
+                        .get("http://localhost:3000/api/bearer/page")
+                        .bearer_auth(bearer)
+                        .send()
+                        .await?;
+                    statuses.push(page.status());
+                }
+                anyhow::Ok(statuses)
+            }
+        };
+        assert_eq!(
+            page_statuses(bearers.clone()).await?,
+            vec![StatusCode::UNAUTHORIZED, StatusCode::OK, StatusCode::OK],
+            "Only the logged out token should be revoked"
+        );
+
+        let logout_all_post = client
+            .post("http://localhost:3000/api/logout-all")
+            .bearer_auth(&bearers[1])
+            .json(&json!({}))
+            .send()
+            .await?;
+        assert_eq!(
+            logout_all_post.status(),
+            StatusCode::OK,
+            "Should be logged out"
+        );
+        assert_eq!(
+            page_statuses(bearers).await?,
+            vec![StatusCode::UNAUTHORIZED; 3],
+            "Every token should be revoked"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sessions_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+
+        hc.do_post(
+            "/register",
+            json!({
+                "username": username,
+                "email": format!("{username}@example.com"),
+                "password": "correct horse battery staple"
+            }),
+        )
+        .await?;
+        let mut bearers = Vec::new();
+        for _ in 0..2 {
+            let login_post = hc
+                .do_post(
+                    "/bearer/login",
+                    json!({
+                        "username": username,
+                        "password": "correct horse battery staple"
+                    }),
+                )
+                .await?;
+            bearers.push(login_post.json_body_as::<ResponseBearer>()?.bearer);
+        }
+
+        let sessions: Vec<serde_json::Value> = client
+            .get("http://localhost:3000/api/me/sessions")
+            .bearer_auth(&bearers[0])
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(sessions.len(), 2, "Every login should open a session");
+        let other = sessions
+            .iter()
+            .find(|session| session["current"] == json!(false))
+            .and_then(|session| session["id"].as_str())
+            .expect("The other session should be listed");
+
+        let revoke_delete = client
+            .delete(format!("http://localhost:3000/api/me/sessions/{other}"))
+            .bearer_auth(&bearers[0])
+            .send()
+            .await?;
+        assert_eq!(
+            revoke_delete.status(),
+            StatusCode::OK,
+            "Should revoke the other session"
+        );
+
+        let mut statuses = Vec::new();
+        for bearer in &bearers {
+            let page = client
+                .get("http://localhost:3000/api/bearer/page")
+                .bearer_auth(bearer)
+                .send()
+                .await?;
+            statuses.push(page.status());
+        }
+        assert_eq!(
+            statuses,
+            vec![StatusCode::OK, StatusCode::UNAUTHORIZED],
+            "Only the revoked session should be logged out"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn totp_login_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": format!("{username}@example.com"),
+                    "password": "correct horse battery staple",
+                    "login": "bearer"
+                }),
+            )
+            .await?;
+        let bearer = register_post
+            .json_body_as::<RegisterResponse>()?
+            .bearer
+            .expect("Should be logged in");
+
+        let enrollment: serde_json::Value = client
+            .post("http://localhost:3000/api/me/totp")
+            .bearer_auth(&bearer)
+            .json(&json!({}))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let otpauth_uri = enrollment["otpauth_uri"]
+            .as_str()
+            .expect("Should return the otpauth uri");
+        let totp = totp_rs::TOTP::from_url(otpauth_uri)?;
+
+        let confirm_post = client
+            .post("http://localhost:3000/api/me/totp/confirm")
+            .bearer_auth(&bearer)
+            .json(&json!({ "code": totp.generate_current()? }))
+            .send()
+            .await?;
+        assert_eq!(
+            confirm_post.status(),
+            StatusCode::OK,
+            "Should enable two-factor authentication"
+        );
+
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({
+                    "username": username,
+                    "password": "correct horse battery staple"
+                }),
+            )
+            .await?;
+        let login = login_post.json_body_as::<serde_json::Value>()?;
+        assert!(
+            login.get("bearer").is_none(),
+            "Shouldn't be logged in without the code"
+        );
+        let mfa_pending = login["mfa_pending"]
+            .as_str()
+            .expect("Should return a mfa_pending token");
+
+        let wrong_code_post = hc
+            .do_post(
+                "/login/mfa",
+                json!({ "mfa_pending": mfa_pending, "code": "000000" }),
+            )
+            .await?;
+        assert_eq!(
+            wrong_code_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't accept a wrong code"
+        );
+
+        // The code of the confirmation can't be used again, the next one is within the skew.
+        let next_time = chrono::Utc::now().timestamp() as u64 + 30;
+        let mfa_post = hc
+            .do_post(
+                "/login/mfa",
+                json!({ "mfa_pending": mfa_pending, "code": totp.generate(next_time) }),
+            )
+            .await?;
+        assert_eq!(mfa_post.status(), StatusCode::OK, "Should be logged in");
+        let bearer = mfa_post.json_body_as::<ResponseBearer>()?.bearer;
+
+        let page = client
+            .get("http://localhost:3000/api/bearer/page")
+            .bearer_auth(bearer)
+            .send()
+            .await?;
+        assert_eq!(page.status(), StatusCode::OK, "Should access the resource");
+
+        let replay_post = hc
+            .do_post(
+                "/login/mfa",
+                json!({ "mfa_pending": mfa_pending, "code": totp.generate(next_time) }),
+            )
+            .await?;
+        assert_eq!(
+            replay_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "The mfa_pending token should only be used once"
+        );
+
+        Ok(())
+    }
+
+    /// A software authenticator holding a single ES256 passkey.
+    struct SoftPasskey {
+        key: p256::ecdsa::SigningKey,
+        credential_id: Vec<u8>,
+        sign_count: u32,
+    }
+
+    impl SoftPasskey {
+        const ORIGIN: &'static str = "http://127.0.0.1:3000";
+
+        fn new() -> Self {
+            Self {
+                key: p256::ecdsa::SigningKey::random(&mut rand::rngs::OsRng),
+                credential_id: uuid::Uuid::new_v4().as_bytes().to_vec(),
+                sign_count: 0,
+            }
+        }
+
+        fn encode(bytes: &[u8]) -> String {
+            use base64::Engine;
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+        }
+
+        fn client_data(kind: &str, options: &serde_json::Value) -> anyhow::Result<Vec<u8>> {
+            Ok(serde_json::to_vec(&json!({
+                "type": kind,
+                "challenge": options["publicKey"]["challenge"],
+                "origin": Self::ORIGIN,
+            }))?)
+        }
+
+        fn authenticator_data(&mut self, rp_id: &str, flags: u8) -> Vec<u8> {
+            use sha2::Digest;
+            self.sign_count += 1;
+            let mut data = sha2::Sha256::digest(rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend(self.sign_count.to_be_bytes());
+            data
+        }
+
+        /// Answer `navigator.credentials.create()`, with user presence and verification.
+        fn create(&mut self, options: &serde_json::Value) -> anyhow::Result<serde_json::Value> {
+            use ciborium::Value;
+            let rp_id = options["publicKey"]["rp"]["id"]
+                .as_str()
+                .unwrap_or_default();
+            let mut authenticator_data = self.authenticator_data(rp_id, 0x01 | 0x04 | 0x40);
+            authenticator_data.extend([0u8; 16]);
+            authenticator_data.extend((self.credential_id.len() as u16).to_be_bytes());
+            authenticator_data.extend(&self.credential_id);
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let cose_key = Value::Map(vec![
+                (Value::from(1), Value::from(2)),
+                (Value::from(3), Value::from(-7)),
+                (Value::from(-1), Value::from(1)),
+                (
+                    Value::from(-2),
+                    Value::from(point.x().map(|x| x.to_vec()).unwrap_or_default()),
+                ),
+                (
+                    Value::from(-3),
+                    Value::from(point.y().map(|y| y.to_vec()).unwrap_or_default()),
+                ),
+            ]);
+            ciborium::into_writer(&cose_key, &mut authenticator_data)?;
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(Vec::new())),
+                (Value::from("authData"), Value::from(authenticator_data)),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object)?;
+
+            Ok(json!({
+                "id": Self::encode(&self.credential_id),
+                "rawId": Self::encode(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "clientDataJSON": Self::encode(&Self::client_data("webauthn.create", options)?),
+                    "attestationObject": Self::encode(&attestation_object),
+                },
+            }))
+        }
+
+        /// Answer `navigator.credentials.get()`, with user presence and verification.
+        fn get(&mut self, options: &serde_json::Value) -> anyhow::Result<serde_json::Value> {
+            use p256::ecdsa::signature::Signer;
+            use sha2::Digest;
+            let rp_id = options["publicKey"]["rpId"].as_str().unwrap_or_default();
+            let authenticator_data = self.authenticator_data(rp_id, 0x01 | 0x04);
+            let client_data = Self::client_data("webauthn.get", options)?;
+            let mut signed = authenticator_data.clone();
+            signed.extend(sha2::Sha256::digest(&client_data));
+            let signature: p256::ecdsa::Signature = self.key.sign(&signed);
+
+            Ok(json!({
+                "id": Self::encode(&self.credential_id),
+                "rawId": Self::encode(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "clientDataJSON": Self::encode(&client_data),
+                    "authenticatorData": Self::encode(&authenticator_data),
+                    "signature": Self::encode(signature.to_der().as_bytes()),
+                },
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn passkey_login_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+        let mut passkey = SoftPasskey::new();
+
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": format!("{username}@example.com"),
+                    "password": "correct horse battery staple",
+                    "login": "bearer"
+                }),
+            )
+            .await?;
+        let bearer = register_post
+            .json_body_as::<RegisterResponse>()?
+            .bearer
+            .expect("Should be logged in");
+
+        let creation_options: serde_json::Value = client
+            .post("http://localhost:3000/api/me/passkeys/options")
+            .bearer_auth(&bearer)
+            .json(&json!({}))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let register_passkey_post = client
+            .post("http://localhost:3000/api/me/passkeys")
+            .bearer_auth(&bearer)
+            .json(&json!({
+                "name": "Test key",
+                "credential": passkey.create(&creation_options)?,
+            }))
+            .send()
+            .await?;
+        assert_eq!(
+            register_passkey_post.status(),
+            StatusCode::OK,
+            "Should register the passkey"
+        );
+
+        let request_options = hc
+            .do_post("/passkey/login/options", json!({ "username": username }))
+            .await?
+            .json_body_as::<serde_json::Value>()?;
+        let passkey_login_post = hc
+            .do_post(
+                "/passkey/login",
+                json!({ "login": "bearer", "credential": passkey.get(&request_options)? }),
+            )
+            .await?;
+        assert_eq!(
+            passkey_login_post.status(),
+            StatusCode::OK,
+            "Should log in with the passkey alone"
+        );
+        let page = client
+            .get("http://localhost:3000/api/bearer/page")
+            .bearer_auth(passkey_login_post.json_body_as::<ResponseBearer>()?.bearer)
+            .send()
+            .await?;
+        assert_eq!(page.status(), StatusCode::OK, "Should access the resource");
+
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({
+                    "username": username,
+                    "password": "correct horse battery staple"
+                }),
+            )
+            .await?;
+        let login = login_post.json_body_as::<serde_json::Value>()?;
+        assert_eq!(
+            login["methods"],
+            json!(["passkey"]),
+            "The passkey should be required as a second factor"
+        );
+        let mfa_pending = login["mfa_pending"]
+            .as_str()
+            .expect("Should return a mfa_pending token");
+
+        let mfa_options = hc
+            .do_post("/login/mfa/options", json!({ "mfa_pending": mfa_pending }))
+            .await?
+            .json_body_as::<serde_json::Value>()?;
+        let mut cloned = SoftPasskey {
+            key: passkey.key.clone(),
+            credential_id: passkey.credential_id.clone(),
+            sign_count: 0,
+        };
+        let cloned_post = hc
+            .do_post(
+                "/login/mfa",
+                json!({ "mfa_pending": mfa_pending, "credential": cloned.get(&mfa_options)? }),
+            )
+            .await?;
+        assert_eq!(
+            cloned_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't accept a sign counter going backwards"
+        );
+
+        let mfa_options = hc
+            .do_post("/login/mfa/options", json!({ "mfa_pending": mfa_pending }))
+            .await?
+            .json_body_as::<serde_json::Value>()?;
+        let mfa_post = hc
+            .do_post(
+                "/login/mfa",
+                json!({ "mfa_pending": mfa_pending, "credential": passkey.get(&mfa_options)? }),
+            )
+            .await?;
+        assert_eq!(mfa_post.status(), StatusCode::OK, "Should be logged in");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recovery_code_login_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": format!("{username}@example.com"),
+                    "password": "correct horse battery staple",
+                    "login": "bearer"
+                }),
+            )
+            .await?;
+        let bearer = register_post
+            .json_body_as::<RegisterResponse>()?
+            .bearer
+            .expect("Should be logged in");
+
+        let enrollment: serde_json::Value = client
+            .post("http://localhost:3000/api/me/totp")
+            .bearer_auth(&bearer)
+            .json(&json!({}))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let totp = totp_rs::TOTP::from_url(enrollment["otpauth_uri"].as_str().unwrap_or_default())?;
+        let confirm: serde_json::Value = client
+            .post("http://localhost:3000/api/me/totp/confirm")
+            .bearer_auth(&bearer)
+            .json(&json!({ "code": totp.generate_current()? }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let recovery_codes: Vec<String> =
+            serde_json::from_value(confirm["recovery_codes"].clone())?;
+        assert_eq!(recovery_codes.len(), 10, "Should return the recovery codes");
+
+        let mfa_login = |recovery_code: String| {
+            let hc = &hc;
+            let username = username.clone();
+            async move {
+                let login = hc
+                    .do_post(
+                        "/bearer/login",
+                        json!({
+                            "username": username,
+                            "password": "correct horse battery staple"
+                        }),
+                    )
+                    .await?
+                    .json_body_as::<serde_json::Value>()?;
+                let mfa_post = hc
+                    .do_post(
+                        "/login/mfa",
+                        json!({
+                            "mfa_pending": login["mfa_pending"],
+                            "recovery_code": recovery_code,
+                        }),
+                    )
+                    .await?;
+                anyhow::Ok(mfa_post.status())
+            }
+        };
+        assert_eq!(
+            mfa_login(recovery_codes[0].to_uppercase()).await?,
+            StatusCode::OK,
+            "Should log in with a recovery code"
+        );
+        assert_eq!(
+            mfa_login(recovery_codes[0].clone()).await?,
+            StatusCode::UNAUTHORIZED,
+            "A recovery code should only be used once"
+        );
+
+        let profile: serde_json::Value = client
+            .get("http://localhost:3000/api/me")
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(
+            profile["recovery_codes_remaining"],
+            json!(9),
+            "The used code should be counted"
+        );
+
+        let regenerate_post = client
+            .post("http://localhost:3000/api/me/recovery-codes")
+            .bearer_auth(&bearer)
+            .json(&json!({}))
+            .send()
+            .await?;
+        assert_eq!(
+            regenerate_post.status(),
+            StatusCode::OK,
+            "Should regenerate the recovery codes"
+        );
+        assert_eq!(
+            mfa_login(recovery_codes[1].clone()).await?,
+            StatusCode::UNAUTHORIZED,
+            "The old recovery codes should be invalidated"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_magic_link_login() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+
+        let magic_link_post = hc
+            .do_post(
+                "/login/magic-link",
+                json!({ "email": "nobody@example.com", "login": "cookie" }),
+            )
+            .await?;
+        assert_eq!(
+            magic_link_post.status(),
+            StatusCode::OK,
+            "Shouldn't tell whether the account exists"
+        );
+        assert!(
+            magic_link_post.client_cookie("magic_link_nonce").is_some(),
+            "Should bind the link to this client"
+        );
+
+        let consume_get = hc
+            .do_get("/login/magic-link/consume?token=not-a-real-token")
+            .await?;
+        assert_eq!(
+            consume_get.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't log in with an unknown link"
+        );
+
+        Ok(())
+    }
+
+    /// A state on the database of the running server, to issue the tokens it only emails.
+    ///
+    /// Needs the same environment as the server, for its database and `JWT_SECRET`.
+    async fn server_state() -> anyhow::Result<RouterState> {
+        let db = connect_db(
+            env_config().db_host.as_str(),
+            env_config().db_user.as_str(),
+            env_config().db_pswd.as_str(),
+            env_config().db_namespace.as_str(),
+            env_config().db_database.as_str(),
+        )
+        .await?;
+        Ok(RouterState {
+            db,
+            mailer: Mailer::from_config(env_config())?,
+            revocations: RevocationStore::default(),
+            oidc: OidcClient::default(),
+            policies: PolicyStore::default(),
+            tenants: TenantDatabases::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn magic_link_login_once() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+        let email = format!("{username}@example.com");
+
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": email,
+                    "password": "correct horse battery staple"
+                }),
+            )
+            .await?;
+        let user_id = register_post
+            .json_body_as::<RegisterResponse>()?
+            .user_id
+            .parse::<Thing>()
+            .map_err(|_| anyhow::anyhow!("Invalid user id"))?;
+        let magic_link_post = hc
+            .do_post(
+                "/login/magic-link",
+                json!({ "email": email, "login": "bearer" }),
+            )
+            .await?;
+        let nonce = magic_link_post
+            .client_cookie("magic_link_nonce")
+            .map(|cookie| cookie.value.clone())
+            .unwrap_or_default();
+
+        let state = server_state().await?;
+        let subject = load_token_subject(&state, &user_id)
+            .await
+            .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        let token = issue_magic_link(&state, &subject, SessionKind::Bearer, hash_token(&nonce))
+            .await
+            .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        let consume_url = format!("/login/magic-link/consume?token={token}");
+
+        let other_hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let consume_get = other_hc.do_get(&consume_url).await?;
+        assert_eq!(
+            consume_get.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't log in without the nonce cookie"
+        );
+        other_hc
+            .do_post(
+                "/login/magic-link",
+                json!({ "email": "nobody@example.com", "login": "bearer" }),
+            )
+            .await?;
+        let consume_get = other_hc.do_get(&consume_url).await?;
+        assert_eq!(
+            consume_get.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't log in with the nonce cookie of another browser"
+        );
+
+        let consume_get = hc.do_get(&consume_url).await?;
+        assert_eq!(
+            consume_get.status(),
+            StatusCode::OK,
+            "Should log in with the link in the browser that asked for it"
+        );
+        let bearer = consume_get.json_body_as::<ResponseBearer>()?.bearer;
+        let page = hc
+            .reqwest_client()
+            .get("http://localhost:3000/api/bearer/page")
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+        assert_eq!(page.status(), StatusCode::OK);
+
+        hc.do_post(
+            "/login/magic-link",
+            json!({ "email": email, "login": "bearer" }),
+        )
+        .await?;
+        let consume_get = hc.do_get(&consume_url).await?;
+        assert_eq!(
+            consume_get.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't log in twice with the same link"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oauth_authorization_code_with_jwt_cookie() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let code_verifier = crate::auth::token::generate_token();
+        let code_challenge = crate::auth::token::hash_token(&code_verifier);
+        let redirect_uri = "http://localhost:3000/callback";
+
+        let discovery = client
+            .get("http://localhost:3000/api/.well-known/openid-configuration")
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        assert_eq!(
+            discovery["token_endpoint"], "http://localhost:3000/api/token",
+            "Should advertise the token endpoint"
+        );
+
+        let login_post = hc
+            .do_post(
+                "/cookie/login",
+                json!({
+                    "username": "root",
+                    "password": "root"
+                }),
+            )
+            .await?;
+        assert_eq!(login_post.status(), StatusCode::OK, "Should be logged in");
+
+        let consent_page = client
+            .get("http://localhost:3000/api/authorize")
+            .query(&[
+                ("response_type", "code"),
+                ("client_id", "dev-client"),
+                ("redirect_uri", redirect_uri),
+                ("scope", "openid profile"),
+                ("state", "some-state"),
+                ("nonce", "some-nonce"),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+                ("prompt", "consent"),
+            ])
+            .send()
+            .await?;
+        assert_eq!(
+            consent_page.status(),
+            StatusCode::OK,
+            "Should ask for the consent"
+        );
+        let consent_page = consent_page.text().await?;
+        let consent = consent_page
+            .split(r#"name="consent" value=""#)
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .expect("Should contain a consent token")
+            .to_string();
+
+        let allowed = client
+            .post("http://localhost:3000/api/authorize")
+            .form(&[("consent", consent.as_str()), ("decision", "allow")])
+            .send()
+            .await?;
+        let callback = allowed.url().clone();
+        let params: std::collections::HashMap<_, _> = callback.query_pairs().into_owned().collect();
+        assert_eq!(
+            params.get("state").map(String::as_str),
+            Some("some-state"),
+            "Should send the state back"
+        );
+        let code = params
+            .get("code")
+            .expect("Should be redirected with a code");
+
+        let token_post = client
+            .post("http://localhost:3000/api/token")
+            .basic_auth("dev-client", Some("dev-secret"))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code.as_str()),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier.as_str()),
+            ])
+            .send()
+            .await?;
+        assert_eq!(
+            token_post.status(),
+            StatusCode::OK,
+            "Should exchange the code"
+        );
+        let tokens = token_post.json::<serde_json::Value>().await?;
+        let access_token = tokens["access_token"].as_str().unwrap_or_default();
+        let id_token = tokens["id_token"].as_str().unwrap_or_default();
+
+        let jwks = client
+            .get("http://localhost:3000/api/jwks")
+            .send()
+            .await?
+            .json::<jsonwebtoken::jwk::JwkSet>()
+            .await?;
+        let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256);
+        validation.set_audience(&["dev-client"]);
+        validation.set_issuer(&["http://localhost:3000/api"]);
+        let id_claims = jsonwebtoken::decode::<serde_json::Value>(
+            id_token,
+            &jsonwebtoken::DecodingKey::from_jwk(&jwks.keys[0])?,
+            &validation,
+        )?
+        .claims;
+        assert_eq!(id_claims["sub"], "user:root", "Should identify the user");
+        assert_eq!(id_claims["nonce"], "some-nonce", "Should carry the nonce");
+
+        let userinfo = client
+            .get("http://localhost:3000/api/userinfo")
+            .bearer_auth(access_token)
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        assert_eq!(
+            userinfo["preferred_username"], "root",
+            "Should share the profile"
+        );
+
+        let replayed = client
+            .post("http://localhost:3000/api/token")
+            .basic_auth("dev-client", Some("dev-secret"))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code.as_str()),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier.as_str()),
+            ])
+            .send()
+            .await?;
+        assert_eq!(
+            replayed.status(),
+            StatusCode::BAD_REQUEST,
+            "Shouldn't redeem a code twice"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_credentials_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+
+        let wrong_secret = client
+            .post("http://localhost:3000/api/token")
+            .basic_auth("dev-service", Some("not-the-secret"))
+            .form(&[("grant_type", "client_credentials")])
+            .send()
+            .await?;
+        assert_eq!(
+            wrong_secret.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't authenticate with a wrong secret"
+        );
+
+        let client_id = format!("plaintext-{}", chrono::Utc::now().timestamp_micros());
+        server_state()
+            .await?
+            .db
+            .query("create service_account set client_id=$client_id, name=$client_id, secret_hash=$secret, scopes=['reports:read']")
+            .bind(("client_id", client_id.clone()))
+            .bind(("secret", "plaintext-secret"))
+            .await?
+            .check()?;
+        let plaintext_secret = client
+            .post("http://localhost:3000/api/token")
+            .basic_auth(&client_id, Some("plaintext-secret"))
+            .form(&[("grant_type", "client_credentials")])
+            .send()
+            .await?;
+        assert_eq!(
+            plaintext_secret.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't authenticate against a secret stored in plaintext"
+        );
+
+        let unknown_scope = client
+            .post("http://localhost:3000/api/token")
+            .basic_auth("dev-service", Some("dev-service-secret"))
+            .form(&[("grant_type", "client_credentials"), ("scope", "admin")])
+            .send()
+            .await?;
+        assert_eq!(
+            unknown_scope.status(),
+            StatusCode::BAD_REQUEST,
+            "Shouldn't grant a scope the service doesn't have"
+        );
+
+        let token_post = client
+            .post("http://localhost:3000/api/token")
+            .basic_auth("dev-service", Some("dev-service-secret"))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("scope", "reports:read"),
+            ])
+            .send()
+            .await?;
+        assert_eq!(token_post.status(), StatusCode::OK, "Should issue a token");
+        let tokens = token_post.json::<serde_json::Value>().await?;
+        assert_eq!(tokens["scope"], "reports:read", "Should grant the scope");
+        let access_token = tokens["access_token"].as_str().unwrap_or_default();
+
+        let page = client
+            .get("http://localhost:3000/api/bearer/page")
+            .bearer_auth(access_token)
+            .send()
+            .await?;
+        assert_eq!(
+            page.status(),
+            StatusCode::OK,
+            "Should access the bearer routes"
+        );
+
+        let me = client
+            .get("http://localhost:3000/api/me")
+            .bearer_auth(access_token)
+            .send()
+            .await?;
+        assert_eq!(
+            me.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't act as a user"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_key_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+
+        hc.do_post(
+            "/register",
+            json!({
+                "username": username,
+                "email": format!("{username}@example.com"),
+                "password": "correct horse battery staple"
+            }),
+        )
+        .await?;
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({
+                    "username": username,
+                    "password": "correct horse battery staple"
+                }),
+            )
+            .await?;
+        let bearer = login_post.json_body_as::<ResponseBearer>()?.bearer;
+
+        let invalid_scope = client
+            .post("http://localhost:3000/api/me/api-keys")
+            .bearer_auth(&bearer)
+            .json(&json!({ "name": "ci", "scopes": ["Not A Scope"] }))
+            .send()
+            .await?;
+        assert_eq!(
+            invalid_scope.status(),
+            StatusCode::BAD_REQUEST,
+            "Shouldn't create a key with an invalid scope"
+        );
+
+        let created: serde_json::Value = client
+            .post("http://localhost:3000/api/me/api-keys")
+            .bearer_auth(&bearer)
+            .json(&json!({ "name": "ci", "scopes": ["reports:read"], "expires_in_days": 30 }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let key = created["key"].as_str().unwrap_or_default().to_string();
+        let key_id = created["id"].as_str().unwrap_or_default().to_string();
+        assert!(key.starts_with("aapi_"), "The key should be recognizable");
+
+        let keys: Vec<serde_json::Value> = client
+            .get("http://localhost:3000/api/me/api-keys")
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(keys.len(), 1, "The key should be listed");
+        assert!(
+            keys[0].get("key").is_none(),
+            "The key should only be shown once"
+        );
+
+        let with_bearer = client
+            .get("http://localhost:3000/api/bearer/page")
+            .bearer_auth(&key)
+            .send()
+            .await?;
+        let with_header = client
+            .get("http://localhost:3000/api/bearer/page")
+            .header("X-Api-Key", &key)
+            .send()
+            .await?;
+        assert_eq!(
+            (with_bearer.status(), with_header.status()),
+            (StatusCode::OK, StatusCode::OK),
+            "Should authenticate with the key in either header"
+        );
+
+        let me = client
+            .get("http://localhost:3000/api/me/api-keys")
+            .bearer_auth(&key)
+            .send()
+            .await?;
+        assert_eq!(
+            me.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't manage keys with a key"
+        );
+
+        let revoke_delete = client
+            .delete(format!("http://localhost:3000/api/me/api-keys/{key_id}"))
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+        assert_eq!(
+            revoke_delete.status(),
+            StatusCode::OK,
+            "Should revoke the key"
+        );
+
+        let revoked = client
+            .get("http://localhost:3000/api/bearer/page")
+            .header("X-Api-Key", &key)
+            .send()
+            .await?;
+        assert_eq!(
+            revoked.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't authenticate with a revoked key"
+        );
+
+        Ok(())
+    }
+
+    /// `root` and a newly registered user, each logged in with a bearer token.
+    struct RootAndUser {
+        username: String,
+        user_id: String,
+        root: ResponseBearer,
+        user: ResponseBearer,
+    }
+
+    async fn bearer_login(
+        hc: &httpc_test::Client,
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<ResponseBearer> {
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({ "username": username, "password": password }),
+            )
+            .await?;
+        let login = login_post.json_body_as::<ResponseBearer>()?;
+        Ok(login)
+    }
+
+    /// Register a new user and log it in along with `root`, to compare what each of them can do.
+    async fn login_root_and_new_user(hc: &httpc_test::Client) -> anyhow::Result<RootAndUser> {
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+        let password = "correct horse battery staple";
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": format!("{username}@example.com"),
+                    "password": password
+                }),
+            )
+            .await?;
+        let user_id = register_post.json_body_as::<RegisterResponse>()?.user_id;
+        Ok(RootAndUser {
+            root: bearer_login(hc, "root", "root").await?,
+            user: bearer_login(hc, &username, password).await?,
+            username,
+            user_id,
+        })
+    }
+
+    #[tokio::test]
+    async fn rbac_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let logins = login_root_and_new_user(&hc).await?;
+
+        let mut statuses = Vec::new();
+        for bearer in [&logins.root.bearer, &logins.user.bearer] {
+            let page = client
+                .get("http://localhost:3000/api/bearer/admin")
+                .bearer_auth(bearer)
+                .send()
+                .await?;
+            statuses.push(page.status());
+        }
+        assert_eq!(
+            statuses,
+            vec![StatusCode::OK, StatusCode::FORBIDDEN],
+            "Only the admin role should be granted `users:read`"
+        );
+
+        let mut statuses = Vec::new();
+        for scopes in [json!(["users:read"]), json!([])] {
+            let created: serde_json::Value = client
+                .post("http://localhost:3000/api/me/api-keys")
+                .bearer_auth(&logins.root.bearer)
+                .json(&json!({ "name": "admin", "scopes": scopes, "expires_in_days": 1 }))
+                .send()
+                .await?
+                .json()
+                .await?;
+            let page = client
+                .get("http://localhost:3000/api/bearer/admin")
+                .header("X-Api-Key", created["key"].as_str().unwrap_or_default())
+                .send()
+                .await?;
+            statuses.push(page.status());
+            client
+                .delete(format!(
+                    "http://localhost:3000/api/me/api-keys/{}",
+                    created["id"].as_str().unwrap_or_default()
+                ))
+                .bearer_auth(&logins.root.bearer)
+                .send()
+                .await?;
+        }
+        assert_eq!(
+            statuses,
+            vec![StatusCode::OK, StatusCode::FORBIDDEN],
+            "An API key should also need the permission in its scopes"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn policy_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+
+        let mut statuses = Vec::new();
+        for scope in ["reports:read", "reports:write"] {
+            let tokens: serde_json::Value = client
+                .post("http://localhost:3000/api/token")
+                .basic_auth("dev-service", Some("dev-service-secret"))
+                .form(&[("grant_type", "client_credentials"), ("scope", scope)])
+                .send()
+                .await?
+                .json()
+                .await?;
+            let report = client
+                .get("http://localhost:3000/api/bearer/reports/monthly")
+                .bearer_auth(tokens["access_token"].as_str().unwrap_or_default())
+                .send()
+                .await?;
+            statuses.push(report.status());
+        }
+        assert_eq!(
+            statuses,
+            vec![StatusCode::OK, StatusCode::FORBIDDEN],
+            "A service should need the `reports:read` scope"
+        );
+
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({
+                    "username": "root",
+                    "password": "root"
+                }),
+            )
+            .await?;
+        let bearer = login_post.json_body_as::<ResponseBearer>()?.bearer;
+        let report = client
+            .get("http://localhost:3000/api/bearer/reports/monthly")
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+        assert_eq!(
+            report.status(),
+            StatusCode::OK,
+            "A user should read reports"
+        );
+
+        let unauthenticated = client
+            .get("http://localhost:3000/api/bearer/reports/monthly")
+            .send()
+            .await?;
+        assert_eq!(
+            unauthenticated.status(),
+            StatusCode::UNAUTHORIZED,
+            "Should authenticate before checking the policies"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rebac_shared_doc_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let logins = login_root_and_new_user(&hc).await?;
+        let doc_statuses = |bearer: &str| {
+            let client = client.clone();
+            let bearer = bearer.to_string();
+            async move {
+                let get = client
+                    .get("http://localhost:3000/api/docs/onboarding")
+                    .bearer_auth(&bearer)
+                    .send()
+                    .await?;
+                let put = client
+                    .put("http://localhost:3000/api/docs/onboarding")
+                    .bearer_auth(&bearer)
+                    .json(&json!({ "content": "Welcome aboard!" }))
+                    .send()
+                    .await?;
+                anyhow::Ok((get.status(), put.status()))
+            }
+        };
+
+        assert_eq!(
+            doc_statuses(&logins.root.bearer).await?,
+            (StatusCode::OK, StatusCode::OK),
+            "The owner of the folder should own its documents through its group"
+        );
+        assert_eq!(
+            doc_statuses(&logins.user.bearer).await?,
+            (StatusCode::FORBIDDEN, StatusCode::FORBIDDEN),
+            "Shouldn't access a document that isn't shared"
+        );
+
+        let share_post = client
+            .post("http://localhost:3000/api/docs/onboarding/share")
+            .bearer_auth(&logins.root.bearer)
+            .json(&json!({ "subject": logins.user_id, "relation": "viewer" }))
+            .send()
+            .await?;
+        assert_eq!(
+            share_post.status(),
+            StatusCode::OK,
+            "Should share the document"
+        );
+        assert_eq!(
+            doc_statuses(&logins.user.bearer).await?,
+            (StatusCode::OK, StatusCode::FORBIDDEN),
+            "A viewer should only read the document"
+        );
+
+        let access: serde_json::Value = client
+            .get("http://localhost:3000/api/docs/onboarding/access?relation=viewer")
+            .bearer_auth(&logins.root.bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        let users = access["users"].as_array().cloned().unwrap_or_default();
+        assert!(
+            users.contains(&json!("user:root")) && users.contains(&json!(logins.user_id)),
+            "Should expand the viewers through the groups and the folder"
+        );
+
+        let unshare_delete = client
+            .delete("http://localhost:3000/api/docs/onboarding/share")
+            .query(&[("subject", logins.user_id.as_str()), ("relation", "viewer")])
+            .bearer_auth(&logins.root.bearer)
+            .send()
+            .await?;
+        assert_eq!(
+            unshare_delete.status(),
+            StatusCode::OK,
+            "Should unshare the document"
+        );
+        assert_eq!(
+            doc_statuses(&logins.user.bearer).await?,
+            (StatusCode::FORBIDDEN, StatusCode::FORBIDDEN),
+            "Shouldn't access the document anymore"
+        );
+
+        let mut statuses = Vec::new();
+        for scopes in [json!(["docs:read"]), json!(["users:read"])] {
+            let created: serde_json::Value = client
+                .post("http://localhost:3000/api/me/api-keys")
+                .bearer_auth(&logins.root.bearer)
+                .json(&json!({ "name": "docs", "scopes": scopes, "expires_in_days": 1 }))
+                .send()
+                .await?
+                .json()
+                .await?;
+            let key = created["key"].as_str().unwrap_or_default();
+            for request in [
+                client.get("http://localhost:3000/api/docs/onboarding"),
+                client
+                    .put("http://localhost:3000/api/docs/onboarding")
+                    .json(&json!({ "content": "Welcome aboard!" })),
+            ] {
+                statuses.push(request.header("X-Api-Key", key).send().await?.status());
+            }
+            client
+                .delete(format!(
+                    "http://localhost:3000/api/me/api-keys/{}",
+                    created["id"].as_str().unwrap_or_default()
+                ))
+                .bearer_auth(&logins.root.bearer)
+                .send()
+                .await?;
+        }
+        assert_eq!(
+            statuses,
+            vec![
+                StatusCode::OK,
+                StatusCode::FORBIDDEN,
+                StatusCode::FORBIDDEN,
+                StatusCode::FORBIDDEN
+            ],
+            "An API key should also need the scope of the relation"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn organizations_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let logins = login_root_and_new_user(&hc).await?;
+        let switch = |bearer: &str, org_id: &str| {
+            client
+                .post("http://localhost:3000/api/orgs/switch")
+                .bearer_auth(bearer)
+                .json(&json!({ "org_id": org_id }))
+                .send()
+        };
+        let current = |bearer: &str| {
+            client
+                .get("http://localhost:3000/api/orgs/current")
+                .bearer_auth(bearer)
+                .send()
+        };
+
+        assert_eq!(
+            current(&logins.root.bearer).await?.status(),
+            StatusCode::FORBIDDEN,
+            "Shouldn't act in an organization before switching to one"
+        );
+        let switch_post = switch(&logins.root.bearer, "organization:acme").await?;
+        assert_eq!(
+            switch_post.status(),
+            StatusCode::OK,
+            "Should switch to an organization of the user"
+        );
+        let bearer = switch_post.json::<ResponseBearer>().await?.bearer;
+        let organization: serde_json::Value = current(&bearer).await?.json().await?;
+        assert_eq!(organization["id"], "organization:acme");
+        assert_eq!(organization["role"], "owner");
+        assert_eq!(
+            current(&logins.root.bearer).await?.status(),
+            StatusCode::UNAUTHORIZED,
+            "The token replaced by the switch should be revoked"
+        );
+        assert_eq!(
+            switch(&logins.user.bearer, "organization:acme")
+                .await?
+                .status(),
+            StatusCode::FORBIDDEN,
+            "Shouldn't switch to an organization the user isn't a member of"
+        );
+
+        let settings_put = client
+            .put("http://localhost:3000/api/orgs/current/settings")
+            .bearer_auth(&bearer)
+            .json(&json!({ "theme": "dark" }))
+            .send()
+            .await?;
+        assert_eq!(settings_put.status(), StatusCode::OK);
+
+        let refresh_post = hc
+            .do_post(
+                "/bearer/refresh",
+                json!({ "refresh_token": logins.root.refresh_token }),
+            )
+            .await?;
+        let refreshed = refresh_post.json_body_as::<ResponseBearer>()?.bearer;
+        let settings: serde_json::Value = client
+            .get("http://localhost:3000/api/orgs/current/settings")
+            .bearer_auth(&refreshed)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(
+            settings,
+            json!({ "theme": "dark" }),
+            "A refreshed token should keep acting in the organization"
+        );
+
+        let create_post = client
+            .post("http://localhost:3000/api/orgs")
+            .bearer_auth(&logins.user.bearer)
+            .json(&json!({ "name": "Team", "slug": logins.username.replace("user", "team") }))
+            .send()
+            .await?;
+        assert_eq!(create_post.status(), StatusCode::OK);
+        let org_id = create_post.json::<serde_json::Value>().await?["id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let bearer = switch(&logins.user.bearer, &org_id)
+            .await?
+            .json::<ResponseBearer>()
+            .await?
+            .bearer;
+        let settings: serde_json::Value = client
+            .get("http://localhost:3000/api/orgs/current/settings")
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(
+            settings,
+            json!({}),
+            "Shouldn't see the settings of another organization"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invitations_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+        let email = format!("{username}@example.com");
+
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({ "username": "root", "password": "root" }),
+            )
+            .await?;
+        let bearer = login_post.json_body_as::<ResponseBearer>()?.bearer;
+        let invitations_url = "http://localhost:3000/api/orgs/current/invitations";
+        assert_eq!(
+            client
+                .post(invitations_url)
+                .bearer_auth(&bearer)
+                .json(&json!({ "email": email }))
+                .send()
+                .await?
+                .status(),
+            StatusCode::FORBIDDEN,
+            "Shouldn't invite without acting in an organization"
+        );
+        let bearer = client
+            .post("http://localhost:3000/api/orgs/switch")
+            .bearer_auth(&bearer)
+            .json(&json!({ "org_id": "organization:acme" }))
+            .send()
+            .await?
+            .json::<ResponseBearer>()
+            .await?
+            .bearer;
+
+        let invite_post = client
+            .post(invitations_url)
+            .bearer_auth(&bearer)
+            .json(&json!({ "email": email, "role": "admin" }))
+            .send()
+            .await?;
+        assert_eq!(invite_post.status(), StatusCode::OK, "Should invite");
+        let invitation: serde_json::Value = invite_post.json().await?;
+        assert_eq!(invitation["role"], "admin");
+        assert!(
+            invitation.get("token").is_none(),
+            "The token should only be emailed"
+        );
+        let invitation_id = invitation["id"].as_str().unwrap_or_default().to_string();
+        let invitations: Vec<serde_json::Value> = client
+            .get(invitations_url)
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert!(
+            invitations
+                .iter()
+                .any(|invitation| invitation["id"] == invitation_id.as_str()),
+            "Should list the pending invitation"
+        );
+
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": email,
+                    "password": "correct horse battery staple",
+                    "invitation": "not-a-real-token"
+                }),
+            )
+            .await?;
+        assert_eq!(
+            register_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "Shouldn't register with an unknown invitation"
+        );
+        let preview_get = hc.do_get("/invitations?token=not-a-real-token").await?;
+        assert_eq!(preview_get.status(), StatusCode::UNAUTHORIZED);
+        let accept_post = client
+            .post("http://localhost:3000/api/invitations/accept")
+            .bearer_auth(&bearer)
+            .json(&json!({ "token": bearer }))
+            .send()
+            .await?;
+        assert_eq!(
+            accept_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "An access token isn't an invitation"
+        );
+
+        let revoke_url = format!("{invitations_url}/{invitation_id}");
+        let revoke_delete = client
+            .delete(&revoke_url)
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+        assert_eq!(revoke_delete.status(), StatusCode::OK, "Should revoke");
+        let revoke_delete = client
+            .delete(&revoke_url)
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+        assert_eq!(
+            revoke_delete.status(),
+            StatusCode::NOT_FOUND,
+            "Shouldn't revoke an invitation twice"
+        );
+
+        let state = server_state().await?;
+        let (_, token) = issue_invitation(
+            &state,
+            &Thing::from(("organization", "acme")),
+            &Thing::from(("user", "root")),
+            email.clone(),
+            OrgRole::Admin,
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        let logins = login_root_and_new_user(&hc).await?;
+        let other_id = logins
+            .user_id
+            .parse::<Thing>()
+            .map_err(|_| anyhow::anyhow!("Invalid user id"))?;
+        state
+            .db
+            .query("update $user_id set verified=true")
+            .bind(("user_id", other_id))
+            .await?
+            .check()?;
+        let accept_post = client
+            .post("http://localhost:3000/api/invitations/accept")
+            .bearer_auth(&logins.user.bearer)
+            .json(&json!({ "token": token }))
+            .send()
+            .await?;
+        assert_eq!(
+            accept_post.status(),
+            StatusCode::FORBIDDEN,
+            "Shouldn't accept an invitation sent to another email"
+        );
+
+        let revoked_username = format!("{username}-revoked");
+        let revoked_email = format!("{revoked_username}@example.com");
+        let (revoked, revoked_token) = issue_invitation(
+            &state,
+            &Thing::from(("organization", "acme")),
+            &Thing::from(("user", "root")),
+            revoked_email.clone(),
+            OrgRole::Member,
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        client
+            .delete(format!("{invitations_url}/{}", revoked.id))
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+        let mut statuses = Vec::new();
+        for invitation in [Some(revoked_token), None] {
+            let register_post = hc
+                .do_post(
+                    "/register",
+                    json!({
+                        "username": revoked_username,
+                        "email": revoked_email,
+                        "password": "correct horse battery staple",
+                        "invitation": invitation
+                    }),
+                )
+                .await?;
+            statuses.push(register_post.status());
+        }
+        assert_eq!(
+            statuses,
+            vec![StatusCode::UNAUTHORIZED, StatusCode::OK],
+            "A revoked invitation shouldn't leave an account behind"
+        );
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": format!("{username}-other"),
+                    "email": format!("{username}-other@example.com"),
+                    "password": "correct horse battery staple",
+                    "invitation": token
+                }),
+            )
+            .await?;
+        assert_eq!(
+            register_post.status(),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Shouldn't register with an invitation sent to another email"
+        );
+
+        let register_post = hc
+            .do_post(
+                "/register",
+                json!({
+                    "username": username,
+                    "email": email.to_uppercase(),
+                    "password": "correct horse battery staple",
+                    "invitation": token,
+                    "login": "bearer"
+                }),
+            )
+            .await?;
+        assert_eq!(
+            register_post.status(),
+            StatusCode::OK,
+            "Should register with the invitation sent to this email"
+        );
+        let bearer = register_post
+            .json_body_as::<RegisterResponse>()?
+            .bearer
+            .unwrap_or_default();
+        let organizations: Vec<serde_json::Value> = client
+            .get("http://localhost:3000/api/orgs")
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert!(
+            organizations.iter().any(|organization| {
+                organization["id"] == "organization:acme" && organization["role"] == "admin"
+            }),
+            "Should join with the role of the invitation"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admin_users_with_jwt() -> anyhow::Result<()> {
+        let hc = httpc_test::new_client("http://localhost:3000/api")?;
+        let client = hc.reqwest_client();
+        let username = format!("user-{}", chrono::Utc::now().timestamp_micros());
+        let password = "correct horse battery staple";
+
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({ "username": "root", "password": "root" }),
+            )
+            .await?;
+        let bearer = login_post.json_body_as::<ResponseBearer>()?.bearer;
+        let users_url = "http://localhost:3000/api/admin/users";
+
+        let create_post = client
+            .post(users_url)
+            .bearer_auth(&bearer)
+            .json(&json!({
+                "username": username,
+                "email": format!("{username}@example.com"),
+                "password": password,
+                "verified": true
+            }))
+            .send()
+            .await?;
+        assert_eq!(create_post.status(), StatusCode::OK, "Should create a user");
+        let user: serde_json::Value = create_post.json().await?;
+        let user_id = user["id"].as_str().unwrap_or_default().to_string();
+        let user_url = format!("{users_url}/{user_id}");
+        let create_post = client
+            .post(users_url)
+            .bearer_auth(&bearer)
+            .json(&json!({ "username": username, "email": "other@example.com" }))
+            .send()
+            .await?;
+        assert_eq!(create_post.status(), StatusCode::CONFLICT);
+
+        let page: serde_json::Value = client
+            .get(format!(
+                "{users_url}?q={}&verified=true&per_page=5",
+                username.to_uppercase()
+            ))
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(page["total"], 1, "Should filter the users");
+        assert_eq!(page["users"][0]["id"], user_id.as_str());
+        let list_get = client
+            .get(format!("{users_url}?per_page=1000"))
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+        assert_eq!(list_get.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let updated: serde_json::Value = client
+            .patch(&user_url)
+            .bearer_auth(&bearer)
+            .json(&json!({ "email": format!("{username}@example.org") }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(updated["email"], format!("{username}@example.org"));
+        assert_eq!(updated["username"], username.as_str());
+
+        let user_bearer = hc
+            .do_post(
+                "/bearer/login",
+                json!({ "username": username, "password": password }),
+            )
+            .await?
+            .json_body_as::<ResponseBearer>()?
+            .bearer;
+        let list_get = client
+            .get(users_url)
+            .bearer_auth(&user_bearer)
+            .send()
+            .await?;
+        assert_eq!(
+            list_get.status(),
+            StatusCode::FORBIDDEN,
+            "Only admins should manage the users"
+        );
+
+        let disabled: serde_json::Value = client
+            .post(format!("{user_url}/disable"))
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(disabled["disabled"], true);
+        let me_get = client
+            .get("http://localhost:3000/api/bearer/page")
+            .bearer_auth(&user_bearer)
+            .send()
+            .await?;
+        assert_eq!(
+            me_get.status(),
+            StatusCode::UNAUTHORIZED,
+            "Disabling should revoke the sessions"
+        );
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({ "username": username, "password": password }),
+            )
+            .await?;
+        assert_eq!(login_post.status(), StatusCode::FORBIDDEN);
+        client
+            .post(format!("{user_url}/enable"))
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+
+        for _ in 0..5 {
+            hc.do_post(
+                "/bearer/login",
+                json!({ "username": username, "password": "wrong" }),
+            )
+            .await?;
+        }
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({ "username": username, "password": password }),
+            )
+            .await?;
+        assert_eq!(
+            login_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "Should lock the client out after too many failed logins, like for a wrong password"
+        );
+        let locked: serde_json::Value = client
+            .get(&user_url)
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert!(locked["locked_until"].is_string());
+        let unlocked: serde_json::Value = client
+            .post(format!("{user_url}/unlock"))
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(unlocked["locked_until"], serde_json::Value::Null);
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({ "username": username, "password": password }),
+            )
+            .await?;
+        assert_eq!(login_post.status(), StatusCode::OK, "Should be unlocked");
+
+        let reset_post = client
+            .post(format!("{user_url}/reset-password"))
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+        assert_eq!(reset_post.status(), StatusCode::OK);
+        let login_post = hc
+            .do_post(
+                "/bearer/login",
+                json!({ "username": username, "password": password }),
+            )
+            .await?;
+        assert_eq!(
+            login_post.status(),
+            StatusCode::UNAUTHORIZED,
+            "The password should be gone until reset"
+        );
+
+        let entries: Vec<serde_json::Value> = client
+            .get(format!(
+                "http://localhost:3000/api/admin/audit-log?target={user_id}"
+            ))
+            .bearer_auth(&bearer)
+            .send()
+            .await?
+            .json()
+            .await?;
+        let actions: Vec<&str> = entries
+            .iter()
+            .filter_map(|entry| entry["action"].as_str())
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                "user.reset_password",
+                "user.unlock",
+                "user.enable",
+                "user.disable",
+                "user.update",
+                "user.create"
+            ],
+            "Every write should be audited"
+        );
+        assert!(entries.iter().all(|entry| entry["actor"] == "user:root"));
+
+        let state = server_state().await?;
+        let user_thing = user_id
+            .parse::<Thing>()
+            .map_err(|_| anyhow::anyhow!("Invalid user id"))?;
+        let org = create_organization(&state, &user_thing, username.clone(), username.clone())
+            .await
+            .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        let org_id = org
+            .id
+            .parse::<Thing>()
+            .map_err(|_| anyhow::anyhow!("Invalid organization id"))?;
+        issue_invitation(
+            &state,
+            &org_id,
+            &user_thing,
+            format!("{username}-invited@example.com"),
+            OrgRole::Member,
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        let delete_user = client.delete(&user_url).bearer_auth(&bearer).send().await?;
+        assert_eq!(
+            delete_user.status(),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Shouldn't delete the last owner of an organization"
+        );
+
+        state
+            .db
+            .query("relate user:root->membership->$org_id set role=\"owner\"")
+            .bind(("org_id", org_id.clone()))
+            .await?
+            .check()?;
+        let delete_user = client.delete(&user_url).bearer_auth(&bearer).send().await?;
+        assert_eq!(delete_user.status(), StatusCode::OK, "Should delete");
+        let invitations = list_invitations(&state, &org_id)
+            .await
+            .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        assert!(
+            invitations.is_empty(),
+            "Should delete the invitations the user sent"
+        );
+        let get_user = client.get(&user_url).bearer_auth(&bearer).send().await?;
+        assert_eq!(get_user.status(), StatusCode::NOT_FOUND);
+        let delete_root = client
+            .delete(format!("{users_url}/root"))
+            .bearer_auth(&bearer)
+            .send()
+            .await?;
+        assert_eq!(
+            delete_root.status(),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "An admin shouldn't delete itself"
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,246 @@
+use crate::auth::cookie_jwt::CookieJWTClaims;
+use crate::auth::oauth::{
+    decode_consent, encode_consent, find_oauth_client, has_consent, issue_authorization_code,
+    record_consent, AuthorizationGrant, OAuthClient, SUPPORTED_SCOPES,
+};
+use crate::auth::security_stamp::TokenSubject;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::extract::{Query, RawQuery, State};
+use axum::http::header::{CONTENT_SECURITY_POLICY, X_FRAME_OPTIONS};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Form;
+use reqwest::Url;
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeQuery {
+    response_type: Option<String>,
+    client_id: Option<String>,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    /// `none` to fail instead of asking the user anything, `consent` to always ask.
+    prompt: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConsentForm {
+    consent: String,
+    /// `allow` or `deny`.
+    decision: String,
+}
+
+/// Send the user back to the client, with the `params` of the authorization response.
+fn redirect_to_client(
+    redirect_uri: &str,
+    params: &[(&str, &str)],
+    state: Option<&str>,
+) -> ApiResult<Response> {
+    let mut url = Url::parse(redirect_uri)
+        .map_err(|_| BackendError::ValidationFailed("Invalid `redirect_uri`".to_string()))?;
+    {
+        let mut query = url.query_pairs_mut();
+        query.extend_pairs(params);
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+        query.append_pair("iss", &env_config().oauth_issuer);
+    }
+    Ok(Redirect::to(url.as_str()).into_response())
+}
+
+/// Start an authorization code flow for a registered client.
+///
+/// The user must be logged in with the cookie session, and is asked for its consent the first
+/// time a client requests a scope. Errors about the client or its `redirect_uri` are answered
+/// directly, the other ones are sent to the client.
+pub async fn api_authorize(
+    State(state): State<RouterState>,
+    claims: ApiResult<CookieJWTClaims>,
+    RawQuery(raw_query): RawQuery,
+    Query(query): Query<AuthorizeQuery>,
+) -> ApiResult<Response> {
+    let client = find_oauth_client(&state, query.client_id.as_deref().unwrap_or_default()).await?;
+    let redirect_uri = query
+        .redirect_uri
+        .clone()
+        .filter(|redirect_uri| client.redirect_uris.contains(redirect_uri))
+        .ok_or_else(|| {
+            BackendError::ValidationFailed(
+                "The `redirect_uri` isn't registered for this client".to_string(),
+            )
+        })?;
+    let fail = |error: &str| {
+        redirect_to_client(&redirect_uri, &[("error", error)], query.state.as_deref())
+    };
+
+    if query.response_type.as_deref() != Some("code") {
+        return fail("unsupported_response_type");
+    }
+    let code_challenge = match (
+        &query.code_challenge,
+        query.code_challenge_method.as_deref(),
+    ) {
+        (Some(code_challenge), Some("S256")) => code_challenge.clone(),
+        _ => return fail("invalid_request"),
+    };
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in query
+        .scope
+        .as_deref()
+        .unwrap_or("openid")
+        .split_whitespace()
+    {
+        if !SUPPORTED_SCOPES.contains(&scope) {
+            return fail("invalid_scope");
+        }
+        if !scopes.iter().any(|granted| granted == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+    if scopes.is_empty() {
+        return fail("invalid_scope");
+    }
+
+    let prompt = query.prompt.as_deref();
+    let claims = match claims {
+        Ok(claims) => claims,
+        Err(_) if prompt == Some("none") => return fail("login_required"),
+        Err(err) => {
+            let Some(login_url) = &env_config().oauth_login_url else {
+                return Err(err);
+            };
+            let return_to = format!(
+                "{}/authorize?{}",
+                env_config().oauth_issuer,
+                raw_query.unwrap_or_default()
+            );
+            let login_url = Url::parse_with_params(login_url, [("return_to", return_to)])
+                .map_err(|_| BackendError::SomethingWentWrong)?;
+            return Ok(Redirect::to(login_url.as_str()).into_response());
+        }
+    };
+    let user_id = claims
+        .sub
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+
+    let grant = AuthorizationGrant {
+        client_id: client.client_id.clone(),
+        redirect_uri: redirect_uri.clone(),
+        scopes,
+        state: query.state.clone(),
+        nonce: query.nonce.clone(),
+        code_challenge,
+    };
+    if prompt != Some("consent") && has_consent(&state, &user_id, &client, &grant.scopes).await? {
+        let subject = TokenSubject::new(&user_id, claims.stamp.clone());
+        return authorize_grant(&state, &subject, &client, &grant).await;
+    }
+    if prompt == Some("none") {
+        return fail("consent_required");
+    }
+
+    let consent = encode_consent(&claims.sub, &grant)?;
+    Ok((
+        [
+            (X_FRAME_OPTIONS, "DENY"),
+            (CONTENT_SECURITY_POLICY, "frame-ancestors 'none'"),
+        ],
+        consent_page(&client, &grant, &consent),
+    )
+        .into_response())
+}
+
+/// Answer the consent screen, sending the user back to the client with a code when allowed.
+pub async fn api_consent(
+    State(state): State<RouterState>,
+    claims: CookieJWTClaims,
+    Form(form): Form<ConsentForm>,
+) -> ApiResult<Response> {
+    let grant = decode_consent(&form.consent, &claims.sub)?;
+    let client = find_oauth_client(&state, &grant.client_id).await?;
+    if !client.redirect_uris.contains(&grant.redirect_uri) {
+        return Err(BackendError::ValidationFailed(
+            "The `redirect_uri` isn't registered for this client".to_string(),
+        ));
+    }
+    if form.decision != "allow" {
+        return redirect_to_client(
+            &grant.redirect_uri,
+            &[("error", "access_denied")],
+            grant.state.as_deref(),
+        );
+    }
+
+    let user_id = claims
+        .sub
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    record_consent(&state, &user_id, &client, &grant.scopes).await?;
+    let subject = TokenSubject::new(&user_id, claims.stamp.clone());
+    authorize_grant(&state, &subject, &client, &grant).await
+}
+
+async fn authorize_grant(
+    state: &RouterState,
+    subject: &TokenSubject,
+    client: &OAuthClient,
+    grant: &AuthorizationGrant,
+) -> ApiResult<Response> {
+    let code = issue_authorization_code(state, subject, client, grant).await?;
+    redirect_to_client(
+        &grant.redirect_uri,
+        &[("code", code.as_str())],
+        grant.state.as_deref(),
+    )
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn describe_scope(scope: &str) -> &'static str {
+    match scope {
+        "openid" => "Know who you are on this service",
+        "email" => "See your email address",
+        "profile" => "See your username",
+        _ => "Unknown permission",
+    }
+}
+
+fn consent_page(client: &OAuthClient, grant: &AuthorizationGrant, consent: &str) -> Html<String> {
+    let name = escape_html(&client.name);
+    let scopes: String = grant
+        .scopes
+        .iter()
+        .map(|scope| format!("<li>{}</li>", describe_scope(scope)))
+        .collect();
+    Html(format!(
+        r#"<!doctype html>
+<html>
+<head><meta charset="utf-8"><title>Authorize {name}</title></head>
+<body>
+<h1>{name} wants to access your account</h1>
+<p>It will be able to:</p>
+<ul>{scopes}</ul>
+<form method="post" action="authorize">
+<input type="hidden" name="consent" value="{consent}">
+<button type="submit" name="decision" value="allow">Allow</button>
+<button type="submit" name="decision" value="deny">Deny</button>
+</form>
+</body>
+</html>
+"#,
+        consent = escape_html(consent),
+    ))
+}
@@ -0,0 +1,30 @@
+use crate::auth::oauth::{oauth_jwks, SUPPORTED_SCOPES};
+use crate::env_config;
+use axum::Json;
+use serde_json::{json, Value};
+
+/// The discovery document of this service as an OpenID Connect provider.
+pub async fn api_openid_configuration() -> Json<Value> {
+    let issuer = &env_config().oauth_issuer;
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "userinfo_endpoint": format!("{issuer}/userinfo"),
+        "jwks_uri": format!("{issuer}/jwks"),
+        "scopes_supported": SUPPORTED_SCOPES,
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code", "client_credentials"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["ES256"],
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+        "code_challenge_methods_supported": ["S256"],
+        "claims_supported": ["sub", "iss", "aud", "exp", "iat", "nonce", "preferred_username", "email", "email_verified"],
+        "authorization_response_iss_parameter_supported": true,
+    }))
+}
+
+/// The public keys of the ID and access tokens.
+pub async fn api_jwks() -> Json<Value> {
+    Json(oauth_jwks())
+}
@@ -0,0 +1,42 @@
+use crate::auth::cookie_jwt::cookie_jwt_bearer_resolver;
+use crate::RouterState;
+use axum::routing::get;
+use axum::Router;
+use tower_cookies::CookieManagerLayer;
+
+mod authorize;
+mod discovery;
+mod token;
+mod userinfo;
+
+/// The endpoints letting other applications log their users in through this service.
+///
+/// `/authorize` relies on the cookie session as the login state, the other endpoints are called
+/// by the clients themselves.
+pub fn create_oauth_router(state: RouterState) -> Router {
+    let authorize_router = Router::new()
+        .route(
+            "/authorize",
+            get(authorize::api_authorize).post(authorize::api_consent),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            cookie_jwt_bearer_resolver,
+        ))
+        .layer(CookieManagerLayer::new())
+        .with_state(state.clone());
+
+    Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(discovery::api_openid_configuration),
+        )
+        .route("/jwks", get(discovery::api_jwks))
+        .route("/token", axum::routing::post(token::api_token))
+        .route(
+            "/userinfo",
+            get(userinfo::api_userinfo).post(userinfo::api_userinfo),
+        )
+        .with_state(state)
+        .merge(authorize_router)
+}
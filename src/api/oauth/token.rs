@@ -0,0 +1,150 @@
+use crate::auth::oauth::{
+    authenticate_oauth_client, issue_oauth_tokens, redeem_authorization_code, OAuthTokens,
+};
+use crate::auth::service_account::{authenticate_service_account, issue_service_token};
+use crate::{env_config, BackendError, RouterState};
+use axum::extract::State;
+use axum::http::header::{CACHE_CONTROL, PRAGMA};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Form, Json};
+use axum_extra::headers::authorization::Basic;
+use axum_extra::headers::Authorization;
+use axum_extra::TypedHeader;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+pub struct TokenForm {
+    grant_type: String,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    /// The scopes a service account asks for, all of its scopes by default.
+    scope: Option<String>,
+}
+
+/// An error of the token endpoint, in the format OAuth clients expect (RFC 6749, section 5.2).
+#[derive(Debug)]
+pub struct OAuthError {
+    status: StatusCode,
+    error: &'static str,
+    description: Option<String>,
+}
+
+impl OAuthError {
+    fn new(status: StatusCode, error: &'static str) -> Self {
+        Self {
+            status,
+            error,
+            description: None,
+        }
+    }
+}
+
+impl From<BackendError> for OAuthError {
+    fn from(err: BackendError) -> Self {
+        match err {
+            BackendError::InvalidClient => Self::new(StatusCode::UNAUTHORIZED, "invalid_client"),
+            BackendError::InvalidGrant => Self::new(StatusCode::BAD_REQUEST, "invalid_grant"),
+            BackendError::InvalidScope => Self::new(StatusCode::BAD_REQUEST, "invalid_scope"),
+            BackendError::ValidationFailed(description) => Self {
+                description: Some(description),
+                ..Self::new(StatusCode::BAD_REQUEST, "invalid_request")
+            },
+            _ => Self::new(StatusCode::INTERNAL_SERVER_ERROR, "server_error"),
+        }
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let body = match self.description {
+            Some(description) => json!({ "error": self.error, "error_description": description }),
+            None => json!({ "error": self.error }),
+        };
+        (
+            self.status,
+            [(CACHE_CONTROL, "no-store"), (PRAGMA, "no-cache")],
+            Json(body),
+        )
+            .into_response()
+    }
+}
+
+fn missing(parameter: &str) -> OAuthError {
+    BackendError::ValidationFailed(format!("Missing `{parameter}`")).into()
+}
+
+/// Exchange an authorization code for tokens, or authenticate a service account with the
+/// `client_credentials` grant.
+///
+/// Clients authenticate with HTTP Basic or the `client_secret` parameter, public clients only
+/// sending their `client_id` and relying on PKCE.
+pub async fn api_token(
+    State(state): State<RouterState>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Form(form): Form<TokenForm>,
+) -> Result<Response, OAuthError> {
+    let (client_id, client_secret) = match (&basic, &form.client_secret) {
+        (Some(TypedHeader(Authorization(basic))), None) => {
+            (basic.username().to_string(), Some(basic.password()))
+        }
+        (Some(_), Some(_)) => {
+            return Err(BackendError::ValidationFailed(
+                "Only one client authentication method can be used".to_string(),
+            )
+            .into())
+        }
+        (None, client_secret) => (
+            form.client_id.clone().ok_or_else(|| missing("client_id"))?,
+            client_secret.as_deref(),
+        ),
+    };
+
+    let tokens = match form.grant_type.as_str() {
+        "authorization_code" => {
+            let client = authenticate_oauth_client(&state, &client_id, client_secret).await?;
+            let code = form.code.as_deref().ok_or_else(|| missing("code"))?;
+            let redirect_uri = form
+                .redirect_uri
+                .as_deref()
+                .ok_or_else(|| missing("redirect_uri"))?;
+            let code_verifier = form
+                .code_verifier
+                .as_deref()
+                .ok_or_else(|| missing("code_verifier"))?;
+
+            let redeemed =
+                redeem_authorization_code(&state, &client, code, redirect_uri, code_verifier)
+                    .await?;
+            issue_oauth_tokens(&state, &client, &redeemed).await?
+        }
+        "client_credentials" => {
+            let client_secret = client_secret.ok_or(BackendError::InvalidClient)?;
+            let account = authenticate_service_account(&state, &client_id, client_secret).await?;
+            let (access_token, scope) = issue_service_token(&account, form.scope.as_deref())?;
+            OAuthTokens {
+                access_token,
+                token_type: "Bearer".to_string(),
+                expires_in: env_config().jwt_access_ttl * 60,
+                scope,
+                id_token: None,
+            }
+        }
+        _ => {
+            return Err(OAuthError::new(
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+            ))
+        }
+    };
+
+    Ok((
+        [(CACHE_CONTROL, "no-store"), (PRAGMA, "no-cache")],
+        Json(tokens),
+    )
+        .into_response())
+}
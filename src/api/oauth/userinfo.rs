@@ -0,0 +1,27 @@
+use crate::auth::oauth::{user_claims, verify_oauth_access_token};
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use axum_extra::headers::authorization::Bearer;
+use axum_extra::headers::Authorization;
+use axum_extra::TypedHeader;
+use serde_json::{Map, Value};
+use surrealdb::sql::Thing;
+
+/// The claims about the user an access token was issued for, limited to the granted scopes.
+pub async fn api_userinfo(
+    State(state): State<RouterState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> ApiResult<Json<Map<String, Value>>> {
+    let claims = verify_oauth_access_token(&state, bearer.token()).await?;
+    let user_id = claims
+        .sub
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let scopes: Vec<String> = claims
+        .scope
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    Ok(Json(user_claims(&state, &user_id, &scopes).await?))
+}
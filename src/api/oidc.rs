@@ -0,0 +1,96 @@
+use super::credentials::{ensure_email_verified, load_user};
+use super::register::LoginMode;
+use super::{start_login, ResponseLogin, ResponseMfaPending};
+use crate::auth::client_info::ClientInfo;
+use crate::auth::mfa::create_mfa_challenge;
+use crate::auth::oidc::{find_oidc_provider, finish_oidc_login, start_oidc_login};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::extract::{Path, Query, State};
+use axum::response::Redirect;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+use tower_cookies::{CookieManagerLayer, Cookies};
+
+#[derive(Debug, Deserialize)]
+pub struct OidcAuthorizeQuery {
+    login: LoginMode,
+}
+
+/// The redirection from the provider, with either a `code` or an `error`.
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+pub fn create_oidc_router(state: RouterState) -> Router {
+    Router::new()
+        .route("/oidc/providers", get(api_oidc_providers))
+        .route("/oidc/:provider/authorize", get(api_oidc_authorize))
+        .route("/oidc/:provider/callback", get(api_oidc_callback))
+        .layer(CookieManagerLayer::new())
+        .with_state(state)
+}
+
+/// List the providers users can sign in with.
+pub async fn api_oidc_providers() -> Json<Value> {
+    let providers: Vec<&str> = env_config()
+        .oidc_providers
+        .iter()
+        .map(|provider| provider.name.as_str())
+        .collect();
+    Json(json!({ "providers": providers }))
+}
+
+/// Redirect the user to the authorization endpoint of a provider.
+pub async fn api_oidc_authorize(
+    cookies: Cookies,
+    State(state): State<RouterState>,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcAuthorizeQuery>,
+) -> ApiResult<Redirect> {
+    let provider = find_oidc_provider(&provider)?;
+    let url = start_oidc_login(&state, &cookies, provider, query.login.into()).await?;
+    Ok(Redirect::to(&url))
+}
+
+/// Log in the user the provider redirected back, the second factor being still required when
+/// the user has one.
+pub async fn api_oidc_callback(
+    cookies: Cookies,
+    client: ClientInfo,
+    State(state): State<RouterState>,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcCallbackQuery>,
+) -> ApiResult<Json<ResponseLogin>> {
+    let provider = find_oidc_provider(&provider)?;
+    let (code, state_param) = match (query.code, query.state, query.error) {
+        (Some(code), Some(state_param), None) => (code, state_param),
+        _ => return Err(BackendError::InvalidCredentials),
+    };
+    let (subject, kind) =
+        finish_oidc_login(&state, &cookies, provider, &code, &state_param).await?;
+    let user_id = subject
+        .user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+
+    let user = load_user(&state, &user_id).await?;
+    ensure_email_verified(&user)?;
+    let methods = user.mfa_methods();
+    if !methods.is_empty() {
+        let mfa_pending = create_mfa_challenge(&state, &subject, kind).await?;
+        return Ok(Json(ResponseLogin::MfaPending(ResponseMfaPending {
+            mfa_pending,
+            methods,
+        })));
+    }
+
+    Ok(Json(ResponseLogin::Bearer(
+        start_login(&state, cookies, &client, &subject, kind).await?,
+    )))
+}
@@ -0,0 +1,121 @@
+use super::super::validation::validate_email;
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::invitation::{
+    accept_invitation, issue_invitation, list_invitations, preview_invitation, revoke_invitation,
+    InvitationInfo, InvitationPreview,
+};
+use crate::auth::organization::{load_organization, OrgMember, OrgRole, OrganizationInfo};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Deserialize)]
+pub struct InvitePayload {
+    email: String,
+    #[serde(default = "default_role")]
+    role: OrgRole,
+}
+
+fn default_role() -> OrgRole {
+    OrgRole::Member
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InvitationTokenQuery {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvitationPayload {
+    token: String,
+}
+
+/// List the pending invitations of the organization, for its admins.
+pub async fn api_list_invitations(
+    State(state): State<RouterState>,
+    member: OrgMember,
+) -> ApiResult<Json<Vec<InvitationInfo>>> {
+    member.require(OrgRole::Admin)?;
+    Ok(Json(list_invitations(&state, &member.org_id).await?))
+}
+
+/// Email an invitation to join the organization, for its admins.
+///
+/// The role can't be above the role of the member sending it.
+pub async fn api_invite(
+    State(state): State<RouterState>,
+    member: OrgMember,
+    Json(payload): Json<InvitePayload>,
+) -> ApiResult<Json<InvitationInfo>> {
+    member.require(OrgRole::Admin)?;
+    member.require(payload.role)?;
+    let email = payload.email.trim().to_string();
+    validate_email(&email)?;
+
+    let organization = load_organization(&state, &member.user_id, &member.org_id).await?;
+    let (invitation, token) =
+        issue_invitation(&state, &member.org_id, &member.user_id, email, payload.role).await?;
+    let link = format!(
+        "{}/invitations/accept?token={token}",
+        env_config().public_url
+    );
+    if let Err(err) = state
+        .mailer
+        .send(
+            &invitation.email,
+            &format!("Join {} on Axum Auth API", organization.name),
+            format!(
+                "You have been invited to join {} as {}. Open this link within 7 days to accept, logging in or creating an account:\n\n{link}\n\nIf you don't know this organization, you can ignore this email.\n",
+                organization.name,
+                invitation.role.as_str(),
+            ),
+        )
+        .await
+    {
+        tracing::warn!("Failed to send the invitation `{}`: {err:?}", invitation.id);
+    }
+    Ok(Json(invitation))
+}
+
+/// Revoke a pending invitation of the organization, for its admins.
+pub async fn api_revoke_invitation(
+    State(state): State<RouterState>,
+    member: OrgMember,
+    Path(invitation_id): Path<String>,
+) -> ApiResult<Json<Value>> {
+    member.require(OrgRole::Admin)?;
+    let invitation_id = match invitation_id.starts_with("invitation:") {
+        true => invitation_id,
+        false => format!("invitation:{invitation_id}"),
+    };
+    revoke_invitation(&state, &member.org_id, &invitation_id).await?;
+    Ok(Json(json!({
+        "value": "The invitation has been revoked",
+    })))
+}
+
+/// Show a pending invitation, for the page of its link.
+pub async fn api_preview_invitation(
+    State(state): State<RouterState>,
+    Query(query): Query<InvitationTokenQuery>,
+) -> ApiResult<Json<InvitationPreview>> {
+    Ok(Json(preview_invitation(&state, &query.token).await?))
+}
+
+/// Accept an invitation sent to the verified email of the logged in user, which joins the
+/// organization.
+pub async fn api_accept_invitation(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    Json(payload): Json<AcceptInvitationPayload>,
+) -> ApiResult<Json<OrganizationInfo>> {
+    let user_id = claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let org_id = accept_invitation(&state, &payload.token, &user_id).await?;
+    Ok(Json(load_organization(&state, &user_id, &org_id).await?))
+}
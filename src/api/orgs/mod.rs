@@ -0,0 +1,48 @@
+use crate::auth::cookie_jwt::cookie_jwt_bearer_resolver;
+use crate::RouterState;
+use axum::routing::{delete, get, post};
+use axum::Router;
+use tower_cookies::CookieManagerLayer;
+
+mod invitations;
+mod organizations;
+mod settings;
+mod switch;
+
+/// Routes on the organizations of the user and their invitations, with either a bearer token or a
+/// jwt cookie.
+pub fn create_orgs_router(state: RouterState) -> Router {
+    Router::new()
+        .route(
+            "/orgs",
+            get(organizations::api_list_organizations).post(organizations::api_create_organization),
+        )
+        .route("/orgs/switch", post(switch::api_switch_organization))
+        .route(
+            "/orgs/current",
+            get(organizations::api_current_organization),
+        )
+        .route(
+            "/orgs/current/settings",
+            get(settings::api_get_settings).put(settings::api_update_settings),
+        )
+        .route(
+            "/orgs/current/invitations",
+            get(invitations::api_list_invitations).post(invitations::api_invite),
+        )
+        .route(
+            "/orgs/current/invitations/:id",
+            delete(invitations::api_revoke_invitation),
+        )
+        .route("/invitations", get(invitations::api_preview_invitation))
+        .route(
+            "/invitations/accept",
+            post(invitations::api_accept_invitation),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            cookie_jwt_bearer_resolver,
+        ))
+        .layer(CookieManagerLayer::new())
+        .with_state(state)
+}
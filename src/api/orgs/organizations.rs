@@ -0,0 +1,55 @@
+use super::super::validation::{validate_name, validate_slug};
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::organization::{
+    create_organization, list_organizations, load_organization, OrgMember, OrganizationInfo,
+};
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateOrganizationPayload {
+    name: String,
+    /// The unique handle of the organization, 3 to 32 lowercase letters, digits or `-`.
+    slug: String,
+}
+
+fn user_id(claims: &AnyJWTClaims) -> ApiResult<Thing> {
+    claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)
+}
+
+/// List the organizations of the user, with its role in each of them.
+pub async fn api_list_organizations(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+) -> ApiResult<Json<Vec<OrganizationInfo>>> {
+    Ok(Json(list_organizations(&state, &user_id(&claims)?).await?))
+}
+
+/// Create an organization owned by the user.
+pub async fn api_create_organization(
+    State(state): State<RouterState>,
+    claims: AnyJWTClaims,
+    Json(payload): Json<CreateOrganizationPayload>,
+) -> ApiResult<Json<OrganizationInfo>> {
+    let name = payload.name.trim().to_string();
+    validate_name(&name)?;
+    validate_slug(&payload.slug)?;
+    let organization = create_organization(&state, &user_id(&claims)?, name, payload.slug).await?;
+    Ok(Json(organization))
+}
+
+/// The organization the token acts in.
+pub async fn api_current_organization(
+    State(state): State<RouterState>,
+    member: OrgMember,
+) -> ApiResult<Json<OrganizationInfo>> {
+    Ok(Json(
+        load_organization(&state, &member.user_id, &member.org_id).await?,
+    ))
+}
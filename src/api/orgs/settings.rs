@@ -0,0 +1,49 @@
+use crate::auth::organization::{organization_db, OrgMember, OrgRole};
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde_json::{Map, Value};
+use surrealdb::sql::Thing;
+
+/// The record holding the settings of an organization, in the database of its tenant.
+fn settings_id(member: &OrgMember) -> Thing {
+    Thing::from(("org_settings", member.org_id.id.clone()))
+}
+
+/// The settings of the organization the token acts in.
+pub async fn api_get_settings(
+    State(state): State<RouterState>,
+    member: OrgMember,
+) -> ApiResult<Json<Map<String, Value>>> {
+    let db = organization_db(&state, &member.org_id).await?;
+    let mut result = db
+        .query("select value settings from $settings_id")
+        .bind(("settings_id", settings_id(&member)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let settings: Option<Map<String, Value>> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(Json(settings.unwrap_or_default()))
+}
+
+/// Replace the settings of the organization the token acts in, for its admins.
+pub async fn api_update_settings(
+    State(state): State<RouterState>,
+    member: OrgMember,
+    Json(settings): Json<Map<String, Value>>,
+) -> ApiResult<Json<Map<String, Value>>> {
+    member.require(OrgRole::Admin)?;
+    let db = organization_db(&state, &member.org_id).await?;
+    db.query(
+        "upsert $settings_id set settings=$settings, updated_by=$user_id, updated_at=time::now()",
+    )
+    .bind(("settings_id", settings_id(&member)))
+    .bind(("settings", Value::Object(settings.clone())))
+    .bind(("user_id", member.user_id.clone()))
+    .await
+    .map_err(|_| BackendError::SomethingWentWrong)?
+    .check()
+    .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(Json(settings))
+}
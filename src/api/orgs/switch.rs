@@ -0,0 +1,78 @@
+use super::super::{bearer_jwt, cookies_jwt, ResponseBearer};
+use crate::auth::bearer_jwt::encode_required_jwt_bearer_claims;
+use crate::auth::claims::AnyJWTClaims;
+use crate::auth::cookie_jwt::encode_cookie_jwt_bearer_claims;
+use crate::auth::organization::{parse_org_id, switch_session_organization};
+use crate::auth::security_stamp::load_token_subject;
+use crate::config::CookieMode;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::Json;
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+use tower_cookies::Cookies;
+
+#[derive(Debug, Deserialize)]
+pub struct SwitchOrganizationPayload {
+    /// The organization to act in, leaving the current one without it.
+    #[serde(default)]
+    org_id: Option<String>,
+}
+
+/// Make the session act in another organization of the user, reissuing its token with the new
+/// `org_id` claim.
+///
+/// The refresh token of a bearer session keeps working, and the tokens it issues carry the new
+/// organization. In cookie session mode the cookie itself is unchanged.
+pub async fn api_switch_organization(
+    State(state): State<RouterState>,
+    cookies: Cookies,
+    claims: AnyJWTClaims,
+    Json(payload): Json<SwitchOrganizationPayload>,
+) -> ApiResult<Json<ResponseBearer>> {
+    let user_id = claims
+        .sub()
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let session_id = claims.sid().ok_or(BackendError::InvalidToken)?;
+    let org_id = payload.org_id.as_deref().map(parse_org_id).transpose()?;
+    switch_session_organization(&state, &user_id, session_id, org_id.as_ref()).await?;
+
+    let subject = load_token_subject(&state, &user_id)
+        .await?
+        .with_session(session_id)
+        .with_org(org_id.map(|org_id| org_id.to_string()));
+    let data_user_id = subject.user_id.clone();
+    let bearer = match &claims {
+        AnyJWTClaims::Bearer(claims) => {
+            state
+                .revocations
+                .revoke(&state.db, &claims.jti, claims.exp)
+                .await?;
+            let data = bearer_jwt::User {
+                user_id: data_user_id,
+            };
+            encode_required_jwt_bearer_claims(&subject, data)?
+        }
+        AnyJWTClaims::Cookie(claims) => match env_config().cookie_mode {
+            CookieMode::Jwt => {
+                state
+                    .revocations
+                    .revoke(&state.db, &claims.jti, claims.exp)
+                    .await?;
+                let data = cookies_jwt::User {
+                    user_id: data_user_id,
+                };
+                encode_cookie_jwt_bearer_claims(cookies, &subject, data)?
+            }
+            CookieMode::Session => cookies
+                .get(&env_config().jwt_cookie_name)
+                .map(|cookie| cookie.value().to_string())
+                .ok_or(BackendError::NoCookieFound)?,
+        },
+    };
+    Ok(Json(ResponseBearer {
+        bearer,
+        refresh_token: None,
+    }))
+}
@@ -0,0 +1,85 @@
+use super::credentials::{ensure_email_verified, load_user};
+use super::register::LoginMode;
+use super::{start_login, ResponseBearer};
+use crate::auth::client_info::ClientInfo;
+use crate::auth::webauthn::{
+    start_passkey_authentication, verify_passkey_assertion, AuthenticationCredential,
+    RequestOptions,
+};
+use crate::{ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+use tower_cookies::{CookieManagerLayer, Cookies};
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyOptionsPayload {
+    /// Restrict the login to the passkeys of this user, for authenticators without discoverable
+    /// credentials.
+    #[serde(default)]
+    username: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginPayload {
+    login: LoginMode,
+    credential: AuthenticationCredential,
+}
+
+pub fn create_passkey_router(state: RouterState) -> Router {
+    Router::new()
+        .route("/passkey/login/options", post(api_passkey_options))
+        .route("/passkey/login", post(api_login_passkey))
+        .layer(CookieManagerLayer::new())
+        .with_state(state)
+}
+
+/// Start a passwordless login with a passkey.
+pub async fn api_passkey_options(
+    State(state): State<RouterState>,
+    payload: Json<PasskeyOptionsPayload>,
+) -> ApiResult<Json<RequestOptions>> {
+    let user_id = match &payload.username {
+        Some(username) => {
+            let mut result = state
+                .db
+                .query("select value id from user where username=$username")
+                .bind(("username", username.clone()))
+                .await
+                .map_err(|_| BackendError::SomethingWentWrong)?;
+            let user_id: Option<Thing> = result
+                .take(0)
+                .map_err(|_| BackendError::SomethingWentWrong)?;
+            user_id
+        }
+        None => None,
+    };
+    Ok(Json(
+        start_passkey_authentication(&state, user_id.as_ref(), true).await?,
+    ))
+}
+
+/// Log a user in with a passkey alone, which must have verified the user with a PIN or biometrics.
+pub async fn api_login_passkey(
+    cookies: Cookies,
+    client: ClientInfo,
+    State(state): State<RouterState>,
+    payload: Json<PasskeyLoginPayload>,
+) -> ApiResult<Json<ResponseBearer>> {
+    let user_id = verify_passkey_assertion(&state, &payload.credential, None, true).await?;
+    let user = load_user(&state, &user_id).await?;
+    ensure_email_verified(&user)?;
+
+    Ok(Json(
+        start_login(
+            &state,
+            cookies,
+            &client,
+            &user.token_subject(),
+            payload.login.into(),
+        )
+        .await?,
+    ))
+}
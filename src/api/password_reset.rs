@@ -0,0 +1,120 @@
+use super::credentials::update_password;
+use super::validation::validate_password;
+use crate::auth::password::hash_password;
+use crate::auth::token::{generate_token, hash_token};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+
+const RESET_TTL: &str = "30m";
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordPayload {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordPayload {
+    token: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBResetUser {
+    user_id: Thing,
+    email: String,
+}
+
+pub fn create_password_reset_router(state: RouterState) -> Router {
+    Router::new()
+        .route("/password/forgot", post(api_forgot_password))
+        .route("/password/reset", post(api_reset_password))
+        .with_state(state)
+}
+
+/// Email a reset link if an account uses this address.
+///
+/// The answer is always the same and the email is sent in the background, so this can't be used
+/// to find out which addresses have an account.
+pub async fn api_forgot_password(
+    State(state): State<RouterState>,
+    payload: Json<ForgotPasswordPayload>,
+) -> ApiResult<Json<Value>> {
+    let email = payload.email.clone();
+    tokio::spawn(async move {
+        if let Err(err) = send_reset_email(&state, email).await {
+            tracing::warn!("Failed to send a password reset email: {err:?}");
+        }
+    });
+
+    Ok(Json(json!({
+        "value": "If an account uses this email, a reset link has been sent to it",
+    })))
+}
+
+/// Email a reset link to the user with this address, if there is one.
+pub(super) async fn send_reset_email(state: &RouterState, email: String) -> ApiResult<()> {
+    let mut result = state
+        .db
+        .query("select id as user_id, email from user where email=$email")
+        .bind(("email", email))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user: Option<DBResetUser> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let Some(user) = user else {
+        return Ok(());
+    };
+
+    let token = generate_token();
+    state
+        .db
+        .query(format!("create password_reset set user=$user_id, token_hash=$token_hash, expires_at=time::now() + {RESET_TTL}"))
+        .bind(("user_id", user.user_id))
+        .bind(("token_hash", hash_token(&token)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+
+    let link = format!("{}/reset-password?token={token}", env_config().public_url);
+    state
+        .mailer
+        .send(
+            &user.email,
+            "Reset your password",
+            format!("A password reset was requested for your account. Choose a new password within {RESET_TTL} by opening this link:\n\n{link}\n\nIf you didn't ask for it, you can ignore this email.\n"),
+        )
+        .await
+}
+
+/// Consume a reset token and replace the password, which logs the user out everywhere.
+pub async fn api_reset_password(
+    State(state): State<RouterState>,
+    payload: Json<ResetPasswordPayload>,
+) -> ApiResult<Json<Value>> {
+    validate_password(&payload.password)?;
+
+    let mut result = state
+        .db
+        .query("(update password_reset set used_at=time::now() where token_hash=$token_hash and used_at=none and expires_at>time::now() return value user)[0]")
+        .bind(("token_hash", hash_token(&payload.token)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user_id: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user_id = user_id.ok_or(BackendError::InvalidToken)?;
+
+    let password_hash = hash_password(payload.password.clone()).await?;
+    update_password(&state, &user_id, password_hash).await?;
+
+    Ok(Json(json!({
+        "value": "Your password has been reset",
+    })))
+}
@@ -0,0 +1,233 @@
+use super::bearer_jwt::User as BearerUser;
+use super::cookies_jwt::User as CookieUser;
+use super::email_verification::send_verification_email;
+use super::validation::{validate_email, validate_password, validate_username};
+use crate::auth::client_info::ClientInfo;
+use crate::auth::cookie_session::start_cookie_session;
+use crate::auth::invitation::{
+    decode_invitation, preview_invitation, InvitationClaims, ACCEPT_INVITATION,
+};
+use crate::auth::password::hash_password;
+use crate::auth::security_stamp::TokenSubject;
+use crate::auth::session::{start_bearer_session, SessionKind};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+use tower_cookies::{CookieManagerLayer, Cookies};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginMode {
+    Cookie,
+    Bearer,
+}
+
+impl From<LoginMode> for SessionKind {
+    fn from(mode: LoginMode) -> Self {
+        match mode {
+            LoginMode::Cookie => SessionKind::Cookie,
+            LoginMode::Bearer => SessionKind::Bearer,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterPayload {
+    username: String,
+    email: String,
+    password: String,
+    /// Log the user in right after the registration, ignored while the email needs to be verified.
+    login: Option<LoginMode>,
+    /// The token of an invitation link sent to `email`, accepted along with the user's creation.
+    invitation: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisterResponse {
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bearer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBCreatedUser {
+    id: Thing,
+    security_stamp: String,
+}
+
+pub fn create_register_router(state: RouterState) -> Router {
+    Router::new()
+        .route("/register", post(api_register))
+        .layer(CookieManagerLayer::new())
+        .with_state(state)
+}
+
+pub async fn api_register(
+    cookies: Cookies,
+    client: ClientInfo,
+    State(state): State<RouterState>,
+    payload: Json<RegisterPayload>,
+) -> ApiResult<Json<RegisterResponse>> {
+    validate_username(&payload.username)?;
+    validate_email(&payload.email)?;
+    validate_password(&payload.password)?;
+    let invitation = match &payload.invitation {
+        Some(token) => {
+            let preview = preview_invitation(&state, token).await?;
+            if !preview.email.eq_ignore_ascii_case(&payload.email) {
+                return Err(BackendError::ValidationFailed(
+                    "The invitation was sent to another email".to_string(),
+                ));
+            }
+            Some(decode_invitation(token)?)
+        }
+        None => None,
+    };
+    // The invitation link was emailed, so opening it verifies the address it was sent to.
+    let verified = invitation.is_some();
+
+    let password_hash = hash_password(payload.password.clone()).await?;
+    let user = create_user(
+        &state,
+        &payload.username,
+        &payload.email,
+        password_hash,
+        invitation,
+    )
+    .await?;
+    let user_id = user.id.clone();
+    let subject = TokenSubject::new(&user.id, user.security_stamp);
+
+    if !verified {
+        if let Err(err) = send_verification_email(&state, &user_id, &payload.email).await {
+            tracing::warn!("Failed to send the verification email of `{user_id}`: {err:?}");
+        }
+    }
+
+    let login = payload
+        .login
+        .filter(|_| verified || !env_config().require_email_verification);
+    let (bearer, refresh_token) = match login {
+        Some(LoginMode::Cookie) => {
+            let bearer = start_cookie_session(
+                &state,
+                cookies,
+                &subject,
+                CookieUser {
+                    user_id: user_id.to_string(),
+                },
+                &client,
+            )
+            .await?;
+            (Some(bearer), None)
+        }
+        Some(LoginMode::Bearer) => {
+            let (bearer, refresh_token) = start_bearer_session(
+                &state,
+                &subject,
+                BearerUser {
+                    user_id: user_id.to_string(),
+                },
+                &client,
+            )
+            .await?;
+            (Some(bearer), Some(refresh_token))
+        }
+        None => (None, None),
+    };
+
+    Ok(Json(RegisterResponse {
+        user_id: user_id.to_string(),
+        bearer,
+        refresh_token,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct DBExistingUser {
+    username_taken: bool,
+    email_taken: bool,
+}
+
+/// Create a user, the `unique_username` and `unique_email` indexes rejecting any duplicate.
+///
+/// With an invitation, the user is created verified and joins the organization in the same
+/// transaction, so an invitation that isn't pending anymore leaves no account behind.
+async fn create_user(
+    state: &RouterState,
+    username: &str,
+    email: &str,
+    password_hash: String,
+    invitation: Option<InvitationClaims>,
+) -> ApiResult<DBCreatedUser> {
+    let (accept, jti, org_id) = match invitation {
+        Some(claims) => {
+            let org_id = claims
+                .org
+                .parse::<Thing>()
+                .map_err(|_| BackendError::InvalidToken)?;
+            let accept = format!(
+                "{ACCEPT_INVITATION}
+                if $joined = none {{ throw \"The invitation isn't pending anymore\" }};"
+            );
+            (accept, Some(claims.jti), Some(org_id))
+        }
+        None => (String::new(), None, None),
+    };
+    let invited = jti.is_some();
+    let created: Option<DBCreatedUser> = match state
+        .db
+        .query(format!(
+            "begin transaction;
+            let $user_id = (create user set username=$username, email=$email, password_hash=$password_hash, verified=$verified return value id)[0];
+            {accept}
+            select id, security_stamp from $user_id;
+            commit transaction;"
+        ))
+        .bind(("username", username.to_string()))
+        .bind(("email", email.to_string()))
+        .bind(("password_hash", password_hash))
+        .bind(("verified", invited))
+        .bind(("jti", jti))
+        .bind(("org_id", org_id))
+        .await
+    {
+        Ok(mut result) => {
+            let last = result.num_statements() - 1;
+            result.take(last).ok().flatten()
+        }
+        Err(_) => None,
+    };
+
+    match created {
+        Some(user) => Ok(user),
+        None => {
+            let mut result = state
+                .db
+                .query("return { username_taken: count(select id from user where username=$username) > 0, email_taken: count(select id from user where email=$email) > 0 }")
+                .bind(("username", username.to_string()))
+                .bind(("email", email.to_string()))
+                .await
+                .map_err(|_| BackendError::SomethingWentWrong)?;
+            let existing: Option<DBExistingUser> = result
+                .take(0)
+                .map_err(|_| BackendError::SomethingWentWrong)?;
+            match existing {
+                Some(DBExistingUser {
+                    username_taken: true,
+                    ..
+                }) => Err(BackendError::UsernameTaken),
+                Some(DBExistingUser {
+                    email_taken: true, ..
+                }) => Err(BackendError::EmailTaken),
+                _ if invited => Err(BackendError::InvalidToken),
+                _ => Err(BackendError::SomethingWentWrong),
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{ApiResult, BackendError};
+
+const USERNAME_MIN_LENGTH: usize = 3;
+const USERNAME_MAX_LENGTH: usize = 32;
+const PASSWORD_MIN_LENGTH: usize = 8;
+const PASSWORD_MAX_LENGTH: usize = 128;
+const EMAIL_MAX_LENGTH: usize = 254;
+const NAME_MAX_LENGTH: usize = 64;
+const SLUG_MIN_LENGTH: usize = 3;
+const SLUG_MAX_LENGTH: usize = 32;
+const SCOPE_MAX_LENGTH: usize = 64;
+const SCOPES_MAX_COUNT: usize = 32;
+const API_KEY_MAX_DAYS: i64 = 365;
+
+/// Check that a username is 3 to 32 ASCII letters, digits, `_`, `-` or `.`.
+pub fn validate_username(username: &str) -> ApiResult<()> {
+    let length = username.chars().count();
+    if !(USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&length) {
+        return Err(BackendError::ValidationFailed(format!(
+            "The username must be between {USERNAME_MIN_LENGTH} and {USERNAME_MAX_LENGTH} characters"
+        )));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(BackendError::ValidationFailed(
+            "The username can only contain letters, digits, `_`, `-` and `.`".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a password follows the password policy.
+pub fn validate_password(password: &str) -> ApiResult<()> {
+    let length = password.chars().count();
+    if !(PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&length) {
+        return Err(BackendError::ValidationFailed(format!(
+            "The password must be between {PASSWORD_MIN_LENGTH} and {PASSWORD_MAX_LENGTH} characters"
+        )));
+    }
+    if password.trim().is_empty() {
+        return Err(BackendError::ValidationFailed(
+            "The password can't only contain whitespaces".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that an email looks like `local@domain.tld`, the real check being the verification email.
+pub fn validate_email(email: &str) -> ApiResult<()> {
+    let invalid = || BackendError::ValidationFailed("The email address is invalid".to_string());
+    if email.len() > EMAIL_MAX_LENGTH || email.chars().any(char::is_whitespace) {
+        return Err(invalid());
+    }
+    let (local, domain) = email.split_once('@').ok_or_else(invalid)?;
+    if local.is_empty()
+        || domain.contains('@')
+        || !domain.contains('.')
+        || domain.starts_with('.')
+        || domain.ends_with('.')
+    {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+/// Check that the name of an API key or an organization isn't blank nor longer than 64 characters.
+pub fn validate_name(name: &str) -> ApiResult<()> {
+    if name.trim().is_empty() || name.chars().count() > NAME_MAX_LENGTH {
+        return Err(BackendError::ValidationFailed(format!(
+            "The name must be between 1 and {NAME_MAX_LENGTH} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// Check that the slug of an organization is 3 to 32 lowercase letters, digits or `-`.
+pub fn validate_slug(slug: &str) -> ApiResult<()> {
+    if !(SLUG_MIN_LENGTH..=SLUG_MAX_LENGTH).contains(&slug.len())
+        || !slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(BackendError::ValidationFailed(format!(
+            "The slug must be {SLUG_MIN_LENGTH} to {SLUG_MAX_LENGTH} lowercase letters, digits or `-`"
+        )));
+    }
+    Ok(())
+}
+
+/// Check that scopes are at most 32 lowercase letters, digits, `:`, `.`, `_` or `-` runs.
+pub fn validate_scopes(scopes: &[String]) -> ApiResult<()> {
+    if scopes.len() > SCOPES_MAX_COUNT {
+        return Err(BackendError::ValidationFailed(format!(
+            "There can't be more than {SCOPES_MAX_COUNT} scopes"
+        )));
+    }
+    for scope in scopes {
+        if scope.is_empty()
+            || scope.len() > SCOPE_MAX_LENGTH
+            || !scope.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, ':' | '.' | '_' | '-')
+            })
+        {
+            return Err(BackendError::ValidationFailed(format!(
+                "The scope `{scope}` is invalid"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Check that an API key expires within 1 to 365 days.
+pub fn validate_api_key_expiry(expires_in_days: Option<i64>) -> ApiResult<()> {
+    match expires_in_days {
+        Some(days) if !(1..=API_KEY_MAX_DAYS).contains(&days) => {
+            Err(BackendError::ValidationFailed(format!(
+                "An API key must expire within 1 to {API_KEY_MAX_DAYS} days"
+            )))
+        }
+        _ => Ok(()),
+    }
+}
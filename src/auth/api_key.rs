@@ -0,0 +1,217 @@
+use super::bearer_jwt::{BearerJWTClaims, PrincipalKind};
+use super::token::{generate_token, hash_token};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use surrealdb::sql::Thing;
+
+/// The prefix of every API key, so secret scanners can recognize a leaked one.
+pub const API_KEY_PREFIX: &str = "aapi_";
+/// The header an API key can be sent with, instead of `Authorization: Bearer`.
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// How many characters of a key are kept to tell the keys of a user apart.
+const API_KEY_DISPLAY_LEN: usize = 12;
+
+/// An API key of a user, without its secret.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    /// The first characters of the key.
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBApiKeyInfo {
+    id: Thing,
+    name: String,
+    prefix: String,
+    scopes: Vec<String>,
+    created_at: String,
+    expires_at: Option<String>,
+    last_used_at: Option<String>,
+}
+
+impl From<DBApiKeyInfo> for ApiKeyInfo {
+    fn from(key: DBApiKeyInfo) -> Self {
+        Self {
+            id: key.id.to_string(),
+            name: key.name,
+            prefix: key.prefix,
+            scopes: key.scopes,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DBApiKey {
+    id: Thing,
+    user: Thing,
+    scopes: Vec<String>,
+    security_stamp: String,
+    /// The unix timestamp the key expires at.
+    expires_at: Option<i64>,
+}
+
+const API_KEY_INFO_FIELDS: &str = "id, name, prefix, scopes, <string> created_at as created_at, <option<string>> expires_at as expires_at, <option<string>> last_used_at as last_used_at";
+
+/// Create an API key for a user, returning the key to show it once along with its info.
+///
+/// The key is bound to the security stamp of the user, so rotating it revokes the key.
+pub async fn create_api_key(
+    state: &RouterState,
+    user_id: &Thing,
+    name: String,
+    scopes: Vec<String>,
+    expires_in_days: Option<i64>,
+) -> ApiResult<(String, ApiKeyInfo)> {
+    let key = format!("{API_KEY_PREFIX}{}", generate_token());
+    let expires_at = match expires_in_days {
+        Some(days) => format!("time::now() + {days}d"),
+        None => "none".to_string(),
+    };
+    let mut result = state
+        .db
+        .query(format!("create api_key set user=$user_id, name=$name, token_hash=$token_hash, prefix=$prefix, scopes=$scopes, security_stamp=$user_id.security_stamp, expires_at={expires_at} return {API_KEY_INFO_FIELDS}"))
+        .bind(("user_id", user_id.clone()))
+        .bind(("name", name))
+        .bind(("token_hash", hash_token(&key)))
+        .bind(("prefix", key[..API_KEY_DISPLAY_LEN].to_string()))
+        .bind(("scopes", scopes))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let info: Option<DBApiKeyInfo> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let info = info.ok_or(BackendError::SomethingWentWrong)?;
+    Ok((key, info.into()))
+}
+
+/// List the API keys of a user that can still be used, the most recent first.
+pub async fn list_api_keys(state: &RouterState, user_id: &Thing) -> ApiResult<Vec<ApiKeyInfo>> {
+    let mut result = state
+        .db
+        .query(format!("select {API_KEY_INFO_FIELDS} from api_key where user=$user_id and revoked_at=none and (expires_at=none or expires_at>time::now()) and security_stamp=$user_id.security_stamp order by created_at desc"))
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let keys: Vec<DBApiKeyInfo> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(keys.into_iter().map(ApiKeyInfo::from).collect())
+}
+
+/// Revoke an API key of a user, returning `NotFound` when it isn't one of its keys.
+pub async fn revoke_api_key(state: &RouterState, user_id: &Thing, key_id: &str) -> ApiResult<()> {
+    let key_id = key_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::NotFound)?;
+    if key_id.tb != "api_key" {
+        return Err(BackendError::NotFound);
+    }
+    let mut result = state
+        .db
+        .query("update $key_id set revoked_at=time::now() where user=$user_id and revoked_at=none return value id")
+        .bind(("key_id", key_id))
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let revoked: Vec<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if revoked.is_empty() {
+        Err(BackendError::NotFound)
+    } else {
+        Ok(())
+    }
+}
+
+/// Authenticate a request with an API key, recording when it was last used.
+///
+/// The key is turned into the claims a bearer token of its user would have, limited to its
+/// scopes and never outliving the key.
+pub async fn authenticate_api_key(state: &RouterState, key: &str) -> ApiResult<BearerJWTClaims> {
+    if !key.starts_with(API_KEY_PREFIX) {
+        return Err(BackendError::InvalidToken);
+    }
+    let mut result = state
+        .db
+        .query(
+            "select id, user, scopes, security_stamp, (if expires_at != none { time::unix(expires_at) }) as expires_at from api_key where token_hash=$token_hash and revoked_at=none and (expires_at=none or expires_at>time::now()) and security_stamp=user.security_stamp;
+            update api_key set last_used_at=time::now() where token_hash=$token_hash and revoked_at=none;",
+        )
+        .bind(("token_hash", hash_token(key)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let keys: Vec<DBApiKey> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let key = keys.into_iter().next().ok_or(BackendError::InvalidToken)?;
+
+    let now = Utc::now();
+    let exp = (now + Duration::minutes(env_config().jwt_access_ttl)).timestamp();
+    let exp = key.expires_at.map_or(exp, |expires_at| expires_at.min(exp));
+    let sub = key.user.to_string();
+    Ok(BearerJWTClaims {
+        data: json!({ "user_id": sub }).to_string(),
+        sub,
+        stamp: key.security_stamp,
+        jti: key.id.to_string(),
+        sid: None,
+        principal: PrincipalKind::User,
+        scope: Some(key.scopes.join(" ")),
+        org_id: None,
+        exp: exp as usize,
+        iat: now.timestamp() as usize,
+    })
+}
+
+/// An extractor accepting either a bearer token or an API key, sent with `X-Api-Key` or as a
+/// bearer token.
+///
+/// Both give the same claims, so a route can serve users and the scripts they made keys for.
+#[derive(Debug, Clone)]
+pub struct BearerOrApiKeyClaims(pub BearerJWTClaims);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for BearerOrApiKeyClaims
+where
+    RouterState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = BackendError;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let api_key = match parts.headers.get(API_KEY_HEADER) {
+            Some(header) => Some(header.to_str().map_err(|_| BackendError::InvalidToken)?),
+            None => parts
+                .headers
+                .get(AUTHORIZATION)
+                .and_then(|header| header.to_str().ok())
+                .and_then(|header| header.strip_prefix("Bearer "))
+                .filter(|token| token.starts_with(API_KEY_PREFIX)),
+        };
+        match api_key {
+            Some(api_key) => {
+                let state = RouterState::from_ref(state);
+                authenticate_api_key(&state, api_key.trim())
+                    .await
+                    .map(BearerOrApiKeyClaims)
+            }
+            None => BearerJWTClaims::from_request_parts(parts, state)
+                .await
+                .map(BearerOrApiKeyClaims),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use super::client_info::ClientInfo;
+use super::principal::Principal;
+use crate::{ApiResult, BackendError, RouterState};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::method::Query;
+use surrealdb::sql::Thing;
+
+/// Who makes an admin write, and from where.
+#[derive(Debug, Clone)]
+pub struct Actor {
+    pub id: Thing,
+    pub client: ClientInfo,
+}
+
+impl Actor {
+    pub fn new(principal: &Principal, client: ClientInfo) -> ApiResult<Self> {
+        let id = principal
+            .sub
+            .parse::<Thing>()
+            .map_err(|_| BackendError::InvalidToken)?;
+        Ok(Self { id, client })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: String,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub details: Option<Value>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBAuditEntry {
+    id: Thing,
+    actor: Thing,
+    action: String,
+    target: Thing,
+    details: Option<Value>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    created_at: String,
+}
+
+/// Run the statements of a write along with its `audit_log` entry, in a single transaction, so
+/// no write is left out of the trail.
+///
+/// The details must never hold a secret, like a password.
+pub async fn audited<'r>(
+    state: &'r RouterState,
+    actor: &Actor,
+    action: &str,
+    target: &Thing,
+    details: Option<Value>,
+    statements: &str,
+    bind: impl FnOnce(Query<'r, Client>) -> Query<'r, Client>,
+) -> ApiResult<()> {
+    let query = state.db.query(format!(
+        "begin transaction;
+        {statements};
+        create audit_log set actor=$audit_actor, action=$audit_action, target=$audit_target, details=$audit_details, ip=$audit_ip, user_agent=$audit_user_agent;
+        commit transaction;"
+    ));
+    bind(query)
+        .bind(("audit_actor", actor.id.clone()))
+        .bind(("audit_action", action.to_string()))
+        .bind(("audit_target", target.clone()))
+        .bind(("audit_details", details))
+        .bind(("audit_ip", actor.client.ip.clone()))
+        .bind(("audit_user_agent", actor.client.user_agent.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(())
+}
+
+/// List the audit trail, optionally of a single target, the most recent first.
+pub async fn list_audit_log(
+    state: &RouterState,
+    target: Option<Thing>,
+    start: usize,
+    limit: usize,
+) -> ApiResult<Vec<AuditEntry>> {
+    let mut result = state
+        .db
+        .query("select id, actor, action, target, details, ip, user_agent, <string> created_at as created_at from audit_log where $target=none or target=$target order by created_at desc limit $limit start $start")
+        .bind(("target", target))
+        .bind(("limit", limit))
+        .bind(("start", start))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let entries: Vec<DBAuditEntry> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| AuditEntry {
+            id: entry.id.to_string(),
+            actor: entry.actor.to_string(),
+            action: entry.action,
+            target: entry.target.to_string(),
+            details: entry.details,
+            ip: entry.ip,
+            user_agent: entry.user_agent,
+            created_at: entry.created_at,
+        })
+        .collect())
+}
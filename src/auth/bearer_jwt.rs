@@ -1,5 +1,7 @@
-use crate::{env_config, ApiResult, BackendError};
-use axum::extract::FromRequestParts;
+use super::security_stamp::{check_security_stamp, TokenSubject};
+use super::session::touch_session;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::extract::{FromRef, FromRequestParts};
 use axum::http::request::Parts;
 use axum::{async_trait, RequestPartsExt};
 use axum_extra::headers::authorization::Bearer;
@@ -9,43 +11,113 @@ use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, Header, Validation};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Who a bearer token was issued to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrincipalKind {
+    #[default]
+    User,
+    /// A service account, authenticated with the `client_credentials` grant.
+    Service,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// An extractor for Bearer token.
 pub struct BearerJWTClaims {
     pub data: String,
+    pub sub: String,
+    pub stamp: String,
+    pub jti: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(default)]
+    pub principal: PrincipalKind,
+    /// The scopes granted to a service account, separated by spaces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// The organization the token acts in, chosen at `/orgs/switch`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
     pub exp: usize,
     pub iat: usize,
 }
 
+impl BearerJWTClaims {
+    /// Whether the token was issued to a service account rather than a user.
+    pub fn is_service(&self) -> bool {
+        self.principal == PrincipalKind::Service
+    }
+}
+
 #[async_trait]
-impl<S: Send + Sync> FromRequestParts<S> for BearerJWTClaims {
+impl<S> FromRequestParts<S> for BearerJWTClaims
+where
+    RouterState: FromRef<S>,
+    S: Send + Sync,
+{
     type Rejection = BackendError;
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
             .map_err(|_| BackendError::InvalidToken)?;
         let token = decode::<BearerJWTClaims>(
             bearer.token(),
-            &&env_config().jwt_decode,
+            &env_config().jwt_decode,
             &Validation::default(),
         )
         .map_err(|_| BackendError::InvalidToken)?;
         let now = Utc::now();
         if token.claims.exp < now.timestamp() as usize {
-            Err(BackendError::InvalidToken)
-        } else {
-            Ok(token.claims)
+            return Err(BackendError::InvalidToken);
+        }
+        let state = RouterState::from_ref(state);
+        if state.revocations.is_revoked(&token.claims.jti) {
+            return Err(BackendError::InvalidToken);
         }
+        check_security_stamp(&state, &token.claims.sub, &token.claims.stamp).await?;
+        if let Some(sid) = &token.claims.sid {
+            touch_session(&state, sid).await?;
+        }
+        Ok(token.claims)
     }
 }
 
-/// Encode a required JWT Bearer token.
-pub fn encode_required_jwt_bearer_claims<T: Serialize>(data: T) -> ApiResult<String> {
+/// Encode a required JWT Bearer token, living for `JWT_ACCESS_TTL` minutes.
+pub fn encode_required_jwt_bearer_claims<T: Serialize>(
+    subject: &TokenSubject,
+    data: T,
+) -> ApiResult<String> {
+    encode_bearer_claims(subject, data, PrincipalKind::User, None)
+}
+
+/// Encode the JWT Bearer token of a service account, limited to its granted scopes.
+pub fn encode_service_jwt_bearer_claims<T: Serialize>(
+    subject: &TokenSubject,
+    data: T,
+    scope: String,
+) -> ApiResult<String> {
+    encode_bearer_claims(subject, data, PrincipalKind::Service, Some(scope))
+}
+
+fn encode_bearer_claims<T: Serialize>(
+    subject: &TokenSubject,
+    data: T,
+    principal: PrincipalKind,
+    scope: Option<String>,
+) -> ApiResult<String> {
     let now = Utc::now();
-    let expire = Duration::hours(24);
+    let expire = Duration::minutes(env_config().jwt_access_ttl);
     let claim = BearerJWTClaims {
+        sub: subject.user_id.clone(),
+        stamp: subject.security_stamp.clone(),
+        jti: Uuid::new_v4().to_string(),
+        sid: subject.session_id.clone(),
+        principal,
+        scope,
+        org_id: subject.org_id.clone(),
         iat: now.timestamp() as usize,
         exp: (now + expire).timestamp() as usize,
         data: serde_json::to_string(&data).map_err(|_| BackendError::SomethingWentWrong)?,
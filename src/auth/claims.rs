@@ -0,0 +1,58 @@
+use super::bearer_jwt::BearerJWTClaims;
+use super::cookie_jwt::CookieJWTClaims;
+use crate::{BackendError, RouterState};
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+
+/// An extractor accepting either a bearer token or a jwt cookie of a user.
+///
+/// The `Authorization` header takes precedence, the cookie is only looked at without it, which
+/// requires the `cookie_jwt_bearer_resolver` layer. Service account tokens are refused, as these
+/// routes act on the user the token was issued for.
+#[derive(Debug, Clone)]
+pub enum AnyJWTClaims {
+    Bearer(BearerJWTClaims),
+    Cookie(CookieJWTClaims),
+}
+
+impl AnyJWTClaims {
+    /// The id of the user the token was issued for.
+    pub fn sub(&self) -> &str {
+        match self {
+            AnyJWTClaims::Bearer(claims) => &claims.sub,
+            AnyJWTClaims::Cookie(claims) => &claims.sub,
+        }
+    }
+
+    /// The id of the session the token belongs to.
+    pub fn sid(&self) -> Option<&str> {
+        match self {
+            AnyJWTClaims::Bearer(claims) => claims.sid.as_deref(),
+            AnyJWTClaims::Cookie(claims) => claims.sid.as_deref(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AnyJWTClaims
+where
+    RouterState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = BackendError;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if parts.headers.contains_key(AUTHORIZATION) {
+            let claims = BearerJWTClaims::from_request_parts(parts, state).await?;
+            if claims.is_service() {
+                return Err(BackendError::InvalidToken);
+            }
+            Ok(AnyJWTClaims::Bearer(claims))
+        } else {
+            CookieJWTClaims::from_request_parts(parts, state)
+                .await
+                .map(AnyJWTClaims::Cookie)
+        }
+    }
+}
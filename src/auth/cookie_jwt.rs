@@ -1,7 +1,11 @@
-use crate::{env_config, ApiResult, BackendError};
+use super::cookie_session::resolve_cookie_session;
+use super::security_stamp::{check_security_stamp, TokenSubject};
+use super::session::touch_session;
+use crate::config::CookieMode;
+use crate::{env_config, ApiResult, BackendError, RouterState};
 use axum::async_trait;
 use axum::body::Body;
-use axum::extract::{FromRequestParts, Request};
+use axum::extract::{FromRequestParts, Request, State};
 use axum::http::request::Parts;
 use axum::middleware::Next;
 use axum::response::Response;
@@ -12,11 +16,22 @@ use serde::{Deserialize, Serialize};
 use std::ops::Add;
 use tower_cookies::cookie::time::OffsetDateTime;
 use tower_cookies::{cookie, Cookie, Cookies};
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Contain the data for a user cookie claims.
+///
+/// With `COOKIE_MODE=session`, the same claims are rebuilt from the server-side session.
 pub struct CookieJWTClaims {
     pub data: String,
+    pub sub: String,
+    pub stamp: String,
+    pub jti: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// The organization the cookie acts in, chosen at `/orgs/switch`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
     pub exp: usize,
     pub iat: usize,
 }
@@ -36,8 +51,9 @@ pub async fn cookie_jwt_bearer_auth(
     Ok(next.run(req).await)
 }
 
-/// Resolve the jwt layer to validate the jwt cookie token.
+/// Resolve the jwt layer to validate the jwt cookie token, or the session id in session mode.
 pub async fn cookie_jwt_bearer_resolver(
+    State(state): State<RouterState>,
     cookies: Cookies,
     mut req: Request<Body>,
     next: Next,
@@ -46,17 +62,9 @@ pub async fn cookie_jwt_bearer_resolver(
         .get(&env_config().jwt_cookie_name)
         .map(|c| c.value().to_string());
     let compute_auth: ApiResult<CookieJWTClaims> = if let Some(token) = auth_token {
-        let token = decode::<CookieJWTClaims>(
-            token.as_str(),
-            &env_config().jwt_decode,
-            &Validation::default(),
-        )
-        .map_err(|_| BackendError::InvalidToken)?;
-        let now = Utc::now();
-        if token.claims.exp < now.timestamp() as usize {
-            Err(BackendError::InvalidToken)
-        } else {
-            Ok(token.claims)
+        match env_config().cookie_mode {
+            CookieMode::Jwt => resolve_cookie_jwt_claims(&state, token.as_str()).await,
+            CookieMode::Session => resolve_cookie_session(&state, token.as_str()).await,
         }
     } else {
         Err(BackendError::NoCookieFound)
@@ -65,6 +73,23 @@ pub async fn cookie_jwt_bearer_resolver(
     Ok(next.run(req).await)
 }
 
+async fn resolve_cookie_jwt_claims(state: &RouterState, token: &str) -> ApiResult<CookieJWTClaims> {
+    let token = decode::<CookieJWTClaims>(token, &env_config().jwt_decode, &Validation::default())
+        .map_err(|_| BackendError::InvalidToken)?;
+    let now = Utc::now();
+    if token.claims.exp < now.timestamp() as usize {
+        return Err(BackendError::InvalidToken);
+    }
+    if state.revocations.is_revoked(&token.claims.jti) {
+        return Err(BackendError::InvalidToken);
+    }
+    check_security_stamp(state, &token.claims.sub, &token.claims.stamp).await?;
+    if let Some(sid) = &token.claims.sid {
+        touch_session(state, sid).await?;
+    }
+    Ok(token.claims)
+}
+
 #[async_trait]
 impl<S: Send + Sync> FromRequestParts<S> for CookieJWTClaims {
     type Rejection = BackendError;
@@ -80,12 +105,17 @@ impl<S: Send + Sync> FromRequestParts<S> for CookieJWTClaims {
 /// Encode a required data in the cookie jwt claims then returns the content of the jwt bearer.
 pub fn encode_cookie_jwt_bearer_claims<T: Serialize>(
     cookies: Cookies,
+    subject: &TokenSubject,
     data: T,
 ) -> ApiResult<String> {
     let now = Utc::now();
-    let now_cookie = OffsetDateTime::now_utc();
     let expire = Duration::hours(24);
     let claim = CookieJWTClaims {
+        sub: subject.user_id.clone(),
+        stamp: subject.security_stamp.clone(),
+        jti: Uuid::new_v4().to_string(),
+        sid: subject.session_id.clone(),
+        org_id: subject.org_id.clone(),
         iat: now.timestamp() as usize,
         exp: (now + expire).timestamp() as usize,
         data: serde_json::to_string(&data).map_err(|_| BackendError::SomethingWentWrong)?,
@@ -93,13 +123,19 @@ pub fn encode_cookie_jwt_bearer_claims<T: Serialize>(
     let encoded_jwt = encode(&Header::default(), &claim, &env_config().jwt_encode)
         .map_err(|_| BackendError::JWTEncodingFailed)?;
 
-    let mut cookie = Cookie::new(&env_config().jwt_cookie_name, encoded_jwt.clone());
+    add_auth_cookie(&cookies, encoded_jwt.clone());
+
+    Ok(encoded_jwt)
+}
+
+/// Set the authentication cookie for the next 24 hours.
+pub(super) fn add_auth_cookie(cookies: &Cookies, value: String) {
+    let now_cookie = OffsetDateTime::now_utc();
+    let mut cookie = Cookie::new(&env_config().jwt_cookie_name, value);
     cookie.set_http_only(true);
     cookie.set_path("/");
     cookie.set_expires(now_cookie.add(cookie::time::Duration::hours(24)));
     cookies.add(cookie);
-
-    Ok(encoded_jwt)
 }
 
 /// Remove a cookie from a jwt bearer
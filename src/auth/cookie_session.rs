@@ -0,0 +1,155 @@
+use super::client_info::ClientInfo;
+use super::cookie_jwt::{
+    add_auth_cookie, encode_cookie_jwt_bearer_claims, remove_cookie_jwt_bearer_claims,
+    CookieJWTClaims,
+};
+use super::security_stamp::{check_security_stamp, TokenSubject};
+use super::session::{open_session, resume_session, revoke_session, ServerSideToken, SessionKind};
+use super::token::{generate_token, hash_token};
+use crate::config::CookieMode;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+use tower_cookies::Cookies;
+
+#[derive(Debug, Deserialize)]
+struct DBSession {
+    id: Thing,
+    user: Thing,
+    organization: Option<Thing>,
+    security_stamp: String,
+    data: String,
+    iat: usize,
+    exp: usize,
+}
+
+/// Log a user in with a cookie, holding either a signed jwt or a server-side session id
+/// depending on `COOKIE_MODE`. Returns the value of the cookie.
+///
+/// A jwt keeps the session of the subject if it has one, a new session being recorded otherwise.
+pub async fn start_cookie_session<T: Serialize>(
+    state: &RouterState,
+    cookies: Cookies,
+    subject: &TokenSubject,
+    data: T,
+    client: &ClientInfo,
+) -> ApiResult<String> {
+    match env_config().cookie_mode {
+        CookieMode::Jwt => {
+            let subject = match subject.session_id {
+                Some(_) => {
+                    resume_session(state, subject).await?;
+                    subject.clone()
+                }
+                None => open_session(state, subject, SessionKind::Cookie, client, None).await?,
+            };
+            encode_cookie_jwt_bearer_claims(cookies, &subject, data)
+        }
+        CookieMode::Session => {
+            let token = create_session(state, subject, data, client).await?;
+            add_auth_cookie(&cookies, token.clone());
+            Ok(token)
+        }
+    }
+}
+
+/// Log a user out, ending its server-side session or revoking its jwt.
+pub async fn end_cookie_session(
+    state: &RouterState,
+    cookies: Cookies,
+    claims: Option<&CookieJWTClaims>,
+) -> ApiResult<()> {
+    let token = cookies
+        .get(&env_config().jwt_cookie_name)
+        .map(|c| c.value().to_string());
+    match (env_config().cookie_mode, token, claims) {
+        (CookieMode::Session, Some(token), _) => end_session(state, &token).await?,
+        (CookieMode::Jwt, _, Some(claims)) => {
+            state
+                .revocations
+                .revoke(&state.db, &claims.jti, claims.exp)
+                .await?;
+            if let Some(sid) = &claims.sid {
+                revoke_session(state, &claims.sub, sid)
+                    .await
+                    .or_else(|err| match err {
+                        BackendError::NotFound => Ok(()),
+                        err => Err(err),
+                    })?;
+            }
+        }
+        _ => {}
+    }
+    remove_cookie_jwt_bearer_claims(cookies);
+    Ok(())
+}
+
+async fn end_session(state: &RouterState, token: &str) -> ApiResult<()> {
+    state
+        .db
+        .query("update session set revoked_at=time::now() where token_hash=$token_hash and revoked_at=none")
+        .bind(("token_hash", hash_token(token)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(())
+}
+
+async fn create_session<T: Serialize>(
+    state: &RouterState,
+    subject: &TokenSubject,
+    data: T,
+    client: &ClientInfo,
+) -> ApiResult<String> {
+    let data = serde_json::to_string(&data).map_err(|_| BackendError::SomethingWentWrong)?;
+    let token = generate_token();
+    let server_side = ServerSideToken {
+        token_hash: hash_token(&token),
+        data,
+    };
+    open_session(
+        state,
+        subject,
+        SessionKind::Cookie,
+        client,
+        Some(server_side),
+    )
+    .await?;
+    Ok(token)
+}
+
+/// Rebuild the cookie claims from an active session, refreshing its last seen time.
+pub(crate) async fn resolve_cookie_session(
+    state: &RouterState,
+    token: &str,
+) -> ApiResult<CookieJWTClaims> {
+    let mut result = state
+        .db
+        .query(
+            "let $session = (select id, user, (select value out from membership where in=$parent.user and out=$parent.organization)[0] as organization, security_stamp, data, time::unix(created_at) as iat, time::unix(expires_at) as exp from session where token_hash=$token_hash and revoked_at=none and expires_at>time::now())[0];
+            if $session != none { update $session.id set last_seen_at=time::now() };
+            return $session;",
+        )
+        .bind(("token_hash", hash_token(token)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let last = result.num_statements() - 1;
+    let session: Option<DBSession> = result
+        .take(last)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let session = session.ok_or(BackendError::InvalidToken)?;
+
+    let sub = session.user.to_string();
+    check_security_stamp(state, &sub, &session.security_stamp).await?;
+    Ok(CookieJWTClaims {
+        data: session.data,
+        sub,
+        stamp: session.security_stamp,
+        jti: session.id.to_string(),
+        sid: Some(session.id.to_string()),
+        org_id: session.organization.map(|org_id| org_id.to_string()),
+        exp: session.exp,
+        iat: session.iat,
+    })
+}
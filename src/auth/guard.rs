@@ -0,0 +1,86 @@
+use crate::{ApiResult, RouterState};
+use axum::async_trait;
+use axum::body::Body;
+use axum::http::request::Parts;
+use axum::http::Request;
+use axum::response::{IntoResponse, Response};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// A check a request must pass before reaching a route, like `RequirePermission`.
+#[async_trait]
+pub trait Guard: Clone + Send + Sync + 'static {
+    async fn check(&self, state: &RouterState, parts: &mut Parts) -> ApiResult<()>;
+}
+
+/// A layer answering with the error of its guard when a request doesn't pass it.
+///
+/// ```ignore
+/// .route("/bearer/admin", get(handler).route_layer(GuardLayer::new(&state, RequirePermission("users:read"))))
+/// ```
+#[derive(Clone)]
+pub struct GuardLayer<G> {
+    state: RouterState,
+    guard: G,
+}
+
+impl<G: Guard> GuardLayer<G> {
+    pub fn new(state: &RouterState, guard: G) -> Self {
+        Self {
+            state: state.clone(),
+            guard,
+        }
+    }
+}
+
+impl<S, G: Guard> Layer<S> for GuardLayer<G> {
+    type Service = GuardService<S, G>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GuardService {
+            inner,
+            state: self.state.clone(),
+            guard: self.guard.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GuardService<S, G> {
+    inner: S,
+    state: RouterState,
+    guard: G,
+}
+
+impl<S, G> Service<Request<Body>> for GuardService<S, G>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    G: Guard,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // The clone isn't ready, keep the service that was polled.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let state = self.state.clone();
+        let guard = self.guard.clone();
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            if let Err(err) = guard.check(&state, &mut parts).await {
+                return Ok(err.into_response());
+            }
+            inner.call(Request::from_parts(parts, body)).await
+        })
+    }
+}
@@ -0,0 +1,274 @@
+use super::organization::{membership_role, OrgRole};
+use super::token::decode_audience_token;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{encode, Header};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+use uuid::Uuid;
+
+const INVITATION_TTL_DAYS: i64 = 7;
+const INVITATION_AUDIENCE: &str = "invitation";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitationClaims {
+    pub jti: String,
+    /// The organization the invitation is for.
+    pub org: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitationInfo {
+    pub id: String,
+    pub email: String,
+    pub role: OrgRole,
+    pub invited_by: String,
+    pub created_at: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBInvitation {
+    id: Thing,
+    email: String,
+    role: OrgRole,
+    invited_by: Thing,
+    created_at: String,
+    expires_at: String,
+}
+
+impl From<DBInvitation> for InvitationInfo {
+    fn from(invitation: DBInvitation) -> Self {
+        Self {
+            id: invitation.id.to_string(),
+            email: invitation.email,
+            role: invitation.role,
+            invited_by: invitation.invited_by.to_string(),
+            created_at: invitation.created_at,
+            expires_at: invitation.expires_at,
+        }
+    }
+}
+
+/// What the link of a pending invitation shows before it is accepted.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitationPreview {
+    pub org_id: String,
+    pub organization: String,
+    pub email: String,
+    pub role: OrgRole,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBInvitationPreview {
+    org_id: Thing,
+    org_name: String,
+    email: String,
+    role: OrgRole,
+    expires_at: String,
+}
+
+/// Invite an email to join an organization with a role, replacing its pending invitations.
+///
+/// Returns the invitation and its signed token, living `INVITATION_TTL_DAYS` days, or
+/// `AlreadyMember` when a member already uses the email.
+pub async fn issue_invitation(
+    state: &RouterState,
+    org_id: &Thing,
+    invited_by: &Thing,
+    email: String,
+    role: OrgRole,
+) -> ApiResult<(InvitationInfo, String)> {
+    let mut result = state
+        .db
+        .query("count(select id from membership where out=$org_id and in.email=$email) > 0")
+        .bind(("org_id", org_id.clone()))
+        .bind(("email", email.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let member: Option<bool> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if member.unwrap_or_default() {
+        return Err(BackendError::AlreadyMember);
+    }
+
+    let jti = Uuid::new_v4().to_string();
+    let mut result = state
+        .db
+        .query(format!(
+            "begin transaction;
+            update invitation set revoked_at=time::now() where organization=$org_id and email=$email and accepted_at=none and revoked_at=none;
+            create invitation set jti=$jti, organization=$org_id, email=$email, role=$role, invited_by=$invited_by, expires_at=time::now() + {INVITATION_TTL_DAYS}d return id, email, role, invited_by, <string> created_at as created_at, <string> expires_at as expires_at;
+            commit transaction;"
+        ))
+        .bind(("jti", jti.clone()))
+        .bind(("org_id", org_id.clone()))
+        .bind(("email", email))
+        .bind(("role", role))
+        .bind(("invited_by", invited_by.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let last = result.num_statements() - 1;
+    let invitation: Option<DBInvitation> = result
+        .take(last)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let invitation = invitation.ok_or(BackendError::SomethingWentWrong)?;
+
+    let now = Utc::now();
+    let claims = InvitationClaims {
+        jti,
+        org: org_id.to_string(),
+        aud: INVITATION_AUDIENCE.to_string(),
+        iat: now.timestamp() as usize,
+        exp: (now + Duration::days(INVITATION_TTL_DAYS)).timestamp() as usize,
+    };
+    let token = encode(&Header::default(), &claims, &env_config().jwt_encode)
+        .map_err(|_| BackendError::JWTEncodingFailed)?;
+    Ok((invitation.into(), token))
+}
+
+/// List the pending invitations of an organization, the most recent first.
+pub async fn list_invitations(
+    state: &RouterState,
+    org_id: &Thing,
+) -> ApiResult<Vec<InvitationInfo>> {
+    let mut result = state
+        .db
+        .query("select id, email, role, invited_by, <string> created_at as created_at, <string> expires_at as expires_at from invitation where organization=$org_id and accepted_at=none and revoked_at=none and expires_at>time::now() order by created_at desc")
+        .bind(("org_id", org_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let invitations: Vec<DBInvitation> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(invitations.into_iter().map(Into::into).collect())
+}
+
+/// Revoke a pending invitation of an organization.
+///
+/// Returns `NotFound` when the invitation isn't a pending invitation of this organization.
+pub async fn revoke_invitation(
+    state: &RouterState,
+    org_id: &Thing,
+    invitation_id: &str,
+) -> ApiResult<()> {
+    let invitation_id = invitation_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::NotFound)?;
+    if invitation_id.tb != "invitation" {
+        return Err(BackendError::NotFound);
+    }
+    let mut result = state
+        .db
+        .query("update $invitation_id set revoked_at=time::now() where organization=$org_id and accepted_at=none and revoked_at=none return value id")
+        .bind(("invitation_id", invitation_id))
+        .bind(("org_id", org_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let revoked: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    revoked.map(|_| ()).ok_or(BackendError::NotFound)
+}
+
+/// Decode the token of an invitation link, without checking that it is still pending.
+pub fn decode_invitation(token: &str) -> ApiResult<InvitationClaims> {
+    decode_audience_token(token, INVITATION_AUDIENCE)
+}
+
+/// The statements accepting the pending invitation `$jti` of `$org_id` for `$user_id`, as long
+/// as it was sent to `$email`, whatever the case. They leave the organization joined in
+/// `$joined`, `NONE` when the invitation isn't pending or was sent to another email.
+///
+/// To run within a transaction, so the invitation is never flagged without the membership.
+pub const ACCEPT_INVITATION: &str = "let $pending = (select id, email, role from invitation where jti=$jti and organization=$org_id and accepted_at=none and revoked_at=none and expires_at>time::now())[0];
+    let $email_matches = $pending != none and $email != none and string::lowercase($pending.email) = string::lowercase($email);
+    let $accepted = (if $email_matches { (update $pending.id set accepted_at=time::now(), accepted_by=$user_id where accepted_at=none return value id)[0] });
+    let $joined = (if $accepted != none { (relate $user_id->membership->$org_id set role=$pending.role return value out)[0] });";
+
+#[derive(Debug, Deserialize)]
+struct DBAcceptance {
+    pending: bool,
+    joined: Option<Thing>,
+}
+
+/// Show the invitation of a token, as long as it is still pending.
+pub async fn preview_invitation(state: &RouterState, token: &str) -> ApiResult<InvitationPreview> {
+    let claims = decode_invitation(token)?;
+    let org_id = claims
+        .org
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let mut result = state
+        .db
+        .query("select organization as org_id, organization.name as org_name, email, role, <string> expires_at as expires_at from invitation where jti=$jti and organization=$org_id and accepted_at=none and revoked_at=none and expires_at>time::now()")
+        .bind(("jti", claims.jti))
+        .bind(("org_id", org_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let invitation: Option<DBInvitationPreview> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let invitation = invitation.ok_or(BackendError::InvalidToken)?;
+    Ok(InvitationPreview {
+        org_id: invitation.org_id.to_string(),
+        organization: invitation.org_name,
+        email: invitation.email,
+        role: invitation.role,
+        expires_at: invitation.expires_at,
+    })
+}
+
+/// Accept an invitation for a user, flagging it as accepted and creating the membership in a
+/// single transaction. Returns the organization the user joined.
+///
+/// The invitation must have been sent to the verified email of the user, so a forwarded or
+/// leaked link can't be used by another account: `Forbidden` otherwise. Returns `AlreadyMember`
+/// when the user is already a member, the invitation staying pending.
+pub async fn accept_invitation(
+    state: &RouterState,
+    token: &str,
+    user_id: &Thing,
+) -> ApiResult<Thing> {
+    let claims = decode_invitation(token)?;
+    let org_id = claims
+        .org
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    if membership_role(state, user_id, &org_id).await?.is_some() {
+        return Err(BackendError::AlreadyMember);
+    }
+
+    let mut result = state
+        .db
+        .query(format!(
+            "begin transaction;
+            let $email = (select value email from $user_id where verified=true)[0];
+            {ACCEPT_INVITATION}
+            let $acceptance = {{ pending: $pending != none, joined: $joined }};
+            $acceptance;
+            commit transaction;"
+        ))
+        .bind(("jti", claims.jti))
+        .bind(("org_id", org_id))
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let last = result.num_statements() - 1;
+    let acceptance: Option<DBAcceptance> = result
+        .take(last)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    match acceptance {
+        Some(DBAcceptance {
+            joined: Some(org_id),
+            ..
+        }) => Ok(org_id),
+        Some(DBAcceptance { pending: true, .. }) => Err(BackendError::Forbidden),
+        _ => Err(BackendError::InvalidToken),
+    }
+}
@@ -0,0 +1,142 @@
+use super::security_stamp::{load_token_subject, TokenSubject};
+use super::session::SessionKind;
+use super::token::{decode_audience_token, generate_token, hash_token};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{encode, Header};
+use serde::{Deserialize, Serialize};
+use std::ops::Add;
+use subtle::ConstantTimeEq;
+use surrealdb::sql::Thing;
+use tower_cookies::cookie::time::OffsetDateTime;
+use tower_cookies::cookie::SameSite;
+use tower_cookies::{cookie, Cookie, Cookies};
+use uuid::Uuid;
+
+const MAGIC_LINK_TTL_MINUTES: i64 = 15;
+const MAGIC_LINK_AUDIENCE: &str = "magic-link";
+const MAGIC_LINK_NONCE_COOKIE: &str = "magic_link_nonce";
+const MAGIC_LINK_COOKIE_PATH: &str = "/api/login/magic-link";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MagicLinkClaims {
+    pub sub: String,
+    pub stamp: String,
+    pub jti: String,
+    /// The hash of the nonce cookie of the browser that asked for the link.
+    pub nonce: String,
+    /// How the user logs in once the link is opened.
+    pub kind: SessionKind,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+}
+
+/// Bind the magic link about to be sent to this browser, with a random nonce kept in a cookie.
+///
+/// Returns the hash of the nonce, to put in the link.
+pub fn add_magic_link_nonce(cookies: &Cookies) -> String {
+    let nonce = generate_token();
+    let mut cookie = Cookie::new(MAGIC_LINK_NONCE_COOKIE, nonce.clone());
+    cookie.set_http_only(true);
+    // Lax, so the cookie is sent when the link is opened from a mail client.
+    cookie.set_same_site(SameSite::Lax);
+    cookie.set_path(MAGIC_LINK_COOKIE_PATH);
+    cookie.set_expires(
+        OffsetDateTime::now_utc().add(cookie::time::Duration::minutes(MAGIC_LINK_TTL_MINUTES)),
+    );
+    cookies.add(cookie);
+    hash_token(&nonce)
+}
+
+fn remove_magic_link_nonce(cookies: &Cookies) {
+    let mut cookie = Cookie::new(MAGIC_LINK_NONCE_COOKIE, "");
+    cookie.set_http_only(true);
+    cookie.set_path(MAGIC_LINK_COOKIE_PATH);
+    cookie.set_expires(OffsetDateTime::now_utc());
+    cookies.add(cookie);
+}
+
+/// Sign a single-use magic link token, living `MAGIC_LINK_TTL_MINUTES` minutes.
+pub async fn issue_magic_link(
+    state: &RouterState,
+    subject: &TokenSubject,
+    kind: SessionKind,
+    nonce_hash: String,
+) -> ApiResult<String> {
+    let user_id = subject
+        .user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let jti = Uuid::new_v4().to_string();
+    state
+        .db
+        .query(format!(
+            "create magic_link set jti=$jti, user=$user_id, expires_at=time::now() + {MAGIC_LINK_TTL_MINUTES}m"
+        ))
+        .bind(("jti", jti.clone()))
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+
+    let now = Utc::now();
+    let claims = MagicLinkClaims {
+        sub: subject.user_id.clone(),
+        stamp: subject.security_stamp.clone(),
+        jti,
+        nonce: nonce_hash,
+        kind,
+        aud: MAGIC_LINK_AUDIENCE.to_string(),
+        iat: now.timestamp() as usize,
+        exp: (now + Duration::minutes(MAGIC_LINK_TTL_MINUTES)).timestamp() as usize,
+    };
+    encode(&Header::default(), &claims, &env_config().jwt_encode)
+        .map_err(|_| BackendError::JWTEncodingFailed)
+}
+
+/// Consume a magic link opened in the browser that asked for it, returning the subject to log
+/// in and how.
+///
+/// Opening the link proves the user owns its email, which is marked as verified.
+pub async fn consume_magic_link(
+    state: &RouterState,
+    cookies: &Cookies,
+    token: &str,
+) -> ApiResult<(TokenSubject, SessionKind)> {
+    let claims: MagicLinkClaims = decode_audience_token(token, MAGIC_LINK_AUDIENCE)?;
+    let nonce = cookies
+        .get(MAGIC_LINK_NONCE_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .ok_or(BackendError::NoCookieFound)?;
+    if !bool::from(hash_token(&nonce).as_bytes().ct_eq(claims.nonce.as_bytes())) {
+        return Err(BackendError::InvalidToken);
+    }
+
+    let mut result = state
+        .db
+        .query("(update magic_link set used_at=time::now() where jti=$jti and used_at=none and expires_at>time::now() return value user)[0]")
+        .bind(("jti", claims.jti.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user_id: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user_id = user_id.ok_or(BackendError::InvalidToken)?;
+    remove_magic_link_nonce(cookies);
+
+    let subject = load_token_subject(state, &user_id).await?;
+    if user_id.to_string() != claims.sub || subject.security_stamp != claims.stamp {
+        return Err(BackendError::InvalidToken);
+    }
+    state
+        .db
+        .query("update $user_id set verified=true")
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok((subject, claims.kind))
+}
@@ -0,0 +1,110 @@
+use super::security_stamp::{load_token_subject, TokenSubject};
+use super::session::SessionKind;
+use super::token::{generate_token, hash_token};
+use crate::{ApiResult, BackendError, RouterState};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+
+const MFA_CHALLENGE_TTL: &str = "5m";
+/// How many second factors can be tried with a single `mfa_pending` token.
+const MFA_MAX_ATTEMPTS: i64 = 5;
+
+/// A second factor a user can log in with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MfaMethod {
+    Totp,
+    Passkey,
+}
+
+/// A login that passed the password check and waits for its second factor.
+#[derive(Debug, Deserialize)]
+pub struct MfaChallenge {
+    pub id: Thing,
+    pub user: Thing,
+    /// How the user logs in once the second factor is checked.
+    pub kind: SessionKind,
+    security_stamp: String,
+}
+
+/// Start the second step of a login, returning the `mfa_pending` token to answer it with.
+pub async fn create_mfa_challenge(
+    state: &RouterState,
+    subject: &TokenSubject,
+    kind: SessionKind,
+) -> ApiResult<String> {
+    let user_id = subject
+        .user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let token = generate_token();
+    state
+        .db
+        .query(format!(
+            "create mfa_challenge set user=$user_id, token_hash=$token_hash, security_stamp=$security_stamp, kind=$kind, expires_at=time::now() + {MFA_CHALLENGE_TTL}"
+        ))
+        .bind(("user_id", user_id))
+        .bind(("token_hash", hash_token(&token)))
+        .bind(("security_stamp", subject.security_stamp.clone()))
+        .bind(("kind", kind))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(token)
+}
+
+/// Find the challenge of a `mfa_pending` token without counting an attempt.
+pub async fn find_mfa_challenge(state: &RouterState, token: &str) -> ApiResult<MfaChallenge> {
+    let mut result = state
+        .db
+        .query("select id, user, kind, security_stamp from mfa_challenge where token_hash=$token_hash and expires_at>time::now() and attempts<$max_attempts")
+        .bind(("token_hash", hash_token(token)))
+        .bind(("max_attempts", MFA_MAX_ATTEMPTS))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let challenge: Option<MfaChallenge> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    challenge.ok_or(BackendError::InvalidToken)
+}
+
+/// Find the challenge of a `mfa_pending` token, counting the attempt before the second factor is checked.
+pub async fn attempt_mfa_challenge(state: &RouterState, token: &str) -> ApiResult<MfaChallenge> {
+    let mut result = state
+        .db
+        .query("(update mfa_challenge set attempts+=1 where token_hash=$token_hash and expires_at>time::now() and attempts<$max_attempts return id, user, kind, security_stamp)[0]")
+        .bind(("token_hash", hash_token(token)))
+        .bind(("max_attempts", MFA_MAX_ATTEMPTS))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let challenge: Option<MfaChallenge> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    challenge.ok_or(BackendError::InvalidToken)
+}
+
+/// Consume a challenge whose second factor was checked, returning the subject to log in.
+///
+/// The login is refused if the security stamp of the user changed since the password check.
+pub async fn complete_mfa_challenge(
+    state: &RouterState,
+    challenge: &MfaChallenge,
+) -> ApiResult<TokenSubject> {
+    let mut result = state
+        .db
+        .query("delete $challenge_id return before")
+        .bind(("challenge_id", challenge.id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let deleted: Option<MfaChallenge> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    deleted.ok_or(BackendError::InvalidToken)?;
+
+    let subject = load_token_subject(state, &challenge.user).await?;
+    if subject.security_stamp != challenge.security_stamp {
+        return Err(BackendError::InvalidToken);
+    }
+    Ok(subject)
+}
@@ -1,2 +1,28 @@
+pub mod api_key;
+pub mod audit;
 pub mod bearer_jwt;
+pub mod claims;
+pub mod client_info;
 pub mod cookie_jwt;
+pub mod cookie_session;
+pub mod guard;
+pub mod invitation;
+pub mod magic_link;
+pub mod mfa;
+pub mod oauth;
+pub mod oidc;
+pub mod organization;
+pub mod password;
+pub mod policy;
+pub mod principal;
+pub mod rbac;
+pub mod rebac;
+pub mod recovery_code;
+pub mod refresh_token;
+pub mod revocation;
+pub mod security_stamp;
+pub mod service_account;
+pub mod session;
+pub mod token;
+pub mod totp;
+pub mod webauthn;
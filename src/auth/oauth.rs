@@ -0,0 +1,456 @@
+use super::password::verify_secret;
+use super::security_stamp::{check_security_stamp, TokenSubject};
+use super::token::{decode_audience_token, generate_token, hash_token};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use p256::ecdsa::SigningKey;
+use p256::pkcs8::EncodePrivateKey;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+use subtle::ConstantTimeEq;
+use surrealdb::sql::Thing;
+use uuid::Uuid;
+
+/// The scopes a client can ask for, `openid` adding an ID token to the token response.
+pub const SUPPORTED_SCOPES: [&str; 3] = ["openid", "email", "profile"];
+const AUTHORIZATION_CODE_TTL: &str = "1m";
+const CONSENT_TTL_MINUTES: i64 = 10;
+const CONSENT_AUDIENCE: &str = "oauth-consent";
+
+/// The ES256 key signing the tokens issued to the OAuth clients, published at the JWKS endpoint.
+struct SigningKeys {
+    kid: String,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Value,
+}
+
+fn signing_keys() -> &'static SigningKeys {
+    static SIGNING_KEYS: OnceLock<SigningKeys> = OnceLock::new();
+    SIGNING_KEYS.get_or_init(|| {
+        // `OAUTH_SIGNING_KEY` was checked to be a valid P-256 scalar when loading the config.
+        let signing_key = SigningKey::from_slice(&env_config().oauth_signing_key)
+            .expect("Invalid `OAUTH_SIGNING_KEY`");
+        let der = signing_key
+            .to_pkcs8_der()
+            .expect("Failed to encode `OAUTH_SIGNING_KEY`");
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let x = URL_SAFE_NO_PAD.encode(point.x().expect("Uncompressed point"));
+        let y = URL_SAFE_NO_PAD.encode(point.y().expect("Uncompressed point"));
+        let kid = hash_token(&format!("{x}.{y}"))[..16].to_string();
+        SigningKeys {
+            encoding: EncodingKey::from_ec_der(der.as_bytes()),
+            decoding: DecodingKey::from_ec_components(&x, &y)
+                .expect("Failed to build the `OAUTH_SIGNING_KEY` public key"),
+            jwk: json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "use": "sig",
+                "alg": "ES256",
+                "kid": kid,
+                "x": x,
+                "y": y,
+            }),
+            kid,
+        }
+    })
+}
+
+/// The public keys the OAuth clients verify the ID tokens with.
+pub fn oauth_jwks() -> Value {
+    json!({ "keys": [signing_keys().jwk] })
+}
+
+fn sign_oauth_token<T: Serialize>(claims: &T) -> ApiResult<String> {
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(signing_keys().kid.clone());
+    encode(&header, claims, &signing_keys().encoding).map_err(|_| BackendError::JWTEncodingFailed)
+}
+
+/// An application registered to log its users in through this service.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthClient {
+    pub id: Thing,
+    pub client_id: String,
+    pub name: String,
+    /// The Argon2 hash of the secret of a confidential client, public clients only rely on PKCE.
+    secret_hash: Option<String>,
+    pub redirect_uris: Vec<String>,
+}
+
+/// Find a registered client by its `client_id`.
+pub async fn find_oauth_client(state: &RouterState, client_id: &str) -> ApiResult<OAuthClient> {
+    select_oauth_client(state, client_id)
+        .await?
+        .ok_or(BackendError::InvalidClient)
+}
+
+async fn select_oauth_client(
+    state: &RouterState,
+    client_id: &str,
+) -> ApiResult<Option<OAuthClient>> {
+    let mut result = state
+        .db
+        .query("select id, client_id, name, secret_hash, redirect_uris from oauth_client where client_id=$client_id")
+        .bind(("client_id", client_id.to_string()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    result.take(0).map_err(|_| BackendError::SomethingWentWrong)
+}
+
+/// Authenticate a client at the token endpoint, a confidential client needing its secret.
+///
+/// A secret sent for an unknown client is still checked, against a dummy hash, so it takes as long
+/// as a wrong secret.
+pub async fn authenticate_oauth_client(
+    state: &RouterState,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> ApiResult<OAuthClient> {
+    let client = select_oauth_client(state, client_id).await?;
+    let Some(client_secret) = client_secret else {
+        return client
+            .filter(|client| client.secret_hash.is_none())
+            .ok_or(BackendError::InvalidClient);
+    };
+    let secret_hash = client
+        .as_ref()
+        .and_then(|client| client.secret_hash.clone());
+    if !verify_secret(client_secret.to_string(), secret_hash).await? {
+        return Err(BackendError::InvalidClient);
+    }
+    client.ok_or(BackendError::InvalidClient)
+}
+
+/// An authorization request whose client and redirect uri were checked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizationGrant {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// The S256 PKCE challenge, the verifier being sent along with the code.
+    pub code_challenge: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ConsentClaims {
+    sub: String,
+    aud: String,
+    exp: usize,
+    iat: usize,
+    #[serde(flatten)]
+    grant: AuthorizationGrant,
+}
+
+/// Sign the authorization request shown on the consent screen, to be posted back by the same user.
+pub fn encode_consent(sub: &str, grant: &AuthorizationGrant) -> ApiResult<String> {
+    let now = Utc::now();
+    let claims = ConsentClaims {
+        sub: sub.to_string(),
+        aud: CONSENT_AUDIENCE.to_string(),
+        iat: now.timestamp() as usize,
+        exp: (now + Duration::minutes(CONSENT_TTL_MINUTES)).timestamp() as usize,
+        grant: grant.clone(),
+    };
+    encode(&Header::default(), &claims, &env_config().jwt_encode)
+        .map_err(|_| BackendError::JWTEncodingFailed)
+}
+
+/// Read back the authorization request a user consented to.
+pub fn decode_consent(token: &str, sub: &str) -> ApiResult<AuthorizationGrant> {
+    let claims: ConsentClaims = decode_audience_token(token, CONSENT_AUDIENCE)?;
+    if claims.sub != sub {
+        return Err(BackendError::InvalidToken);
+    }
+    Ok(claims.grant)
+}
+
+/// The scopes a user already granted to a client.
+async fn granted_scopes(
+    state: &RouterState,
+    user_id: &Thing,
+    client: &OAuthClient,
+) -> ApiResult<Vec<String>> {
+    let mut result = state
+        .db
+        .query("select value scopes from oauth_consent where user=$user_id and client=$client")
+        .bind(("user_id", user_id.clone()))
+        .bind(("client", client.id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let scopes: Option<Vec<String>> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(scopes.unwrap_or_default())
+}
+
+/// Whether a user already granted all of these scopes to a client, skipping the consent screen.
+pub async fn has_consent(
+    state: &RouterState,
+    user_id: &Thing,
+    client: &OAuthClient,
+    scopes: &[String],
+) -> ApiResult<bool> {
+    let granted = granted_scopes(state, user_id, client).await?;
+    Ok(scopes.iter().all(|scope| granted.contains(scope)))
+}
+
+/// Remember the scopes a user granted to a client, along with the ones granted before.
+pub async fn record_consent(
+    state: &RouterState,
+    user_id: &Thing,
+    client: &OAuthClient,
+    scopes: &[String],
+) -> ApiResult<()> {
+    let mut granted = granted_scopes(state, user_id, client).await?;
+    for scope in scopes {
+        if !granted.contains(scope) {
+            granted.push(scope.clone());
+        }
+    }
+    state
+        .db
+        .query("upsert type::thing('oauth_consent', [$user_id, $client]) set user=$user_id, client=$client, scopes=$scopes, updated_at=time::now()")
+        .bind(("user_id", user_id.clone()))
+        .bind(("client", client.id.clone()))
+        .bind(("scopes", granted))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(())
+}
+
+/// Issue the single-use authorization code the client exchanges at the token endpoint.
+pub async fn issue_authorization_code(
+    state: &RouterState,
+    subject: &TokenSubject,
+    client: &OAuthClient,
+    grant: &AuthorizationGrant,
+) -> ApiResult<String> {
+    let user_id = subject
+        .user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let code = generate_token();
+    state
+        .db
+        .query(format!(
+            "create oauth_code set code_hash=$code_hash, client=$client, user=$user_id, security_stamp=$security_stamp, redirect_uri=$redirect_uri, scopes=$scopes, nonce=$nonce, code_challenge=$code_challenge, expires_at=time::now() + {AUTHORIZATION_CODE_TTL}"
+        ))
+        .bind(("code_hash", hash_token(&code)))
+        .bind(("client", client.id.clone()))
+        .bind(("user_id", user_id))
+        .bind(("security_stamp", subject.security_stamp.clone()))
+        .bind(("redirect_uri", grant.redirect_uri.clone()))
+        .bind(("scopes", grant.scopes.clone()))
+        .bind(("nonce", grant.nonce.clone()))
+        .bind(("code_challenge", grant.code_challenge.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(code)
+}
+
+#[derive(Debug, Deserialize)]
+struct DBOAuthCode {
+    client: Thing,
+    user: Thing,
+    security_stamp: String,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    nonce: Option<String>,
+    code_challenge: String,
+}
+
+/// What an authorization code grants once redeemed.
+#[derive(Debug)]
+pub struct RedeemedCode {
+    pub subject: TokenSubject,
+    pub scopes: Vec<String>,
+    pub nonce: Option<String>,
+}
+
+/// Redeem an authorization code for the client it was issued to, checking its PKCE verifier.
+pub async fn redeem_authorization_code(
+    state: &RouterState,
+    client: &OAuthClient,
+    code: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
+) -> ApiResult<RedeemedCode> {
+    let mut result = state
+        .db
+        .query("(update oauth_code set used_at=time::now() where code_hash=$code_hash and used_at=none and expires_at>time::now() return client, user, security_stamp, redirect_uri, scopes, nonce, code_challenge)[0]")
+        .bind(("code_hash", hash_token(code)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let redeemed: Option<DBOAuthCode> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let redeemed = redeemed.ok_or(BackendError::InvalidGrant)?;
+
+    let challenge = hash_token(code_verifier);
+    if redeemed.client != client.id
+        || redeemed.redirect_uri != redirect_uri
+        || !bool::from(
+            challenge
+                .as_bytes()
+                .ct_eq(redeemed.code_challenge.as_bytes()),
+        )
+    {
+        return Err(BackendError::InvalidGrant);
+    }
+    let user_id = redeemed.user.to_string();
+    check_security_stamp(state, &user_id, &redeemed.security_stamp)
+        .await
+        .map_err(|_| BackendError::InvalidGrant)?;
+
+    Ok(RedeemedCode {
+        subject: TokenSubject::new(&redeemed.user, redeemed.security_stamp),
+        scopes: redeemed.scopes,
+        nonce: redeemed.nonce,
+    })
+}
+
+/// The claims of the access tokens issued to the OAuth clients, accepted at `/userinfo`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthAccessClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub client_id: String,
+    /// The granted scopes, separated by spaces.
+    pub scope: String,
+    pub stamp: String,
+    pub jti: String,
+    pub exp: usize,
+    pub iat: usize,
+}
+
+/// The tokens answered by the token endpoint.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthTokens {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub scope: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBOAuthUser {
+    username: String,
+    email: Option<String>,
+    verified: bool,
+}
+
+async fn load_oauth_user(state: &RouterState, user_id: &Thing) -> ApiResult<DBOAuthUser> {
+    let mut result = state
+        .db
+        .query("select username, email, verified from $user_id")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user: Option<DBOAuthUser> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    user.ok_or(BackendError::InvalidToken)
+}
+
+/// The claims about a user the granted scopes give access to.
+pub async fn user_claims(
+    state: &RouterState,
+    user_id: &Thing,
+    scopes: &[String],
+) -> ApiResult<Map<String, Value>> {
+    let user = load_oauth_user(state, user_id).await?;
+    let mut claims = Map::new();
+    claims.insert("sub".to_string(), json!(user_id.to_string()));
+    if scopes.iter().any(|scope| scope == "profile") {
+        claims.insert("preferred_username".to_string(), json!(user.username));
+    }
+    if scopes.iter().any(|scope| scope == "email") {
+        if let Some(email) = user.email {
+            claims.insert("email".to_string(), json!(email));
+            claims.insert("email_verified".to_string(), json!(user.verified));
+        }
+    }
+    Ok(claims)
+}
+
+/// Issue the access token of a redeemed code, and its ID token when `openid` was granted.
+pub async fn issue_oauth_tokens(
+    state: &RouterState,
+    client: &OAuthClient,
+    redeemed: &RedeemedCode,
+) -> ApiResult<OAuthTokens> {
+    let now = Utc::now();
+    let expires_in = Duration::minutes(env_config().jwt_access_ttl);
+    let scope = redeemed.scopes.join(" ");
+    let access_claims = OAuthAccessClaims {
+        iss: env_config().oauth_issuer.clone(),
+        sub: redeemed.subject.user_id.clone(),
+        aud: client.client_id.clone(),
+        client_id: client.client_id.clone(),
+        scope: scope.clone(),
+        stamp: redeemed.subject.security_stamp.clone(),
+        jti: Uuid::new_v4().to_string(),
+        iat: now.timestamp() as usize,
+        exp: (now + expires_in).timestamp() as usize,
+    };
+    let access_token = sign_oauth_token(&access_claims)?;
+
+    let id_token = if redeemed.scopes.iter().any(|scope| scope == "openid") {
+        let user_id = redeemed
+            .subject
+            .user_id
+            .parse::<Thing>()
+            .map_err(|_| BackendError::SomethingWentWrong)?;
+        let mut claims = user_claims(state, &user_id, &redeemed.scopes).await?;
+        claims.insert("iss".to_string(), json!(access_claims.iss));
+        claims.insert("aud".to_string(), json!(client.client_id));
+        claims.insert("iat".to_string(), json!(access_claims.iat));
+        claims.insert("exp".to_string(), json!(access_claims.exp));
+        if let Some(nonce) = &redeemed.nonce {
+            claims.insert("nonce".to_string(), json!(nonce));
+        }
+        Some(sign_oauth_token(&claims)?)
+    } else {
+        None
+    };
+
+    Ok(OAuthTokens {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: expires_in.num_seconds(),
+        scope,
+        id_token,
+    })
+}
+
+/// Validate an access token issued to an OAuth client, whose user must still be valid.
+pub async fn verify_oauth_access_token(
+    state: &RouterState,
+    token: &str,
+) -> ApiResult<OAuthAccessClaims> {
+    let mut validation = Validation::new(Algorithm::ES256);
+    validation.set_issuer(&[&env_config().oauth_issuer]);
+    // Any client can read the claims it was granted, whatever the audience.
+    validation.validate_aud = false;
+    let claims = decode::<OAuthAccessClaims>(token, &signing_keys().decoding, &validation)
+        .map_err(|_| BackendError::InvalidToken)?
+        .claims;
+    check_security_stamp(state, &claims.sub, &claims.stamp).await?;
+    Ok(claims)
+}
@@ -0,0 +1,805 @@
+use super::security_stamp::{load_token_subject, TokenSubject};
+use super::session::SessionKind;
+use super::token::{generate_token, hash_token};
+use crate::config::OidcProvider;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ops::Add;
+use std::sync::{Arc, RwLock};
+use subtle::ConstantTimeEq;
+use surrealdb::sql::Thing;
+use tower_cookies::cookie::time::OffsetDateTime;
+use tower_cookies::cookie::SameSite;
+use tower_cookies::{cookie, Cookie, Cookies};
+
+const OIDC_LOGIN_TTL_MINUTES: i64 = 10;
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+const OIDC_COOKIE_PATH: &str = "/api/oidc";
+/// The asymmetric algorithms an ID token can be signed with, the client secret never being used
+/// as a signing key.
+const ID_TOKEN_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// The parts of a provider discovery document used by the relying party.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// The claims of a validated ID token.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    /// The client the token was issued to, when it has several audiences.
+    #[serde(default)]
+    pub azp: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    /// A boolean, though some providers send it as a string.
+    #[serde(default)]
+    email_verified: Option<Value>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+}
+
+impl IdTokenClaims {
+    /// Whether the provider vouches for the email of the user.
+    pub fn email_verified(&self) -> bool {
+        match &self.email_verified {
+            Some(Value::Bool(verified)) => *verified,
+            Some(Value::String(verified)) => verified == "true",
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+/// The random values of an authorization request, checked when the provider redirects back.
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+    pub state: String,
+    pub nonce: String,
+    /// The PKCE verifier, only its S256 challenge is sent to the authorization endpoint.
+    pub code_verifier: String,
+}
+
+impl AuthorizationRequest {
+    pub fn new() -> Self {
+        Self {
+            state: generate_token(),
+            nonce: generate_token(),
+            code_verifier: generate_token(),
+        }
+    }
+}
+
+/// The S256 PKCE challenge of a verifier, which is the base64url SHA-256 digest `hash_token` computes.
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    hash_token(code_verifier)
+}
+
+/// Talk to the OpenID Connect providers, caching their discovery documents and signing keys.
+#[derive(Clone, Debug, Default)]
+pub struct OidcClient {
+    http: reqwest::Client,
+    metadata: Arc<RwLock<HashMap<String, ProviderMetadata>>>,
+    jwks: Arc<RwLock<HashMap<String, JwkSet>>>,
+}
+
+impl OidcClient {
+    /// Fetch the discovery document of an issuer, which must name that same issuer.
+    pub async fn discover(&self, issuer: &str) -> ApiResult<ProviderMetadata> {
+        if let Some(metadata) = self
+            .metadata
+            .read()
+            .ok()
+            .and_then(|metadata| metadata.get(issuer).cloned())
+        {
+            return Ok(metadata);
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|_| BackendError::IdentityProviderFailed)?
+            .json()
+            .await
+            .map_err(|_| BackendError::IdentityProviderFailed)?;
+        if metadata.issuer != issuer {
+            return Err(BackendError::IdentityProviderFailed);
+        }
+
+        if let Ok(mut cached) = self.metadata.write() {
+            cached.insert(issuer.to_string(), metadata.clone());
+        }
+        Ok(metadata)
+    }
+
+    /// The signing keys of a provider, fetched again when `refresh` is set to pick up a rotation.
+    async fn jwks(&self, metadata: &ProviderMetadata, refresh: bool) -> ApiResult<JwkSet> {
+        if !refresh {
+            if let Some(jwks) = self
+                .jwks
+                .read()
+                .ok()
+                .and_then(|jwks| jwks.get(&metadata.jwks_uri).cloned())
+            {
+                return Ok(jwks);
+            }
+        }
+
+        let jwks: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|_| BackendError::IdentityProviderFailed)?
+            .json()
+            .await
+            .map_err(|_| BackendError::IdentityProviderFailed)?;
+
+        if let Ok(mut cached) = self.jwks.write() {
+            cached.insert(metadata.jwks_uri.clone(), jwks.clone());
+        }
+        Ok(jwks)
+    }
+
+    /// Exchange an authorization code for the ID token of the user, along with the PKCE verifier.
+    pub async fn exchange_code(
+        &self,
+        metadata: &ProviderMetadata,
+        provider: &OidcProvider,
+        redirect_uri: &str,
+        code: &str,
+        code_verifier: &str,
+    ) -> ApiResult<String> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", provider.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &provider.client_secret {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+
+        let response: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|_| BackendError::IdentityProviderFailed)?
+            .json()
+            .await
+            .map_err(|_| BackendError::IdentityProviderFailed)?;
+        response
+            .id_token
+            .ok_or(BackendError::IdentityProviderFailed)
+    }
+
+    /// Validate an ID token against the signing keys of its provider, and the nonce of the
+    /// authorization request it answers.
+    pub async fn validate_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        provider: &OidcProvider,
+        id_token: &str,
+        nonce: &str,
+    ) -> ApiResult<IdTokenClaims> {
+        let header = decode_header(id_token).map_err(|_| BackendError::InvalidToken)?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(BackendError::InvalidToken);
+        }
+
+        let mut jwks = self.jwks(metadata, false).await?;
+        if find_jwk(&jwks, header.kid.as_deref()).is_none() {
+            jwks = self.jwks(metadata, true).await?;
+        }
+        let jwk = find_jwk(&jwks, header.kid.as_deref()).ok_or(BackendError::InvalidToken)?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|_| BackendError::InvalidToken)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|_| BackendError::InvalidToken)?
+            .claims;
+
+        let nonce_matches = claims
+            .nonce
+            .as_ref()
+            .is_some_and(|claimed| bool::from(claimed.as_bytes().ct_eq(nonce.as_bytes())));
+        if !nonce_matches {
+            return Err(BackendError::InvalidToken);
+        }
+        if claims
+            .azp
+            .as_ref()
+            .is_some_and(|azp| azp != &provider.client_id)
+        {
+            return Err(BackendError::InvalidToken);
+        }
+        Ok(claims)
+    }
+}
+
+/// Find the key an ID token was signed with, by its `kid` or as the only key of the provider.
+fn find_jwk<'a>(jwks: &'a JwkSet, kid: Option<&str>) -> Option<&'a jsonwebtoken::jwk::Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+}
+
+/// Build the url of the authorization endpoint the user is redirected to.
+pub fn authorization_url(
+    metadata: &ProviderMetadata,
+    provider: &OidcProvider,
+    redirect_uri: &str,
+    request: &AuthorizationRequest,
+) -> ApiResult<String> {
+    let scope = provider.scopes.join(" ");
+    let code_challenge = pkce_challenge(&request.code_verifier);
+    Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        [
+            ("response_type", "code"),
+            ("client_id", provider.client_id.as_str()),
+            ("redirect_uri", redirect_uri),
+            ("scope", scope.as_str()),
+            ("state", request.state.as_str()),
+            ("nonce", request.nonce.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map(String::from)
+    .map_err(|_| BackendError::IdentityProviderFailed)
+}
+
+/// Find a provider defined in `OIDC_PROVIDERS`.
+pub fn find_oidc_provider(name: &str) -> ApiResult<&'static OidcProvider> {
+    env_config()
+        .oidc_providers
+        .iter()
+        .find(|provider| provider.name == name)
+        .ok_or(BackendError::NotFound)
+}
+
+/// The callback the provider redirects the user to, which must be registered with the provider.
+pub fn oidc_redirect_uri(provider: &OidcProvider) -> String {
+    format!(
+        "{}/api/oidc/{}/callback",
+        env_config().public_url,
+        provider.name
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct DBOidcLogin {
+    nonce: String,
+    code_verifier: String,
+    kind: SessionKind,
+}
+
+/// Start a login with a provider, returning the url of its authorization endpoint.
+///
+/// The `state` is kept in a cookie, so only the browser that started the login can finish it.
+pub async fn start_oidc_login(
+    state: &RouterState,
+    cookies: &Cookies,
+    provider: &OidcProvider,
+    kind: SessionKind,
+) -> ApiResult<String> {
+    let metadata = state.oidc.discover(&provider.issuer).await?;
+    let request = AuthorizationRequest::new();
+    let url = authorization_url(&metadata, provider, &oidc_redirect_uri(provider), &request)?;
+
+    state
+        .db
+        .query(format!(
+            "create oidc_login set provider=$provider, state_hash=$state_hash, nonce=$nonce, code_verifier=$code_verifier, kind=$kind, expires_at=time::now() + {OIDC_LOGIN_TTL_MINUTES}m"
+        ))
+        .bind(("provider", provider.name.clone()))
+        .bind(("state_hash", hash_token(&request.state)))
+        .bind(("nonce", request.nonce))
+        .bind(("code_verifier", request.code_verifier))
+        .bind(("kind", kind))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+
+    let mut cookie = Cookie::new(OIDC_STATE_COOKIE, request.state);
+    cookie.set_http_only(true);
+    // Lax, so the cookie is sent along the redirection from the provider.
+    cookie.set_same_site(SameSite::Lax);
+    cookie.set_path(OIDC_COOKIE_PATH);
+    cookie.set_expires(
+        OffsetDateTime::now_utc().add(cookie::time::Duration::minutes(OIDC_LOGIN_TTL_MINUTES)),
+    );
+    cookies.add(cookie);
+    Ok(url)
+}
+
+/// Finish a login the provider redirected back with a `code`, returning the subject to log in
+/// and how.
+pub async fn finish_oidc_login(
+    state: &RouterState,
+    cookies: &Cookies,
+    provider: &OidcProvider,
+    code: &str,
+    state_param: &str,
+) -> ApiResult<(TokenSubject, SessionKind)> {
+    let state_cookie = cookies
+        .get(OIDC_STATE_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .ok_or(BackendError::NoCookieFound)?;
+    if !bool::from(state_cookie.as_bytes().ct_eq(state_param.as_bytes())) {
+        return Err(BackendError::InvalidToken);
+    }
+    let mut cookie = Cookie::new(OIDC_STATE_COOKIE, "");
+    cookie.set_http_only(true);
+    cookie.set_path(OIDC_COOKIE_PATH);
+    cookie.set_expires(OffsetDateTime::now_utc());
+    cookies.add(cookie);
+
+    let mut result = state
+        .db
+        .query("(delete oidc_login where state_hash=$state_hash and provider=$provider and expires_at>time::now() return before)[0]")
+        .bind(("state_hash", hash_token(state_param)))
+        .bind(("provider", provider.name.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let login: Option<DBOidcLogin> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let login = login.ok_or(BackendError::InvalidToken)?;
+
+    let metadata = state.oidc.discover(&provider.issuer).await?;
+    let id_token = state
+        .oidc
+        .exchange_code(
+            &metadata,
+            provider,
+            &oidc_redirect_uri(provider),
+            code,
+            &login.code_verifier,
+        )
+        .await?;
+    let claims = state
+        .oidc
+        .validate_id_token(&metadata, provider, &id_token, &login.nonce)
+        .await?;
+
+    let user_id = link_identity(state, provider, &claims).await?;
+    let subject = load_token_subject(state, &user_id).await?;
+    Ok((subject, login.kind))
+}
+
+/// Find the user of a provider account, linking it on its first login.
+///
+/// The account is linked to the user with the same email when both the provider and the user
+/// verified it, otherwise a new user is created.
+async fn link_identity(
+    state: &RouterState,
+    provider: &OidcProvider,
+    claims: &IdTokenClaims,
+) -> ApiResult<Thing> {
+    let mut result = state
+        .db
+        .query("(update identity set email=$email, last_login_at=time::now() where provider=$provider and subject=$subject return value user)[0]")
+        .bind(("provider", provider.name.clone()))
+        .bind(("subject", claims.sub.clone()))
+        .bind(("email", claims.email.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user_id: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if let Some(user_id) = user_id {
+        return Ok(user_id);
+    }
+
+    let existing: Option<DBEmailOwner> = match &claims.email {
+        Some(email) => {
+            let mut result = state
+                .db
+                .query("select id, verified from user where email=$email")
+                .bind(("email", email.clone()))
+                .await
+                .map_err(|_| BackendError::SomethingWentWrong)?;
+            result
+                .take(0)
+                .map_err(|_| BackendError::SomethingWentWrong)?
+        }
+        None => None,
+    };
+
+    match existing {
+        Some(owner) if owner.verified && claims.email_verified() => {
+            state
+                .db
+                .query("create identity set user=$user_id, provider=$provider, subject=$subject, email=$email")
+                .bind(("user_id", owner.id.clone()))
+                .bind(("provider", provider.name.clone()))
+                .bind(("subject", claims.sub.clone()))
+                .bind(("email", claims.email.clone()))
+                .await
+                .map_err(|_| BackendError::SomethingWentWrong)?
+                .check()
+                .map_err(|_| BackendError::SomethingWentWrong)?;
+            Ok(owner.id)
+        }
+        // The email belongs to another user, the new one is created without it.
+        Some(_) => create_identity_user(state, provider, claims, None).await,
+        None => create_identity_user(state, provider, claims, claims.email.clone()).await,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DBEmailOwner {
+    id: Thing,
+    verified: bool,
+}
+
+/// How many usernames are tried before giving up on creating a user.
+const USERNAME_ATTEMPTS: usize = 3;
+
+/// Create a user without a password for a provider account, along with its identity.
+async fn create_identity_user(
+    state: &RouterState,
+    provider: &OidcProvider,
+    claims: &IdTokenClaims,
+    email: Option<String>,
+) -> ApiResult<Thing> {
+    let base = username_candidate(claims);
+    for attempt in 0..USERNAME_ATTEMPTS {
+        let username = match attempt {
+            0 => base.clone(),
+            _ => format!("{base}-{}", &generate_token()[..6]),
+        };
+        let created = state
+            .db
+            .query(
+                "begin transaction;
+                let $user_id = (create user set username=$username, email=$email, verified=$verified return value id)[0];
+                create identity set user=$user_id, provider=$provider, subject=$subject, email=$identity_email;
+                return $user_id;
+                commit transaction;",
+            )
+            .bind(("username", username))
+            .bind(("email", email.clone()))
+            .bind(("verified", email.is_some() && claims.email_verified()))
+            .bind(("provider", provider.name.clone()))
+            .bind(("subject", claims.sub.clone()))
+            .bind(("identity_email", claims.email.clone()))
+            .await;
+        let user_id: Option<Thing> = match created {
+            Ok(mut result) => {
+                let last = result.num_statements() - 1;
+                result.take(last).ok().flatten()
+            }
+            Err(_) => None,
+        };
+        if let Some(user_id) = user_id {
+            return Ok(user_id);
+        }
+    }
+    Err(BackendError::SomethingWentWrong)
+}
+
+/// A username following the username rules, from the provider username or the email.
+fn username_candidate(claims: &IdTokenClaims) -> String {
+    let name = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| claims.email.as_deref()?.split('@').next())
+        .unwrap_or_default();
+    let username: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        .take(24)
+        .collect();
+    if username.len() < 3 {
+        "user".to_string()
+    } else {
+        username
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::{Form, Json, Router};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use p256::ecdsa::SigningKey;
+    use p256::pkcs8::EncodePrivateKey;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    const CLIENT_ID: &str = "axum-auth-api";
+    const REDIRECT_URI: &str = "http://localhost:3000/api/oidc/mock/callback";
+
+    /// An authorization the user granted, waiting for its code to be exchanged.
+    struct MockGrant {
+        code: String,
+        code_challenge: String,
+        nonce: String,
+    }
+
+    /// An in-process OpenID Connect provider, signing its ID tokens with an ES256 key.
+    #[derive(Clone)]
+    struct MockProvider {
+        issuer: String,
+        encoding_key: Arc<EncodingKey>,
+        jwks: Value,
+        grant: Arc<Mutex<Option<MockGrant>>>,
+    }
+
+    impl MockProvider {
+        async fn spawn() -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+            let signing_key = SigningKey::random(&mut rand::rngs::OsRng);
+            let der = signing_key.to_pkcs8_der().unwrap();
+            let point = signing_key.verifying_key().to_encoded_point(false);
+            let jwks = json!({
+                "keys": [{
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "use": "sig",
+                    "alg": "ES256",
+                    "kid": "mock-key",
+                    "x": URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+                    "y": URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+                }]
+            });
+            let provider = Self {
+                issuer,
+                encoding_key: Arc::new(EncodingKey::from_ec_der(der.as_bytes())),
+                jwks,
+                grant: Arc::new(Mutex::new(None)),
+            };
+
+            let router = Router::new()
+                .route("/.well-known/openid-configuration", get(mock_discovery))
+                .route("/jwks", get(mock_jwks))
+                .route("/token", post(mock_token))
+                .with_state(provider.clone());
+            tokio::spawn(async move { axum::serve(listener, router).await });
+            provider
+        }
+
+        fn config(&self) -> OidcProvider {
+            OidcProvider {
+                name: "mock".to_string(),
+                issuer: self.issuer.clone(),
+                client_id: CLIENT_ID.to_string(),
+                client_secret: Some("mock-secret".to_string()),
+                scopes: vec!["openid".to_string(), "email".to_string()],
+            }
+        }
+
+        /// Let the user through the authorization endpoint, returning the code it is redirected with.
+        fn authorize(&self, authorization_url: &str) -> String {
+            let url = Url::parse(authorization_url).unwrap();
+            let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+            assert_eq!(params["response_type"], "code");
+            assert_eq!(params["client_id"], CLIENT_ID);
+            assert_eq!(params["redirect_uri"], REDIRECT_URI);
+            assert_eq!(params["code_challenge_method"], "S256");
+            let code = generate_token();
+            *self.grant.lock().unwrap() = Some(MockGrant {
+                code: code.clone(),
+                code_challenge: params["code_challenge"].clone(),
+                nonce: params["nonce"].clone(),
+            });
+            code
+        }
+
+        fn sign(&self, claims: Value) -> String {
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some("mock-key".to_string());
+            encode(&header, &claims, &self.encoding_key).unwrap()
+        }
+
+        fn id_token_claims(&self, nonce: &str) -> Value {
+            let now = chrono::Utc::now().timestamp();
+            json!({
+                "iss": self.issuer,
+                "aud": CLIENT_ID,
+                "sub": "mock-user",
+                "email": "mock-user@example.com",
+                "email_verified": true,
+                "nonce": nonce,
+                "iat": now,
+                "exp": now + 300,
+            })
+        }
+    }
+
+    async fn mock_discovery(State(provider): State<MockProvider>) -> Json<Value> {
+        Json(json!({
+            "issuer": provider.issuer,
+            "authorization_endpoint": format!("{}/authorize", provider.issuer),
+            "token_endpoint": format!("{}/token", provider.issuer),
+            "jwks_uri": format!("{}/jwks", provider.issuer),
+        }))
+    }
+
+    async fn mock_jwks(State(provider): State<MockProvider>) -> Json<Value> {
+        Json(provider.jwks.clone())
+    }
+
+    async fn mock_token(
+        State(provider): State<MockProvider>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let grant = provider
+            .grant
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        if form.get("code") != Some(&grant.code)
+            || form.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+            || form.get("client_secret").map(String::as_str) != Some("mock-secret")
+            || form.get("redirect_uri").map(String::as_str) != Some(REDIRECT_URI)
+            || form
+                .get("code_verifier")
+                .map(|verifier| pkce_challenge(verifier))
+                != Some(grant.code_challenge)
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let id_token = provider.sign(provider.id_token_claims(&grant.nonce));
+        Ok(Json(json!({
+            "access_token": generate_token(),
+            "token_type": "Bearer",
+            "id_token": id_token,
+        })))
+    }
+
+    #[tokio::test]
+    async fn oidc_code_flow_with_mock_provider() {
+        let mock = MockProvider::spawn().await;
+        let provider = mock.config();
+        let client = OidcClient::default();
+
+        let metadata = client.discover(&provider.issuer).await.unwrap();
+        assert_eq!(metadata.token_endpoint, format!("{}/token", mock.issuer));
+
+        let request = AuthorizationRequest::new();
+        let url = authorization_url(&metadata, &provider, REDIRECT_URI, &request).unwrap();
+        let code = mock.authorize(&url);
+
+        let id_token = client
+            .exchange_code(
+                &metadata,
+                &provider,
+                REDIRECT_URI,
+                &code,
+                &request.code_verifier,
+            )
+            .await
+            .unwrap();
+        let claims = client
+            .validate_id_token(&metadata, &provider, &id_token, &request.nonce)
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "mock-user");
+        assert_eq!(claims.email.as_deref(), Some("mock-user@example.com"));
+        assert!(claims.email_verified());
+        assert_eq!(username_candidate(&claims), "mock-user");
+    }
+
+    #[tokio::test]
+    async fn oidc_rejects_invalid_exchanges_and_id_tokens() {
+        let mock = MockProvider::spawn().await;
+        let provider = mock.config();
+        let client = OidcClient::default();
+        let metadata = client.discover(&provider.issuer).await.unwrap();
+
+        let request = AuthorizationRequest::new();
+        let url = authorization_url(&metadata, &provider, REDIRECT_URI, &request).unwrap();
+        let code = mock.authorize(&url);
+        let exchanged = client
+            .exchange_code(&metadata, &provider, REDIRECT_URI, &code, "wrong-verifier")
+            .await;
+        assert_eq!(
+            exchanged.unwrap_err(),
+            BackendError::IdentityProviderFailed,
+            "Shouldn't exchange a code without its PKCE verifier"
+        );
+
+        let id_token = mock.sign(mock.id_token_claims("another-nonce"));
+        let validated = client
+            .validate_id_token(&metadata, &provider, &id_token, &request.nonce)
+            .await;
+        assert_eq!(
+            validated.unwrap_err(),
+            BackendError::InvalidToken,
+            "Shouldn't accept the ID token of another authorization request"
+        );
+
+        let mut claims = mock.id_token_claims(&request.nonce);
+        claims["aud"] = json!("another-client");
+        let validated = client
+            .validate_id_token(&metadata, &provider, &mock.sign(claims), &request.nonce)
+            .await;
+        assert_eq!(
+            validated.unwrap_err(),
+            BackendError::InvalidToken,
+            "Shouldn't accept an ID token issued to another client"
+        );
+
+        let mut claims = mock.id_token_claims(&request.nonce);
+        claims["iss"] = json!("https://evil.example.com");
+        let validated = client
+            .validate_id_token(&metadata, &provider, &mock.sign(claims), &request.nonce)
+            .await;
+        assert_eq!(
+            validated.unwrap_err(),
+            BackendError::InvalidToken,
+            "Shouldn't accept an ID token from another issuer"
+        );
+
+        let forged = encode(
+            &Header::new(Algorithm::HS256),
+            &mock.id_token_claims(&request.nonce),
+            &EncodingKey::from_secret(b"mock-secret"),
+        )
+        .unwrap();
+        let validated = client
+            .validate_id_token(&metadata, &provider, &forged, &request.nonce)
+            .await;
+        assert_eq!(
+            validated.unwrap_err(),
+            BackendError::InvalidToken,
+            "Shouldn't accept an ID token signed with the client secret"
+        );
+    }
+}
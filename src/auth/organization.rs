@@ -0,0 +1,352 @@
+use super::bearer_jwt::PrincipalKind;
+use super::principal::Principal;
+use crate::surreal::connect_db;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::request::Parts;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+use tokio::sync::{Mutex, OnceCell};
+
+/// The role of a user within an organization, each role including the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrgRole {
+    Member,
+    /// Manages the members of the organization.
+    Admin,
+    Owner,
+}
+
+impl OrgRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrgRole::Member => "member",
+            OrgRole::Admin => "admin",
+            OrgRole::Owner => "owner",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrganizationInfo {
+    pub id: String,
+    pub name: String,
+    pub slug: String,
+    pub role: OrgRole,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBOrganization {
+    id: Thing,
+    name: String,
+    slug: String,
+    role: OrgRole,
+}
+
+impl From<DBOrganization> for OrganizationInfo {
+    fn from(organization: DBOrganization) -> Self {
+        Self {
+            id: organization.id.to_string(),
+            name: organization.name,
+            slug: organization.slug,
+            role: organization.role,
+        }
+    }
+}
+
+/// Parse the id of an organization, with or without its `organization:` table.
+pub fn parse_org_id(org_id: &str) -> ApiResult<Thing> {
+    let org_id = match org_id.split_once(':') {
+        Some(_) => org_id.to_string(),
+        None => format!("organization:{org_id}"),
+    };
+    org_id
+        .parse::<Thing>()
+        .ok()
+        .filter(|org_id| org_id.tb == "organization")
+        .ok_or(BackendError::NotFound)
+}
+
+/// List the organizations a user is a member of, with its role in each of them.
+pub async fn list_organizations(
+    state: &RouterState,
+    user_id: &Thing,
+) -> ApiResult<Vec<OrganizationInfo>> {
+    let mut result = state
+        .db
+        .query("select out as id, out.name as name, out.slug as slug, role from membership where in=$user_id order by name")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let organizations: Vec<DBOrganization> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(organizations.into_iter().map(Into::into).collect())
+}
+
+/// Create an organization, its creator becoming its owner.
+///
+/// Returns `SlugTaken` when another organization has the slug.
+pub async fn create_organization(
+    state: &RouterState,
+    user_id: &Thing,
+    name: String,
+    slug: String,
+) -> ApiResult<OrganizationInfo> {
+    let mut result = state
+        .db
+        .query("count(select id from organization where slug=$slug) > 0")
+        .bind(("slug", slug.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let taken: Option<bool> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if taken.unwrap_or_default() {
+        return Err(BackendError::SlugTaken);
+    }
+
+    let mut result = state
+        .db
+        .query(
+            "begin transaction;
+            let $org_id = (create organization set name=$name, slug=$slug return value id)[0];
+            relate $user_id->membership->$org_id set role=\"owner\" return value out;
+            commit transaction;",
+        )
+        .bind(("user_id", user_id.clone()))
+        .bind(("name", name.clone()))
+        .bind(("slug", slug.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let last = result.num_statements() - 1;
+    let org_id: Option<Thing> = result
+        .take(last)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let org_id = org_id.ok_or(BackendError::SomethingWentWrong)?;
+    Ok(OrganizationInfo {
+        id: org_id.to_string(),
+        name,
+        slug,
+        role: OrgRole::Owner,
+    })
+}
+
+/// Load an organization along with the role of a member, `NotFound` for anyone else.
+pub async fn load_organization(
+    state: &RouterState,
+    user_id: &Thing,
+    org_id: &Thing,
+) -> ApiResult<OrganizationInfo> {
+    let mut result = state
+        .db
+        .query("select out as id, out.name as name, out.slug as slug, role from membership where in=$user_id and out=$org_id")
+        .bind(("user_id", user_id.clone()))
+        .bind(("org_id", org_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let organization: Option<DBOrganization> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    organization.map(Into::into).ok_or(BackendError::NotFound)
+}
+
+/// The role of a user in an organization, `None` when it isn't a member.
+pub async fn membership_role(
+    state: &RouterState,
+    user_id: &Thing,
+    org_id: &Thing,
+) -> ApiResult<Option<OrgRole>> {
+    let mut result = state
+        .db
+        .query("select value role from membership where in=$user_id and out=$org_id")
+        .bind(("user_id", user_id.clone()))
+        .bind(("org_id", org_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let role: Option<OrgRole> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(role)
+}
+
+/// Make a session act in an organization the user is a member of, or in none of them.
+///
+/// Returns `Forbidden` when the user isn't a member of the organization.
+pub async fn switch_session_organization(
+    state: &RouterState,
+    user_id: &Thing,
+    session_id: &str,
+    org_id: Option<&Thing>,
+) -> ApiResult<()> {
+    if let Some(org_id) = org_id {
+        membership_role(state, user_id, org_id)
+            .await?
+            .ok_or(BackendError::Forbidden)?;
+    }
+    let session_id = session_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let mut result = state
+        .db
+        .query("update $session_id set organization=$org_id where user=$user_id and revoked_at=none return value id")
+        .bind(("session_id", session_id))
+        .bind(("user_id", user_id.clone()))
+        .bind(("org_id", org_id.cloned()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let updated: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    updated.map(|_| ()).ok_or(BackendError::InvalidToken)
+}
+
+/// The organization a session acts in, as long as its user is still a member of it.
+pub async fn session_organization(
+    state: &RouterState,
+    session_id: &str,
+) -> ApiResult<Option<String>> {
+    let session_id = session_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let mut result = state
+        .db
+        .query(
+            "let $session = (select user, organization from $session_id)[0];
+            (select value out from membership where in=$session.user and out=$session.organization)[0];",
+        )
+        .bind(("session_id", session_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let org_id: Option<Thing> = result
+        .take(result.num_statements() - 1)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(org_id.map(|org_id| org_id.to_string()))
+}
+
+/// An extractor for a user acting in an organization it is a member of, chosen at `/orgs/switch`.
+///
+/// The membership is checked on every request, so a removed member loses its access right away.
+#[derive(Debug, Clone)]
+pub struct OrgMember {
+    pub user_id: Thing,
+    pub org_id: Thing,
+    pub role: OrgRole,
+}
+
+impl OrgMember {
+    /// Reject with `Forbidden` a member whose role doesn't include `role`.
+    pub fn require(&self, role: OrgRole) -> ApiResult<()> {
+        if self.role >= role {
+            Ok(())
+        } else {
+            Err(BackendError::Forbidden)
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OrgMember
+where
+    RouterState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = BackendError;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let principal = Principal::from_request_parts(parts, state).await?;
+        if principal.kind != PrincipalKind::User {
+            return Err(BackendError::Forbidden);
+        }
+        let org_id = principal.org_id.ok_or(BackendError::Forbidden)?;
+        let org_id = org_id
+            .parse::<Thing>()
+            .map_err(|_| BackendError::InvalidToken)?;
+        let user_id = principal
+            .sub
+            .parse::<Thing>()
+            .map_err(|_| BackendError::InvalidToken)?;
+        let state = RouterState::from_ref(state);
+        let role = membership_role(&state, &user_id, &org_id)
+            .await?
+            .ok_or(BackendError::Forbidden)?;
+        Ok(Self {
+            user_id,
+            org_id,
+            role,
+        })
+    }
+}
+
+/// How many tenant connections are kept open at once.
+const MAX_TENANT_CONNECTIONS: usize = 64;
+
+/// The connection of a tenant, opened once by the first request needing it.
+type TenantConnection = Arc<OnceCell<Surreal<Client>>>;
+
+/// The connections to the databases of the tenants, opened on first use.
+///
+/// A connection is bound to the database it was opened for, so each tenant gets its own rather
+/// than switching the database of the shared one. Tenants connect concurrently, the lock is only
+/// held to find the cell of a tenant, and beyond `MAX_TENANT_CONNECTIONS` another tenant's
+/// connection is dropped, to be opened again when it is next used.
+#[derive(Debug, Clone, Default)]
+pub struct TenantDatabases {
+    connections: Arc<Mutex<HashMap<String, TenantConnection>>>,
+}
+
+impl TenantDatabases {
+    async fn connect(&self, database: &str) -> ApiResult<Surreal<Client>> {
+        let cell = {
+            let mut connections = self.connections.lock().await;
+            if !connections.contains_key(database) && connections.len() >= MAX_TENANT_CONNECTIONS {
+                if let Some(evicted) = connections.keys().next().cloned() {
+                    connections.remove(&evicted);
+                }
+            }
+            connections.entry(database.to_string()).or_default().clone()
+        };
+        cell.get_or_try_init(|| async {
+            connect_db(
+                env_config().db_host.as_str(),
+                env_config().db_user.as_str(),
+                env_config().db_pswd.as_str(),
+                env_config().db_namespace.as_str(),
+                database,
+            )
+            .await
+            .map_err(|err| {
+                tracing::error!("Failed to connect to the tenant database `{database}`: {err}");
+                BackendError::SomethingWentWrong
+            })
+        })
+        .await
+        .cloned()
+    }
+}
+
+/// The database holding the data of an organization.
+///
+/// With `TENANT_DATABASES`, it is the `database` of the organization, `org_<key>` by default, in
+/// `DB_NAMESPACE`. Otherwise every organization shares the main database.
+pub async fn organization_db(state: &RouterState, org_id: &Thing) -> ApiResult<Surreal<Client>> {
+    if !env_config().tenant_databases {
+        return Ok(state.db.clone());
+    }
+    let mut result = state
+        .db
+        .query("select value database from $org_id")
+        .bind(("org_id", org_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let database: Option<String> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let database = database.unwrap_or_else(|| format!("org_{}", org_id.id.to_raw()));
+    state.tenants.connect(&database).await
+}
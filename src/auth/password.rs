@@ -0,0 +1,266 @@
+use crate::{env_config, ApiResult, BackendError};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use pbkdf2::Pbkdf2;
+use rand::rngs::OsRng;
+use subtle::ConstantTimeEq;
+use tokio::sync::OnceCell;
+
+/// The outcome of checking a password against a stored credential.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordVerification {
+    /// The password doesn't match.
+    Invalid,
+    /// The password matches a hash using the current parameters.
+    Valid,
+    /// The password matches, but the stored credential is a legacy format or uses weaker parameters
+    /// and should be replaced by a fresh hash.
+    Outdated,
+}
+
+fn argon2_params() -> ApiResult<Params> {
+    Params::new(
+        env_config().password_memory_cost,
+        env_config().password_time_cost,
+        env_config().password_parallelism,
+        None,
+    )
+    .map_err(|_| BackendError::PasswordHashingFailed)
+}
+
+/// Build an Argon2id hasher from the configured memory and time costs.
+fn argon2id() -> ApiResult<Argon2<'static>> {
+    Ok(Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        argon2_params()?,
+    ))
+}
+
+/// Hash a password into an Argon2id PHC string.
+pub async fn hash_password(password: String) -> ApiResult<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        argon2id()?
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|_| BackendError::PasswordHashingFailed)
+    })
+    .await
+    .map_err(|_| BackendError::SomethingWentWrong)?
+}
+
+/// A hash that is verified when the user or client doesn't exist, so a missing account takes as long
+/// as a wrong password.
+pub async fn dummy_password_hash() -> ApiResult<&'static String> {
+    static DUMMY_HASH: OnceCell<String> = OnceCell::const_new();
+    DUMMY_HASH
+        .get_or_try_init(|| hash_password("dummy-password".to_string()))
+        .await
+}
+
+/// Check a password against a stored credential.
+///
+/// Besides Argon2 PHC strings, this accepts bcrypt hashes, PBKDF2 PHC strings and plaintext
+/// values (anything not starting with `$`), which are all reported as `Outdated` on a match.
+pub async fn verify_password(
+    password: String,
+    password_hash: String,
+) -> ApiResult<PasswordVerification> {
+    let current = argon2_params()?;
+    tokio::task::spawn_blocking(move || {
+        verify_password_blocking(&password, &password_hash, &current)
+    })
+    .await
+    .map_err(|_| BackendError::SomethingWentWrong)?
+}
+
+fn verify_password_blocking(
+    password: &str,
+    password_hash: &str,
+    current: &Params,
+) -> ApiResult<PasswordVerification> {
+    if !password_hash.starts_with('$') {
+        let matches: bool = password.as_bytes().ct_eq(password_hash.as_bytes()).into();
+        return Ok(outdated_if(matches));
+    }
+
+    if ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| password_hash.starts_with(prefix))
+    {
+        let matches = bcrypt::verify(password, password_hash).unwrap_or(false);
+        return Ok(outdated_if(matches));
+    }
+
+    let hash = PasswordHash::new(password_hash).map_err(|_| BackendError::InvalidCredentials)?;
+    if hash.algorithm.as_str().starts_with("pbkdf2") {
+        let matches = Pbkdf2.verify_password(password.as_bytes(), &hash).is_ok();
+        return Ok(outdated_if(matches));
+    }
+
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        return Ok(PasswordVerification::Invalid);
+    }
+
+    if is_current_argon2(&hash, current) {
+        Ok(PasswordVerification::Valid)
+    } else {
+        Ok(PasswordVerification::Outdated)
+    }
+}
+
+/// Check the secret of a client against its stored hash, or the dummy hash for an unknown client.
+///
+/// Unlike passwords, secrets have no legacy formats to migrate from: only Argon2 PHC strings are
+/// accepted, and any other stored value refuses every secret.
+pub async fn verify_secret(secret: String, secret_hash: Option<String>) -> ApiResult<bool> {
+    let Some(secret_hash) = secret_hash else {
+        let dummy_hash = dummy_password_hash().await?.clone();
+        tokio::task::spawn_blocking(move || verify_secret_blocking(&secret, &dummy_hash))
+            .await
+            .map_err(|_| BackendError::SomethingWentWrong)?;
+        return Ok(false);
+    };
+    tokio::task::spawn_blocking(move || verify_secret_blocking(&secret, &secret_hash))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)
+}
+
+fn verify_secret_blocking(secret: &str, secret_hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(secret_hash) else {
+        return false;
+    };
+    let is_argon2 = [Algorithm::Argon2id, Algorithm::Argon2i, Algorithm::Argon2d]
+        .iter()
+        .any(|algorithm| hash.algorithm == algorithm.ident());
+    is_argon2
+        && Argon2::default()
+            .verify_password(secret.as_bytes(), &hash)
+            .is_ok()
+}
+
+fn outdated_if(matches: bool) -> PasswordVerification {
+    if matches {
+        PasswordVerification::Outdated
+    } else {
+        PasswordVerification::Invalid
+    }
+}
+
+/// Whether an Argon2 hash uses Argon2id with the current version and at least the `current` costs.
+fn is_current_argon2(hash: &PasswordHash, current: &Params) -> bool {
+    let Ok(params) = Params::try_from(hash) else {
+        return false;
+    };
+    hash.algorithm == Algorithm::Argon2id.ident()
+        && hash.version == Some(Version::V0x13.into())
+        && params.m_cost() >= current.m_cost()
+        && params.t_cost() >= current.t_cost()
+        && params.p_cost() >= current.p_cost()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    /// Low costs, to keep the tests fast.
+    fn current() -> Params {
+        Params::new(1024, 2, 2, None).unwrap()
+    }
+
+    fn argon2id_hash(params: Params) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(PASSWORD.as_bytes(), &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    fn verify(password: &str, password_hash: &str) -> PasswordVerification {
+        verify_password_blocking(password, password_hash, &current()).unwrap()
+    }
+
+    #[test]
+    fn legacy_formats_are_outdated() {
+        let bcrypt_hash = bcrypt::hash(PASSWORD, 4).unwrap();
+        assert!(bcrypt_hash.starts_with("$2b$"));
+        let salt = SaltString::generate(&mut OsRng);
+        let rounds = pbkdf2::Params {
+            rounds: 1000,
+            output_length: 32,
+        };
+        let pbkdf2_hash = Pbkdf2
+            .hash_password_customized(PASSWORD.as_bytes(), None, None, rounds, &salt)
+            .unwrap()
+            .to_string();
+
+        for password_hash in [PASSWORD, bcrypt_hash.as_str(), pbkdf2_hash.as_str()] {
+            assert_eq!(
+                verify(PASSWORD, password_hash),
+                PasswordVerification::Outdated,
+                "{password_hash}"
+            );
+            assert_eq!(
+                verify("wrong", password_hash),
+                PasswordVerification::Invalid,
+                "{password_hash}"
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_password_is_invalid() {
+        let password_hash = argon2id_hash(current());
+        assert_eq!(
+            verify("wrong", &password_hash),
+            PasswordVerification::Invalid
+        );
+    }
+
+    #[test]
+    fn lower_argon2_costs_are_outdated() {
+        let weaker = [
+            Params::new(512, 2, 2, None).unwrap(),
+            Params::new(1024, 1, 2, None).unwrap(),
+            Params::new(1024, 2, 1, None).unwrap(),
+        ];
+        for params in weaker {
+            let password_hash = argon2id_hash(params);
+            assert_eq!(
+                verify(PASSWORD, &password_hash),
+                PasswordVerification::Outdated,
+                "{password_hash}"
+            );
+        }
+    }
+
+    #[test]
+    fn secrets_only_match_argon2() {
+        let bcrypt_hash = bcrypt::hash(PASSWORD, 4).unwrap();
+        for secret_hash in [PASSWORD, bcrypt_hash.as_str(), "$argon2id$v=19$invalid"] {
+            assert!(
+                !verify_secret_blocking(PASSWORD, secret_hash),
+                "{secret_hash}"
+            );
+        }
+
+        let secret_hash = argon2id_hash(current());
+        assert!(verify_secret_blocking(PASSWORD, &secret_hash));
+        assert!(!verify_secret_blocking("wrong", &secret_hash));
+    }
+
+    #[test]
+    fn current_argon2_is_valid() {
+        let password_hash = argon2id_hash(current());
+        assert_eq!(
+            verify(PASSWORD, &password_hash),
+            PasswordVerification::Valid
+        );
+    }
+}
@@ -0,0 +1,594 @@
+use super::client_info::ClientInfo;
+use super::guard::Guard;
+use super::principal::Principal;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::async_trait;
+use axum::extract::{FromRequestParts, RawPathParams};
+use axum::http::request::Parts;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+/// A rule of the policy file, applying when the action matches and all of its conditions hold.
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    /// The name logged when the rule denies a request.
+    pub name: String,
+    pub effect: Effect,
+    /// The actions, `*` matching any action and `reports:*` any action starting with `reports:`.
+    pub actions: Vec<String>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+/// A test on an attribute of the request, like `{ "attribute": "context.hour", "less_or_equal": 18 }`.
+#[derive(Debug, Deserialize)]
+pub struct Condition {
+    /// The dotted path of the attribute, under `principal`, `action`, `resource` or `context`.
+    pub attribute: String,
+    #[serde(flatten)]
+    pub test: Test,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Test {
+    Equals(Operand),
+    NotEquals(Operand),
+    /// The attribute is one of the values of an array.
+    In(Operand),
+    /// The attribute is an array holding the value, or a string holding it.
+    Contains(Operand),
+    GreaterOrEqual(Operand),
+    LessOrEqual(Operand),
+    /// The attribute is an IP address in a network, like `10.0.0.0/8`.
+    InCidr(Cidr),
+    /// Whether the attribute is set.
+    Exists(bool),
+}
+
+/// What an attribute is tested against.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Operand {
+    /// The value of another attribute, like `{ "attribute": "principal.sub" }`.
+    Attribute {
+        attribute: String,
+    },
+    Value(Value),
+}
+
+/// An IP network, checked when the policies are loaded.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid network `{value}`");
+        let (network, prefix) = value.split_once('/').ok_or_else(invalid)?;
+        let network = network.parse::<IpAddr>().map_err(|_| invalid())?;
+        let prefix = prefix.parse::<u32>().map_err(|_| invalid())?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { network, prefix })
+    }
+}
+
+impl Cidr {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The rules of the policy file.
+///
+/// A request is denied when a `deny` rule matches, allowed when an `allow` rule matches, and
+/// denied when no rule does.
+#[derive(Debug, Default, Deserialize)]
+pub struct PolicySet {
+    pub rules: Vec<Rule>,
+}
+
+/// The outcome of the policies for a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    pub effect: Effect,
+    /// The rule that matched, `None` when the request is denied because no rule did.
+    pub rule: Option<String>,
+}
+
+/// When and from where a request is made.
+#[derive(Debug, Clone)]
+pub struct PolicyContext {
+    pub ip: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+impl PolicyContext {
+    /// The context of a request made now by a client.
+    pub fn new(client: &ClientInfo) -> Self {
+        Self {
+            ip: client.ip.clone(),
+            time: Utc::now(),
+        }
+    }
+}
+
+fn lookup<'a>(attributes: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(attributes, |value, key| value.get(key))
+        .filter(|value| !value.is_null())
+}
+
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64()?.partial_cmp(&right.as_f64()?),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        _ => None,
+    }
+}
+
+impl Operand {
+    fn resolve<'a>(&'a self, attributes: &'a Value) -> Option<&'a Value> {
+        match self {
+            Operand::Attribute { attribute } => lookup(attributes, attribute),
+            Operand::Value(value) => Some(value),
+        }
+    }
+}
+
+fn contains(value: &Value, operand: &Value) -> bool {
+    match (value, operand) {
+        (Value::Array(values), operand) => values.contains(operand),
+        (Value::String(value), Value::String(operand)) => value.contains(operand.as_str()),
+        _ => false,
+    }
+}
+
+impl Condition {
+    fn holds(&self, attributes: &Value) -> bool {
+        let value = lookup(attributes, &self.attribute);
+        let test_with = |operand: &Operand, test: fn(&Value, &Value) -> bool| match (
+            value,
+            operand.resolve(attributes),
+        ) {
+            (Some(value), Some(operand)) => test(value, operand),
+            _ => false,
+        };
+        match &self.test {
+            Test::Equals(operand) => test_with(operand, |value, operand| value == operand),
+            Test::NotEquals(operand) => test_with(operand, |value, operand| value != operand),
+            Test::In(operand) => test_with(operand, |value, operand| {
+                operand
+                    .as_array()
+                    .is_some_and(|values| values.contains(value))
+            }),
+            Test::Contains(operand) => test_with(operand, contains),
+            Test::GreaterOrEqual(operand) => test_with(operand, |value, operand| {
+                compare(value, operand).is_some_and(Ordering::is_ge)
+            }),
+            Test::LessOrEqual(operand) => test_with(operand, |value, operand| {
+                compare(value, operand).is_some_and(Ordering::is_le)
+            }),
+            Test::InCidr(cidr) => value
+                .and_then(Value::as_str)
+                .and_then(|ip| ip.parse::<IpAddr>().ok())
+                .is_some_and(|ip| cidr.contains(ip)),
+            Test::Exists(exists) => value.is_some() == *exists,
+        }
+    }
+}
+
+impl Rule {
+    fn applies(&self, action: &str, attributes: &Value) -> bool {
+        let action_matches = self
+            .actions
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => action.starts_with(prefix),
+                None => pattern == action,
+            });
+        action_matches
+            && self
+                .conditions
+                .iter()
+                .all(|condition| condition.holds(attributes))
+    }
+}
+
+impl PolicySet {
+    /// Decide whether a principal can perform an action on a resource with the given attributes.
+    pub fn evaluate(
+        &self,
+        principal: &Principal,
+        action: &str,
+        resource: &Value,
+        context: &PolicyContext,
+    ) -> Decision {
+        let attributes = json!({
+            "principal": principal,
+            "action": action,
+            "resource": resource,
+            "context": {
+                "ip": context.ip,
+                "time": context.time.to_rfc3339(),
+                "hour": context.time.hour(),
+                "weekday": context.time.weekday().to_string().to_lowercase(),
+            },
+        });
+        let mut allowed_by = None;
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| rule.applies(action, &attributes))
+        {
+            match rule.effect {
+                Effect::Deny => {
+                    return Decision {
+                        effect: Effect::Deny,
+                        rule: Some(rule.name.clone()),
+                    }
+                }
+                Effect::Allow => {
+                    allowed_by.get_or_insert(rule.name.clone());
+                }
+            }
+        }
+        Decision {
+            effect: match allowed_by {
+                Some(_) => Effect::Allow,
+                None => Effect::Deny,
+            },
+            rule: allowed_by,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("Failed to read `{0}`: {1}")]
+    Read(String, std::io::Error),
+    #[error("Failed to parse `{0}`: {1}")]
+    Parse(String, serde_json::Error),
+}
+
+fn read_policies(path: &Path) -> Result<PolicySet, PolicyError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| PolicyError::Read(path.display().to_string(), err))?;
+    serde_json::from_str(&content)
+        .map_err(|err| PolicyError::Parse(path.display().to_string(), err))
+}
+
+/// The policies loaded from the policy file, swapped when the file changes.
+#[derive(Clone, Debug, Default)]
+pub struct PolicyStore {
+    policies: Arc<RwLock<Arc<PolicySet>>>,
+}
+
+impl PolicyStore {
+    /// Load the policies of a file, denying every request checked against them when it doesn't
+    /// exist.
+    pub fn load(path: &Path) -> Result<Self, PolicyError> {
+        let store = Self::default();
+        if path.exists() {
+            store.replace(read_policies(path)?);
+        } else {
+            tracing::warn!(
+                "No policy file at `{}`, policies deny every request",
+                path.display()
+            );
+        }
+        Ok(store)
+    }
+
+    /// The policies currently loaded.
+    pub fn current(&self) -> Arc<PolicySet> {
+        self.policies
+            .read()
+            .map(|policies| policies.clone())
+            .unwrap_or_default()
+    }
+
+    fn replace(&self, policies: PolicySet) {
+        if let Ok(mut current) = self.policies.write() {
+            *current = Arc::new(policies);
+        }
+    }
+
+    /// Reload the policies when the file was modified after `since`, returning its modification
+    /// time. An invalid file is logged and the previous policies are kept.
+    pub fn reload_if_modified(&self, path: &Path, since: Option<SystemTime>) -> Option<SystemTime> {
+        let modified = std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified.is_none() || modified == since {
+            return since;
+        }
+        match read_policies(path) {
+            Ok(policies) => {
+                tracing::info!(
+                    "Reloaded {} policy rules from `{}`",
+                    policies.rules.len(),
+                    path.display()
+                );
+                self.replace(policies);
+            }
+            Err(err) => tracing::warn!("Kept the previous policies: {err}"),
+        }
+        modified
+    }
+
+    /// Watch the policy file in the background, reloading it when it changes.
+    pub fn spawn_reload(&self, path: PathBuf) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut modified = std::fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .ok();
+            loop {
+                tokio::time::sleep(RELOAD_INTERVAL).await;
+                modified = store.reload_if_modified(&path, modified);
+            }
+        });
+    }
+}
+
+/// Check that a principal can perform an action on a resource, from handlers.
+///
+/// Denials are logged along with the rule that matched, and rejected with `Forbidden`.
+pub fn enforce_policy(
+    state: &RouterState,
+    principal: &Principal,
+    action: &str,
+    resource: &Value,
+    context: &PolicyContext,
+) -> ApiResult<()> {
+    let decision = state
+        .policies
+        .current()
+        .evaluate(principal, action, resource, context);
+    match decision.effect {
+        Effect::Allow => Ok(()),
+        Effect::Deny => {
+            tracing::warn!(
+                sub = %principal.sub,
+                action,
+                ip = context.ip.as_deref().unwrap_or("unknown"),
+                rule = decision.rule.as_deref().unwrap_or("no matching rule"),
+                "Policy denied the request"
+            );
+            Err(BackendError::Forbidden)
+        }
+    }
+}
+
+/// A guard checking the policies for an action, the resource being the request itself: its
+/// `method`, `path` and path `params`.
+///
+/// ```ignore
+/// .route("/bearer/reports/:report_id", get(handler).route_layer(GuardLayer::new(&state, RequirePolicy("reports:read"))))
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RequirePolicy(pub &'static str);
+
+#[async_trait]
+impl Guard for RequirePolicy {
+    async fn check(&self, state: &RouterState, parts: &mut Parts) -> ApiResult<()> {
+        let principal = Principal::from_request_parts(parts, state).await?;
+        let Ok(client) = ClientInfo::from_request_parts(parts, state).await;
+        let params: Map<String, Value> = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), Value::from(value)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let resource = json!({
+            "method": parts.method.as_str(),
+            "path": parts.uri.path(),
+            "params": params,
+        });
+        enforce_policy(
+            state,
+            &principal,
+            self.0,
+            &resource,
+            &PolicyContext::new(&client),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::bearer_jwt::PrincipalKind;
+
+    const POLICIES: &str = r#"{
+        "rules": [
+            {
+                "name": "deny-documentation-network",
+                "effect": "deny",
+                "actions": ["*"],
+                "conditions": [{ "attribute": "context.ip", "in_cidr": "192.0.2.0/24" }]
+            },
+            {
+                "name": "owners-edit-documents",
+                "effect": "allow",
+                "actions": ["documents:*"],
+                "conditions": [
+                    { "attribute": "resource.owner", "equals": { "attribute": "principal.sub" } },
+                    { "attribute": "resource.status", "in": ["draft", "review"] }
+                ]
+            },
+            {
+                "name": "services-read-reports",
+                "effect": "allow",
+                "actions": ["reports:read"],
+                "conditions": [
+                    { "attribute": "principal.kind", "equals": "service" },
+                    { "attribute": "principal.scopes", "contains": "reports:read" },
+                    { "attribute": "context.hour", "greater_or_equal": 0 }
+                ]
+            }
+        ]
+    }"#;
+
+    fn user(sub: &str) -> Principal {
+        Principal {
+            sub: sub.to_string(),
+            kind: PrincipalKind::User,
+            scopes: None,
+            org_id: None,
+        }
+    }
+
+    fn context(ip: &str) -> PolicyContext {
+        PolicyContext {
+            ip: Some(ip.to_string()),
+            time: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn policies_allow_matching_rules_and_deny_by_default() {
+        let policies: PolicySet = serde_json::from_str(POLICIES).unwrap();
+        let draft = json!({ "owner": "user:alice", "status": "draft" });
+
+        let decision = policies.evaluate(
+            &user("user:alice"),
+            "documents:edit",
+            &draft,
+            &context("10.0.0.1"),
+        );
+        assert_eq!(decision.effect, Effect::Allow);
+        assert_eq!(decision.rule.as_deref(), Some("owners-edit-documents"));
+
+        let decision = policies.evaluate(
+            &user("user:bob"),
+            "documents:edit",
+            &draft,
+            &context("10.0.0.1"),
+        );
+        assert_eq!(
+            decision,
+            Decision {
+                effect: Effect::Deny,
+                rule: None
+            }
+        );
+
+        let published = json!({ "owner": "user:alice", "status": "published" });
+        let decision = policies.evaluate(
+            &user("user:alice"),
+            "documents:edit",
+            &published,
+            &context("10.0.0.1"),
+        );
+        assert_eq!(decision.effect, Effect::Deny);
+
+        let service = Principal {
+            sub: "service_account:reports".to_string(),
+            kind: PrincipalKind::Service,
+            scopes: Some(vec!["reports:read".to_string()]),
+            org_id: None,
+        };
+        let decision =
+            policies.evaluate(&service, "reports:read", &Value::Null, &context("10.0.0.1"));
+        assert_eq!(decision.effect, Effect::Allow);
+        let decision = policies.evaluate(
+            &user("user:alice"),
+            "reports:read",
+            &Value::Null,
+            &context("10.0.0.1"),
+        );
+        assert_eq!(decision.effect, Effect::Deny);
+    }
+
+    #[test]
+    fn policies_of_the_repository_are_valid() {
+        let policies = read_policies(Path::new("policies.json")).unwrap();
+        assert!(!policies.rules.is_empty());
+    }
+
+    #[test]
+    fn policies_deny_rules_take_precedence() {
+        let policies: PolicySet = serde_json::from_str(POLICIES).unwrap();
+        let draft = json!({ "owner": "user:alice", "status": "draft" });
+        let decision = policies.evaluate(
+            &user("user:alice"),
+            "documents:edit",
+            &draft,
+            &context("192.0.2.7"),
+        );
+        assert_eq!(
+            decision,
+            Decision {
+                effect: Effect::Deny,
+                rule: Some("deny-documentation-network".to_string())
+            }
+        );
+
+        let invalid = r#"{ "rules": [{ "name": "x", "effect": "deny", "actions": ["*"], "conditions": [{ "attribute": "context.ip", "in_cidr": "10.0.0.0/33" }] }] }"#;
+        assert!(serde_json::from_str::<PolicySet>(invalid).is_err());
+    }
+
+    #[test]
+    fn policies_reload_when_the_file_changes() {
+        let path = std::env::temp_dir().join(format!("policies-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{ "rules": [] }"#).unwrap();
+        let store = PolicyStore::load(&path).unwrap();
+        let modified = std::fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        assert!(store.current().rules.is_empty());
+
+        std::fs::write(&path, POLICIES).unwrap();
+        let reloaded =
+            store.reload_if_modified(&path, modified.map(|time| time - Duration::from_secs(1)));
+        assert_eq!(store.current().rules.len(), 3);
+
+        std::fs::write(&path, "not json").unwrap();
+        store.reload_if_modified(&path, reloaded.map(|time| time - Duration::from_secs(1)));
+        assert_eq!(
+            store.current().rules.len(),
+            3,
+            "An invalid file should be ignored"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use super::api_key::{BearerOrApiKeyClaims, API_KEY_HEADER};
+use super::bearer_jwt::{BearerJWTClaims, PrincipalKind};
+use super::cookie_jwt::CookieJWTClaims;
+use crate::{BackendError, RouterState};
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use serde::Serialize;
+
+/// Who is making a request, whatever the way it authenticated.
+#[derive(Debug, Clone, Serialize)]
+pub struct Principal {
+    /// The id of the user or the service account.
+    pub sub: String,
+    pub kind: PrincipalKind,
+    /// The scopes the token is limited to, `None` when it isn't limited.
+    pub scopes: Option<Vec<String>>,
+    /// The organization the token acts in.
+    pub org_id: Option<String>,
+}
+
+impl Principal {
+    /// Whether the token of the principal was granted a scope.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes
+            .as_ref()
+            .is_some_and(|scopes| scopes.iter().any(|granted| granted == scope))
+    }
+}
+
+impl From<BearerJWTClaims> for Principal {
+    fn from(claims: BearerJWTClaims) -> Self {
+        Self {
+            sub: claims.sub,
+            kind: claims.principal,
+            scopes: claims
+                .scope
+                .map(|scope| scope.split_whitespace().map(str::to_string).collect()),
+            org_id: claims.org_id,
+        }
+    }
+}
+
+impl From<CookieJWTClaims> for Principal {
+    fn from(claims: CookieJWTClaims) -> Self {
+        Self {
+            sub: claims.sub,
+            kind: PrincipalKind::User,
+            scopes: None,
+            org_id: claims.org_id,
+        }
+    }
+}
+
+/// Authenticate with a bearer token or an API key when one is sent, and with the jwt cookie
+/// otherwise, which requires the `cookie_jwt_bearer_resolver` layer.
+#[async_trait]
+impl<S> FromRequestParts<S> for Principal
+where
+    RouterState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = BackendError;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if parts.headers.contains_key(AUTHORIZATION) || parts.headers.contains_key(API_KEY_HEADER) {
+            let BearerOrApiKeyClaims(claims) =
+                BearerOrApiKeyClaims::from_request_parts(parts, state).await?;
+            Ok(claims.into())
+        } else {
+            let claims = CookieJWTClaims::from_request_parts(parts, state).await?;
+            Ok(claims.into())
+        }
+    }
+}
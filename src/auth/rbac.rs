@@ -0,0 +1,69 @@
+use super::bearer_jwt::PrincipalKind;
+use super::guard::Guard;
+use super::principal::Principal;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use surrealdb::sql::Thing;
+
+/// List the permissions a user is granted through its roles.
+pub async fn user_permissions(state: &RouterState, user_id: &Thing) -> ApiResult<Vec<String>> {
+    let mut result = state
+        .db
+        .query(
+            "let $roles = select value out from has_role where in=$user_id;
+            array::distinct(array::flatten(select value permissions.name from $roles));",
+        )
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let permissions: Option<Vec<String>> = result
+        .take(result.num_statements() - 1)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(permissions.unwrap_or_default())
+}
+
+/// Check that a principal holds a permission, rejecting it with `Forbidden` otherwise.
+///
+/// Users need a role granting it, and a token limited to scopes, like an API key, must also
+/// have it in its scopes. Service accounts have no roles and are only granted their scopes.
+pub async fn check_permission(
+    state: &RouterState,
+    principal: &Principal,
+    permission: &str,
+) -> ApiResult<()> {
+    let in_scope = principal.has_scope(permission);
+    if principal.kind == PrincipalKind::Service {
+        return in_scope.then_some(()).ok_or(BackendError::Forbidden);
+    }
+    if principal.scopes.is_some() && !in_scope {
+        return Err(BackendError::Forbidden);
+    }
+    let user_id = principal
+        .sub
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let permissions = user_permissions(state, &user_id).await?;
+    if permissions.iter().any(|granted| granted == permission) {
+        Ok(())
+    } else {
+        Err(BackendError::Forbidden)
+    }
+}
+
+/// A guard rejecting with `Forbidden` the principals without a permission.
+///
+/// ```ignore
+/// .route("/bearer/admin", get(handler).route_layer(GuardLayer::new(&state, RequirePermission("users:read"))))
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RequirePermission(pub &'static str);
+
+#[async_trait]
+impl Guard for RequirePermission {
+    async fn check(&self, state: &RouterState, parts: &mut Parts) -> ApiResult<()> {
+        let principal = Principal::from_request_parts(parts, state).await?;
+        check_permission(state, &principal, self.0).await
+    }
+}
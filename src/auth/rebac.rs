@@ -0,0 +1,424 @@
+use super::bearer_jwt::PrincipalKind;
+use super::principal::Principal;
+use crate::{ApiResult, BackendError, RouterState};
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts, RawPathParams};
+use axum::http::request::Parts;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use surrealdb::sql::Thing;
+
+/// How deep group memberships and parent folders are followed.
+const MAX_DEPTH: usize = 8;
+
+/// A relation of a relation tuple `subject->relation->object`, stored as a graph edge.
+///
+/// `owner` implies `editor`, which implies `viewer`, and the relations on a folder are inherited
+/// by what the folder is the `parent` of. Subjects are users, or groups whose members, direct or
+/// through nested groups, have the relation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Relation {
+    Owner,
+    Editor,
+    Viewer,
+    /// The membership of a user or a group in a group.
+    Member,
+}
+
+impl Relation {
+    /// The edge table of the relation.
+    fn table(self) -> &'static str {
+        match self {
+            Relation::Owner => "owner",
+            Relation::Editor => "editor",
+            Relation::Viewer => "viewer",
+            Relation::Member => "member_of",
+        }
+    }
+
+    /// The relation directly implying this one.
+    fn implied_by(self) -> Option<Relation> {
+        match self {
+            Relation::Viewer => Some(Relation::Editor),
+            Relation::Editor => Some(Relation::Owner),
+            Relation::Owner | Relation::Member => None,
+        }
+    }
+
+    /// The edge tables granting the relation on an object.
+    fn granting_tables(self) -> Vec<&'static str> {
+        std::iter::successors(Some(self), |relation| relation.implied_by())
+            .map(Relation::table)
+            .collect()
+    }
+}
+
+/// Follow the edges from a set of records, level by level, until no new record is found.
+async fn follow(state: &RouterState, query: &str, start: Vec<Thing>) -> ApiResult<Vec<Thing>> {
+    let mut found: Vec<Thing> = Vec::new();
+    let mut frontier = start;
+    for _ in 0..MAX_DEPTH {
+        if frontier.is_empty() {
+            break;
+        }
+        let mut result = state
+            .db
+            .query(query)
+            .bind(("frontier", frontier))
+            .await
+            .map_err(|_| BackendError::SomethingWentWrong)?;
+        let next: Vec<Thing> = result
+            .take(0)
+            .map_err(|_| BackendError::SomethingWentWrong)?;
+        frontier = next
+            .into_iter()
+            .filter(|record| !found.contains(record))
+            .collect();
+        found.extend(frontier.iter().cloned());
+    }
+    Ok(found)
+}
+
+/// The groups a subject is a member of, directly or through nested groups.
+async fn groups_of(state: &RouterState, subject: &Thing) -> ApiResult<Vec<Thing>> {
+    follow(
+        state,
+        "array::distinct(select value out from member_of where in inside $frontier)",
+        vec![subject.clone()],
+    )
+    .await
+}
+
+/// The folders an object is in, directly or through parent folders.
+async fn parents_of(state: &RouterState, object: &Thing) -> ApiResult<Vec<Thing>> {
+    follow(
+        state,
+        "array::distinct(select value in from parent where out inside $frontier)",
+        vec![object.clone()],
+    )
+    .await
+}
+
+/// Check whether a subject has a relation to an object, following group memberships, implied
+/// relations and parent folders.
+pub async fn check(
+    state: &RouterState,
+    subject: &Thing,
+    relation: Relation,
+    object: &Thing,
+) -> ApiResult<bool> {
+    let mut subjects = groups_of(state, subject).await?;
+    if relation == Relation::Member {
+        return Ok(subjects.contains(object));
+    }
+    subjects.push(subject.clone());
+    let mut objects = parents_of(state, object).await?;
+    objects.push(object.clone());
+
+    let mut result = state
+        .db
+        .query(format!(
+            "select value id from {} where in inside $subjects and out inside $objects limit 1",
+            relation.granting_tables().join(", ")
+        ))
+        .bind(("subjects", subjects))
+        .bind(("objects", objects))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let tuples: Vec<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(!tuples.is_empty())
+}
+
+/// A subject of a relation tuple, with the members of the groups.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Subject {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<Subject>,
+}
+
+/// How the subjects having a relation to an object get it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsersetTree {
+    pub relation: Relation,
+    pub object: String,
+    /// The subjects of the tuples `subject->relation->object`.
+    pub subjects: Vec<Subject>,
+    /// The trees of the implying relation and of the parent folders.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<UsersetTree>,
+}
+
+impl UsersetTree {
+    /// The users found anywhere in the tree.
+    pub fn users(&self) -> Vec<String> {
+        fn collect(subjects: &[Subject], users: &mut Vec<String>) {
+            for subject in subjects {
+                if subject.id.starts_with("user:") && !users.contains(&subject.id) {
+                    users.push(subject.id.clone());
+                }
+                collect(&subject.members, users);
+            }
+        }
+        let mut users = Vec::new();
+        collect(&self.subjects, &mut users);
+        for child in &self.children {
+            for user in child.users() {
+                if !users.contains(&user) {
+                    users.push(user);
+                }
+            }
+        }
+        users
+    }
+}
+
+async fn related_subjects(
+    state: &RouterState,
+    relation: Relation,
+    object: &Thing,
+) -> ApiResult<Vec<Thing>> {
+    let mut result = state
+        .db
+        .query(format!(
+            "select value in from {} where out=$object",
+            relation.table()
+        ))
+        .bind(("object", object.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    result.take(0).map_err(|_| BackendError::SomethingWentWrong)
+}
+
+fn expand_subject<'a>(
+    state: &'a RouterState,
+    subject: Thing,
+    depth: usize,
+) -> Pin<Box<dyn Future<Output = ApiResult<Subject>> + Send + 'a>> {
+    Box::pin(async move {
+        let mut members = Vec::new();
+        if subject.tb == "group" && depth < MAX_DEPTH {
+            for member in related_subjects(state, Relation::Member, &subject).await? {
+                members.push(expand_subject(state, member, depth + 1).await?);
+            }
+        }
+        Ok(Subject {
+            id: subject.to_string(),
+            members,
+        })
+    })
+}
+
+fn expand_tree<'a>(
+    state: &'a RouterState,
+    relation: Relation,
+    object: Thing,
+    depth: usize,
+) -> Pin<Box<dyn Future<Output = ApiResult<UsersetTree>> + Send + 'a>> {
+    Box::pin(async move {
+        let mut subjects = Vec::new();
+        for subject in related_subjects(state, relation, &object).await? {
+            subjects.push(expand_subject(state, subject, 0).await?);
+        }
+        let mut children = Vec::new();
+        if let Some(implying) = relation.implied_by() {
+            children.push(expand_tree(state, implying, object.clone(), depth).await?);
+        }
+        if relation != Relation::Member && depth < MAX_DEPTH {
+            let mut result = state
+                .db
+                .query("select value in from parent where out=$object")
+                .bind(("object", object.clone()))
+                .await
+                .map_err(|_| BackendError::SomethingWentWrong)?;
+            let parents: Vec<Thing> = result
+                .take(0)
+                .map_err(|_| BackendError::SomethingWentWrong)?;
+            for parent in parents {
+                children.push(expand_tree(state, relation, parent, depth + 1).await?);
+            }
+        }
+        Ok(UsersetTree {
+            relation,
+            object: object.to_string(),
+            subjects,
+            children,
+        })
+    })
+}
+
+/// Expand the subjects having a relation to an object into the tree of how they get it.
+pub async fn expand(
+    state: &RouterState,
+    relation: Relation,
+    object: &Thing,
+) -> ApiResult<UsersetTree> {
+    expand_tree(state, relation, object.clone(), 0).await
+}
+
+/// Write the relation tuple `subject->relation->object`, doing nothing when it already exists.
+pub async fn write_tuple(
+    state: &RouterState,
+    subject: &Thing,
+    relation: Relation,
+    object: &Thing,
+) -> ApiResult<()> {
+    state
+        .db
+        .query(format!(
+            "if !(select value id from {table} where in=$subject and out=$object) {{ relate $subject->{table}->$object }}",
+            table = relation.table()
+        ))
+        .bind(("subject", subject.clone()))
+        .bind(("object", object.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| {
+            BackendError::ValidationFailed(format!(
+                "`{subject}` can't have this relation to `{object}`"
+            ))
+        })?;
+    Ok(())
+}
+
+/// Delete the relation tuple `subject->relation->object`, returning `NotFound` without it.
+pub async fn delete_tuple(
+    state: &RouterState,
+    subject: &Thing,
+    relation: Relation,
+    object: &Thing,
+) -> ApiResult<()> {
+    let mut result = state
+        .db
+        .query(format!(
+            "delete {} where in=$subject and out=$object return before",
+            relation.table()
+        ))
+        .bind(("subject", subject.clone()))
+        .bind(("object", object.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let deleted: Vec<serde_json::Value> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if deleted.is_empty() {
+        Err(BackendError::NotFound)
+    } else {
+        Ok(())
+    }
+}
+
+/// A relation a route requires, for `Authorized`.
+pub trait RequiredRelation {
+    const RELATION: Relation;
+    /// The scope a token limited to scopes, like an API key, also needs.
+    const SCOPE: &'static str;
+}
+
+pub struct CanView;
+pub struct CanEdit;
+pub struct IsOwner;
+
+impl RequiredRelation for CanView {
+    const RELATION: Relation = Relation::Viewer;
+    const SCOPE: &'static str = "docs:read";
+}
+
+impl RequiredRelation for CanEdit {
+    const RELATION: Relation = Relation::Editor;
+    const SCOPE: &'static str = "docs:write";
+}
+
+impl RequiredRelation for IsOwner {
+    const RELATION: Relation = Relation::Owner;
+    const SCOPE: &'static str = "docs:share";
+}
+
+/// A kind of resource a route acts on, named by a path parameter, for `Authorized`.
+pub trait Resource {
+    const TABLE: &'static str;
+    /// The path parameter holding the id of the resource, with or without its table.
+    const PARAM: &'static str;
+}
+
+pub struct Doc;
+pub struct Folder;
+
+impl Resource for Doc {
+    const TABLE: &'static str = "doc";
+    const PARAM: &'static str = "doc_id";
+}
+
+impl Resource for Folder {
+    const TABLE: &'static str = "folder";
+    const PARAM: &'static str = "folder_id";
+}
+
+/// An extractor rejecting with `Forbidden` the users without a relation to the resource of the
+/// path, like `Authorized<CanView, Doc>` for `/docs/:doc_id`.
+///
+/// Missing resources are refused the same way, so their existence isn't revealed, and so are
+/// tokens limited to scopes without the `SCOPE` of the relation.
+#[derive(Debug)]
+pub struct Authorized<R, O> {
+    /// The user making the request.
+    pub subject: Thing,
+    pub object: Thing,
+    marker: PhantomData<(R, O)>,
+}
+
+#[async_trait]
+impl<S, R, O> FromRequestParts<S> for Authorized<R, O>
+where
+    RouterState: FromRef<S>,
+    S: Send + Sync,
+    R: RequiredRelation,
+    O: Resource,
+{
+    type Rejection = BackendError;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let principal = Principal::from_request_parts(parts, state).await?;
+        if principal.kind != PrincipalKind::User {
+            return Err(BackendError::Forbidden);
+        }
+        if principal.scopes.is_some() && !principal.has_scope(R::SCOPE) {
+            return Err(BackendError::Forbidden);
+        }
+        let params = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map_err(|_| BackendError::NotFound)?;
+        let id = params
+            .iter()
+            .find(|(key, _)| *key == O::PARAM)
+            .map(|(_, value)| value.to_string())
+            .ok_or(BackendError::NotFound)?;
+        let id = match id.split_once(':') {
+            Some(_) => id,
+            None => format!("{}:{id}", O::TABLE),
+        };
+        let object = id.parse::<Thing>().map_err(|_| BackendError::NotFound)?;
+        if object.tb != O::TABLE {
+            return Err(BackendError::NotFound);
+        }
+        let subject = principal
+            .sub
+            .parse::<Thing>()
+            .map_err(|_| BackendError::InvalidToken)?;
+
+        let state = RouterState::from_ref(state);
+        if !check(&state, &subject, R::RELATION, &object).await? {
+            return Err(BackendError::Forbidden);
+        }
+        Ok(Self {
+            subject,
+            object,
+            marker: PhantomData,
+        })
+    }
+}
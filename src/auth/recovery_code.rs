@@ -0,0 +1,123 @@
+use super::client_info::ClientInfo;
+use super::token::hash_token;
+use crate::{ApiResult, BackendError, RouterState};
+use rand::rngs::OsRng;
+use rand::Rng;
+use surrealdb::sql::Thing;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Crockford's base32 alphabet, which leaves out `i`, `l`, `o` and `u`.
+const RECOVERY_CODE_ALPHABET: &[u8] = b"0123456789abcdefghjkmnpqrstvwxyz";
+/// 16 characters of 5 bits each, written as 4 groups of 4.
+const RECOVERY_CODE_LEN: usize = 16;
+
+fn generate_recovery_code() -> String {
+    let mut code = String::with_capacity(RECOVERY_CODE_LEN + 3);
+    for i in 0..RECOVERY_CODE_LEN {
+        if i > 0 && i % 4 == 0 {
+            code.push('-');
+        }
+        let index = OsRng.gen_range(0..RECOVERY_CODE_ALPHABET.len());
+        code.push(RECOVERY_CODE_ALPHABET[index] as char);
+    }
+    code
+}
+
+/// Hash a recovery code the way it was typed, ignoring the case, the dashes and the spaces, and
+/// reading the letters mistaken for digits as those digits.
+fn hash_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'o' => '0',
+            'i' | 'l' => '1',
+            c => c,
+        })
+        .collect();
+    hash_token(&code)
+}
+
+/// Replace the recovery codes of a user with a new set, returning the codes to show it once.
+///
+/// Used codes are kept as a record of when and by which client they were used.
+pub async fn generate_recovery_codes(
+    state: &RouterState,
+    user_id: &Thing,
+) -> ApiResult<Vec<String>> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let code_hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    state
+        .db
+        .query(
+            "begin transaction;
+            delete recovery_code where user=$user_id and used_at=none;
+            for $code_hash in $code_hashes {
+                create recovery_code set user=$user_id, code_hash=$code_hash;
+            };
+            commit transaction;",
+        )
+        .bind(("user_id", user_id.clone()))
+        .bind(("code_hashes", code_hashes))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(codes)
+}
+
+/// Count the recovery codes a user can still use.
+pub async fn count_recovery_codes(state: &RouterState, user_id: &Thing) -> ApiResult<usize> {
+    let mut result = state
+        .db
+        .query("count(select id from recovery_code where user=$user_id and used_at=none)")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let count: Option<usize> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(count.unwrap_or_default())
+}
+
+/// Give a user its first recovery codes when it enrolls a second factor.
+///
+/// Returns `None` when it already has some, which another factor keeps valid.
+pub async fn ensure_recovery_codes(
+    state: &RouterState,
+    user_id: &Thing,
+) -> ApiResult<Option<Vec<String>>> {
+    if count_recovery_codes(state, user_id).await? > 0 {
+        return Ok(None);
+    }
+    generate_recovery_codes(state, user_id).await.map(Some)
+}
+
+/// Use a recovery code of a user in place of a second factor, recording the client using it.
+pub async fn use_recovery_code(
+    state: &RouterState,
+    user_id: &Thing,
+    code: &str,
+    client: &ClientInfo,
+) -> ApiResult<()> {
+    let mut result = state
+        .db
+        .query("update recovery_code set used_at=time::now(), used_ip=$ip, used_user_agent=$user_agent where code_hash=$code_hash and user=$user_id and used_at=none return value id")
+        .bind(("code_hash", hash_recovery_code(code)))
+        .bind(("user_id", user_id.clone()))
+        .bind(("ip", client.ip.clone()))
+        .bind(("user_agent", client.user_agent.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let used: Vec<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if used.is_empty() {
+        Err(BackendError::InvalidMfaCode)
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use super::organization::session_organization;
+use super::security_stamp::{load_token_subject, TokenSubject};
+use super::session::{extend_session, touch_session, SessionKind};
+use super::token::{generate_token, hash_token};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Deserialize)]
+struct DBRefreshToken {
+    id: Thing,
+    user: Thing,
+    family: String,
+    session: Option<Thing>,
+    security_stamp: String,
+    expired: bool,
+    used: bool,
+    revoked: bool,
+}
+
+/// Issue an opaque refresh token, starting a new token family unless one is given.
+pub async fn issue_refresh_token(
+    state: &RouterState,
+    subject: &TokenSubject,
+    family: Option<String>,
+) -> ApiResult<String> {
+    let user_id = subject
+        .user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let session_id = subject
+        .session_id
+        .as_deref()
+        .map(str::parse::<Thing>)
+        .transpose()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let token = generate_token();
+    state
+        .db
+        .query(format!(
+            "create refresh_token set user=$user_id, family=$family ?? rand::uuid(), session=$session_id, token_hash=$token_hash, security_stamp=$security_stamp, expires_at=time::now() + {}d",
+            env_config().refresh_token_ttl
+        ))
+        .bind(("user_id", user_id))
+        .bind(("family", family))
+        .bind(("session_id", session_id))
+        .bind(("token_hash", hash_token(&token)))
+        .bind(("security_stamp", subject.security_stamp.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(token)
+}
+
+/// Exchange a refresh token for a new one of the same family.
+///
+/// A refresh token can only be used once: presenting it again means it leaked, so the whole
+/// family is revoked. Returns the subject to issue the new access token with.
+pub async fn rotate_refresh_token(
+    state: &RouterState,
+    token: &str,
+) -> ApiResult<(TokenSubject, String)> {
+    let mut result = state
+        .db
+        .query("select id, user, family, session, security_stamp, expires_at<time::now() as expired, used_at!=none as used, revoked_at!=none as revoked from refresh_token where token_hash=$token_hash")
+        .bind(("token_hash", hash_token(token)))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let refresh_token: Option<DBRefreshToken> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let refresh_token = refresh_token.ok_or(BackendError::InvalidToken)?;
+
+    if refresh_token.revoked || refresh_token.used {
+        tracing::warn!(
+            "Refresh token reuse detected for `{}`, revoking its family",
+            refresh_token.user
+        );
+        revoke_refresh_token_family(state, &refresh_token.family).await?;
+        return Err(BackendError::InvalidToken);
+    }
+    if refresh_token.expired {
+        return Err(BackendError::InvalidToken);
+    }
+
+    let mut subject = load_token_subject(state, &refresh_token.user).await?;
+    if subject.security_stamp != refresh_token.security_stamp {
+        revoke_refresh_token_family(state, &refresh_token.family).await?;
+        return Err(BackendError::InvalidToken);
+    }
+    if let Some(session_id) = &refresh_token.session {
+        let session_id = session_id.to_string();
+        if touch_session(state, &session_id).await.is_err() {
+            revoke_refresh_token_family(state, &refresh_token.family).await?;
+            return Err(BackendError::InvalidToken);
+        }
+        let org_id = session_organization(state, &session_id).await?;
+        subject = subject.with_session(session_id).with_org(org_id);
+    }
+
+    // Only the request that flags the token as used may rotate it.
+    let mut result = state
+        .db
+        .query("update $id set used_at=time::now() where used_at=none and revoked_at=none return value id")
+        .bind(("id", refresh_token.id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let used: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if used.is_none() {
+        revoke_refresh_token_family(state, &refresh_token.family).await?;
+        return Err(BackendError::InvalidToken);
+    }
+
+    if let Some(session_id) = &subject.session_id {
+        extend_session(state, session_id, SessionKind::Bearer).await?;
+    }
+    let new_token = issue_refresh_token(state, &subject, Some(refresh_token.family)).await?;
+    Ok((subject, new_token))
+}
+
+/// Revoke every refresh token of a family.
+pub async fn revoke_refresh_token_family(state: &RouterState, family: &str) -> ApiResult<()> {
+    state
+        .db
+        .query("update refresh_token set revoked_at=time::now() where family=$family and revoked_at=none")
+        .bind(("family", family.to_string()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(())
+}
+
+/// Revoke the family of a refresh token, if it belongs to the given user.
+pub async fn revoke_refresh_token(
+    state: &RouterState,
+    token: &str,
+    user_id: &str,
+) -> ApiResult<()> {
+    let user_id = user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let mut result = state
+        .db
+        .query(
+            "select value family from refresh_token where token_hash=$token_hash and user=$user_id",
+        )
+        .bind(("token_hash", hash_token(token)))
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let family: Option<String> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    match family {
+        Some(family) => revoke_refresh_token_family(state, &family).await,
+        None => Ok(()),
+    }
+}
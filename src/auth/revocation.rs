@@ -0,0 +1,96 @@
+use crate::{ApiResult, BackendError};
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::Surreal;
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+struct DBRevokedToken {
+    jti: String,
+    exp: i64,
+}
+
+/// The revoked tokens, by `jti`.
+///
+/// Revocations are stored in the `revoked_token` table and cached in memory until the token
+/// would have expired anyway, so checking a token doesn't hit the database. The cache is
+/// periodically synced to pick up the revocations made by other instances.
+#[derive(Clone, Debug, Default)]
+pub struct RevocationStore {
+    revoked: Arc<RwLock<HashMap<String, i64>>>,
+}
+
+impl RevocationStore {
+    /// Load the revocations of the tokens that haven't expired yet.
+    pub async fn load(db: &Surreal<Client>) -> Result<Self, surrealdb::Error> {
+        let store = Self::default();
+        store.sync(db, 0).await?;
+        Ok(store)
+    }
+
+    /// Whether the token was revoked.
+    pub fn is_revoked(&self, jti: &str) -> bool {
+        self.revoked
+            .read()
+            .map(|revoked| revoked.contains_key(jti))
+            .unwrap_or(true)
+    }
+
+    /// Revoke a token until its expiration time, revoking it again being a no-op.
+    pub async fn revoke(&self, db: &Surreal<Client>, jti: &str, exp: usize) -> ApiResult<()> {
+        db.query(
+            "upsert revoked_token set jti=$jti, expires_at=time::from::unix($exp) where jti=$jti",
+        )
+        .bind(("jti", jti.to_string()))
+        .bind(("exp", exp as i64))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+        if let Ok(mut revoked) = self.revoked.write() {
+            revoked.insert(jti.to_string(), exp as i64);
+        }
+        Ok(())
+    }
+
+    /// Keep the cache in sync with the database in the background.
+    pub fn spawn_sync(&self, db: Surreal<Client>) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut since = Utc::now().timestamp();
+            loop {
+                tokio::time::sleep(SYNC_INTERVAL).await;
+                let now = Utc::now().timestamp();
+                match store
+                    .sync(&db, since - SYNC_INTERVAL.as_secs() as i64)
+                    .await
+                {
+                    Ok(()) => since = now,
+                    Err(err) => tracing::warn!("Failed to sync the revoked tokens: {err}"),
+                }
+            }
+        });
+    }
+
+    /// Add the revocations made since `since`, then drop the expired ones.
+    async fn sync(&self, db: &Surreal<Client>, since: i64) -> Result<(), surrealdb::Error> {
+        let mut result = db
+            .query("delete revoked_token where expires_at<time::now()")
+            .query("select jti, time::unix(expires_at) as exp from revoked_token where time::unix(created_at)>=$since")
+            .bind(("since", since))
+            .await?;
+        let tokens: Vec<DBRevokedToken> = result.take(1)?;
+
+        let now = Utc::now().timestamp();
+        if let Ok(mut revoked) = self.revoked.write() {
+            revoked.extend(tokens.into_iter().map(|token| (token.jti, token.exp)));
+            revoked.retain(|_, exp| *exp >= now);
+        }
+        Ok(())
+    }
+}
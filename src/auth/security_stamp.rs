@@ -0,0 +1,87 @@
+use crate::{ApiResult, BackendError, RouterState};
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+
+/// The user a token is issued for, along with its security stamp at that time, the session
+/// the token belongs to and the organization it acts in.
+///
+/// Rotating the `security_stamp` of a user invalidates every token issued with the previous one.
+#[derive(Debug, Clone)]
+pub struct TokenSubject {
+    pub user_id: String,
+    pub security_stamp: String,
+    pub session_id: Option<String>,
+    pub org_id: Option<String>,
+}
+
+impl TokenSubject {
+    pub fn new(user_id: &Thing, security_stamp: impl Into<String>) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            security_stamp: security_stamp.into(),
+            session_id: None,
+            org_id: None,
+        }
+    }
+
+    pub fn with_session(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+
+    pub fn with_org(mut self, org_id: Option<String>) -> Self {
+        self.org_id = org_id;
+        self
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DBSecurityStamp {
+    security_stamp: String,
+}
+
+/// Load the current subject of a user.
+pub async fn load_token_subject(state: &RouterState, user_id: &Thing) -> ApiResult<TokenSubject> {
+    let mut result = state
+        .db
+        .query("select security_stamp from $user_id")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user: Option<DBSecurityStamp> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user = user.ok_or(BackendError::InvalidToken)?;
+    Ok(TokenSubject::new(user_id, user.security_stamp))
+}
+
+/// Reject a token whose user no longer exists or whose security stamp was rotated.
+pub async fn check_security_stamp(state: &RouterState, sub: &str, stamp: &str) -> ApiResult<()> {
+    let user_id = sub
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let subject = load_token_subject(state, &user_id).await?;
+    if subject.security_stamp == stamp {
+        Ok(())
+    } else {
+        Err(BackendError::InvalidToken)
+    }
+}
+
+/// Rotate the security stamp of a user, invalidating all of its tokens and sessions.
+pub async fn rotate_security_stamp(
+    state: &RouterState,
+    user_id: &Thing,
+) -> ApiResult<TokenSubject> {
+    let mut result = state
+        .db
+        .query("update $user_id set security_stamp=rand::uuid() return value security_stamp")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let security_stamp: Option<String> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let security_stamp = security_stamp.ok_or(BackendError::SomethingWentWrong)?;
+    Ok(TokenSubject::new(user_id, security_stamp))
+}
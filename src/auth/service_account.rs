@@ -0,0 +1,77 @@
+use super::bearer_jwt::encode_service_jwt_bearer_claims;
+use super::password::verify_secret;
+use super::security_stamp::TokenSubject;
+use crate::{ApiResult, BackendError, RouterState};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+
+/// A backend service calling the API on its own behalf, with the `client_credentials` grant.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceAccount {
+    pub id: Thing,
+    pub client_id: String,
+    /// The Argon2 hash of the secret.
+    secret_hash: String,
+    /// The scopes the service can be granted.
+    pub scopes: Vec<String>,
+    security_stamp: String,
+}
+
+/// The data of the bearer tokens of a service account.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Service {
+    pub client_id: String,
+}
+
+/// Authenticate a service account with its secret, an unknown one taking as long as a wrong secret.
+pub async fn authenticate_service_account(
+    state: &RouterState,
+    client_id: &str,
+    client_secret: &str,
+) -> ApiResult<ServiceAccount> {
+    let mut result = state
+        .db
+        .query("select id, client_id, secret_hash, scopes, security_stamp from service_account where client_id=$client_id and disabled=false")
+        .bind(("client_id", client_id.to_string()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let account: Option<ServiceAccount> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let secret_hash = account.as_ref().map(|account| account.secret_hash.clone());
+    if !verify_secret(client_secret.to_string(), secret_hash).await? {
+        return Err(BackendError::InvalidClient);
+    }
+    account.ok_or(BackendError::InvalidClient)
+}
+
+/// Issue a bearer token to a service account, for the requested scopes or all of its scopes.
+///
+/// Returns the token along with the granted scopes.
+pub fn issue_service_token(
+    account: &ServiceAccount,
+    requested: Option<&str>,
+) -> ApiResult<(String, String)> {
+    let scope = match requested {
+        Some(requested) => {
+            let mut scopes: Vec<&str> = Vec::new();
+            for scope in requested.split_whitespace() {
+                if !account.scopes.iter().any(|allowed| allowed == scope) {
+                    return Err(BackendError::InvalidScope);
+                }
+                if !scopes.contains(&scope) {
+                    scopes.push(scope);
+                }
+            }
+            scopes.join(" ")
+        }
+        None => account.scopes.join(" "),
+    };
+
+    let subject = TokenSubject::new(&account.id, account.security_stamp.clone());
+    let data = Service {
+        client_id: account.client_id.clone(),
+    };
+    let token = encode_service_jwt_bearer_claims(&subject, data, scope.clone())?;
+    Ok((token, scope))
+}
@@ -0,0 +1,235 @@
+use super::bearer_jwt::encode_required_jwt_bearer_claims;
+use super::client_info::ClientInfo;
+use super::refresh_token::issue_refresh_token;
+use super::security_stamp::TokenSubject;
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+
+const COOKIE_SESSION_TTL: &str = "24h";
+
+/// How the client of a session authenticates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionKind {
+    Cookie,
+    Bearer,
+}
+
+impl SessionKind {
+    /// How long the session lasts without being refreshed.
+    fn ttl(&self) -> String {
+        match self {
+            SessionKind::Cookie => COOKIE_SESSION_TTL.to_string(),
+            SessionKind::Bearer => format!("{}d", env_config().refresh_token_ttl),
+        }
+    }
+}
+
+/// The token of a session held server-side, the cookie only carrying its opaque value.
+#[derive(Debug, Clone)]
+pub struct ServerSideToken {
+    pub token_hash: String,
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub kind: SessionKind,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: String,
+    pub last_seen_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBSessionInfo {
+    id: Thing,
+    kind: SessionKind,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    created_at: String,
+    last_seen_at: String,
+}
+
+/// Record a new session for a login, returning the subject to issue its tokens with.
+pub async fn open_session(
+    state: &RouterState,
+    subject: &TokenSubject,
+    kind: SessionKind,
+    client: &ClientInfo,
+    server_side: Option<ServerSideToken>,
+) -> ApiResult<TokenSubject> {
+    let user_id = subject
+        .user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let (token_hash, data) = server_side
+        .map(|token| (Some(token.token_hash), Some(token.data)))
+        .unwrap_or_default();
+    let mut result = state
+        .db
+        .query(format!(
+            "create session set user=$user_id, kind=$kind, token_hash=$token_hash, security_stamp=$security_stamp, data=$data, ip=$ip, user_agent=$user_agent, expires_at=time::now() + {} return value id",
+            kind.ttl()
+        ))
+        .bind(("user_id", user_id))
+        .bind(("kind", kind))
+        .bind(("token_hash", token_hash))
+        .bind(("security_stamp", subject.security_stamp.clone()))
+        .bind(("data", data))
+        .bind(("ip", client.ip.clone()))
+        .bind(("user_agent", client.user_agent.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let session_id: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let session_id = session_id.ok_or(BackendError::SomethingWentWrong)?;
+    Ok(subject.clone().with_session(session_id.to_string()))
+}
+
+/// Log a user in with a bearer token, recording its session and issuing its refresh token.
+///
+/// The session of the subject is kept if it has one. Returns the access and refresh tokens.
+pub async fn start_bearer_session<T: Serialize>(
+    state: &RouterState,
+    subject: &TokenSubject,
+    data: T,
+    client: &ClientInfo,
+) -> ApiResult<(String, String)> {
+    let subject = match subject.session_id {
+        Some(_) => {
+            resume_session(state, subject).await?;
+            subject.clone()
+        }
+        None => open_session(state, subject, SessionKind::Bearer, client, None).await?,
+    };
+    let bearer = encode_required_jwt_bearer_claims(&subject, data)?;
+    let refresh_token = issue_refresh_token(state, &subject, None).await?;
+    Ok((bearer, refresh_token))
+}
+
+/// Keep the session of a subject after its security stamp was rotated.
+pub async fn resume_session(state: &RouterState, subject: &TokenSubject) -> ApiResult<()> {
+    let Some(session_id) = &subject.session_id else {
+        return Ok(());
+    };
+    let session_id = session_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    state
+        .db
+        .query("update $session_id set security_stamp=$security_stamp where revoked_at=none")
+        .bind(("session_id", session_id))
+        .bind(("security_stamp", subject.security_stamp.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(())
+}
+
+/// Reject a revoked or expired session, otherwise refresh its last seen time.
+pub async fn touch_session(state: &RouterState, session_id: &str) -> ApiResult<()> {
+    let session_id = session_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let mut result = state
+        .db
+        .query("update $session_id set last_seen_at=time::now() where revoked_at=none and expires_at>time::now() return value id")
+        .bind(("session_id", session_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let session_id: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    session_id.map(|_| ()).ok_or(BackendError::InvalidToken)
+}
+
+/// Push back the expiration of a session, when its refresh token is rotated.
+pub async fn extend_session(
+    state: &RouterState,
+    session_id: &str,
+    kind: SessionKind,
+) -> ApiResult<()> {
+    let session_id = session_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    state
+        .db
+        .query(format!(
+            "update $session_id set expires_at=time::now() + {} where revoked_at=none",
+            kind.ttl()
+        ))
+        .bind(("session_id", session_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(())
+}
+
+/// Revoke a session of a user and its refresh tokens.
+///
+/// Returns `NotFound` when the session isn't an active session of this user.
+pub async fn revoke_session(state: &RouterState, user_id: &str, session_id: &str) -> ApiResult<()> {
+    let user_id = user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let session_id = session_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::NotFound)?;
+    if session_id.tb != "session" {
+        return Err(BackendError::NotFound);
+    }
+    let mut result = state
+        .db
+        .query(
+            "begin transaction;
+            update refresh_token set revoked_at=time::now() where session=$session_id and user=$user_id and revoked_at=none;
+            update $session_id set revoked_at=time::now() where user=$user_id and revoked_at=none return value id;
+            commit transaction;",
+        )
+        .bind(("session_id", session_id))
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let last = result.num_statements() - 1;
+    let revoked: Vec<Thing> = result
+        .take(last)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if revoked.is_empty() {
+        Err(BackendError::NotFound)
+    } else {
+        Ok(())
+    }
+}
+
+/// List the active sessions of a user, the most recently used first.
+pub async fn list_sessions(state: &RouterState, user_id: &str) -> ApiResult<Vec<SessionInfo>> {
+    let user_id = user_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::InvalidToken)?;
+    let mut result = state
+        .db
+        .query("select id, kind, ip, user_agent, <string> created_at as created_at, <string> last_seen_at as last_seen_at from session where user=$user_id and revoked_at=none and expires_at>time::now() and security_stamp=$user_id.security_stamp order by last_seen_at desc")
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let sessions: Vec<DBSessionInfo> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(sessions
+        .into_iter()
+        .map(|session| SessionInfo {
+            id: session.id.to_string(),
+            kind: session.kind,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+        })
+        .collect())
+}
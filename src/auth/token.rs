@@ -0,0 +1,32 @@
+use crate::{env_config, ApiResult, BackendError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{decode, Validation};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+
+/// Generate an opaque, URL safe, random token.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hash a token before storing it, so the database never holds a usable token.
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Decode a single-purpose jwt signed with `JWT_SECRET`, like a magic link or an invitation.
+///
+/// Each purpose has its own `aud`, which bearer and cookie jwts don't have, so such a token is
+/// never accepted anywhere a bearer or cookie jwt is, nor for another purpose.
+pub fn decode_audience_token<T: DeserializeOwned>(token: &str, audience: &str) -> ApiResult<T> {
+    let mut validation = Validation::default();
+    validation.set_audience(&[audience]);
+    decode::<T>(token, &env_config().jwt_decode, &validation)
+        .map(|token| token.claims)
+        .map_err(|_| BackendError::InvalidToken)
+}
@@ -0,0 +1,195 @@
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use surrealdb::sql::Thing;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+/// How many steps before or after the current one are still accepted, for clock drift.
+const TOTP_SKEW: u64 = 1;
+const NONCE_LEN: usize = 12;
+
+/// What an authenticator app needs to generate the codes of a user.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    /// The base32 secret, for apps that can't scan the uri.
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBTotpUser {
+    username: String,
+    totp_secret: Option<String>,
+    totp_enabled: bool,
+}
+
+fn cipher() -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(
+        &env_config().totp_encryption_key,
+    ))
+}
+
+/// Encrypt a secret with AES-256-GCM, the random nonce being prepended to the ciphertext.
+///
+/// The id of the user is authenticated along with it, so the ciphertext can't be copied to the
+/// row of another user.
+fn encrypt_secret(secret: &[u8], user_id: &Thing) -> ApiResult<String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let aad = user_id.to_string();
+    let payload = Payload {
+        msg: secret,
+        aad: aad.as_bytes(),
+    };
+    let ciphertext = cipher()
+        .encrypt(&nonce, payload)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(URL_SAFE_NO_PAD.encode(sealed))
+}
+
+fn decrypt_secret(sealed: &str, user_id: &Thing) -> ApiResult<Vec<u8>> {
+    let sealed = URL_SAFE_NO_PAD
+        .decode(sealed)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if sealed.len() < NONCE_LEN {
+        return Err(BackendError::SomethingWentWrong);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let aad = user_id.to_string();
+    let payload = Payload {
+        msg: ciphertext,
+        aad: aad.as_bytes(),
+    };
+    cipher()
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| BackendError::SomethingWentWrong)
+}
+
+fn build_totp(secret: Vec<u8>, username: String) -> ApiResult<TOTP> {
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW as u8,
+        TOTP_STEP,
+        secret,
+        Some(env_config().totp_issuer.clone()),
+        username,
+    )
+    .map_err(|_| BackendError::SomethingWentWrong)
+}
+
+/// Find the time step a code was generated for, within the accepted skew.
+fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let current = now / TOTP_STEP;
+    (current.saturating_sub(TOTP_SKEW)..=current + TOTP_SKEW)
+        .find(|step| {
+            totp.generate(step * TOTP_STEP)
+                .as_bytes()
+                .ct_eq(code.trim().as_bytes())
+                .into()
+        })
+        .and_then(|step| i64::try_from(step).ok())
+}
+
+async fn load_totp_user(state: &RouterState, user_id: &Thing) -> ApiResult<DBTotpUser> {
+    let mut result = state
+        .db
+        .query("select username, totp_secret, totp_enabled from $user_id")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let user: Option<DBTotpUser> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    user.ok_or(BackendError::InvalidToken)
+}
+
+/// Generate a new secret for a user, which only protects its logins once confirmed.
+///
+/// Enrolling again before the confirmation replaces the pending secret.
+pub async fn enroll_totp(state: &RouterState, user_id: &Thing) -> ApiResult<TotpEnrollment> {
+    let user = load_totp_user(state, user_id).await?;
+    if user.totp_enabled {
+        return Err(BackendError::MfaAlreadyEnabled);
+    }
+    let secret = Secret::generate_secret()
+        .to_bytes()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let totp_secret = encrypt_secret(&secret, user_id)?;
+    let totp = build_totp(secret, user.username)?;
+
+    let mut result = state
+        .db
+        .query("update $user_id set totp_secret=$totp_secret, totp_last_step=none where totp_enabled=false return value id")
+        .bind(("user_id", user_id.clone()))
+        .bind(("totp_secret", totp_secret))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let updated: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    updated.ok_or(BackendError::MfaAlreadyEnabled)?;
+
+    Ok(TotpEnrollment {
+        secret: totp.get_secret_base32(),
+        otpauth_uri: totp.get_url(),
+    })
+}
+
+/// Enable the pending secret of a user with a first code from its authenticator app.
+pub async fn confirm_totp(state: &RouterState, user_id: &Thing, code: &str) -> ApiResult<()> {
+    let user = load_totp_user(state, user_id).await?;
+    if user.totp_enabled {
+        return Err(BackendError::MfaAlreadyEnabled);
+    }
+    let sealed = user.totp_secret.ok_or(BackendError::NotFound)?;
+    let totp = build_totp(decrypt_secret(&sealed, user_id)?, user.username)?;
+    let step = matching_step(&totp, code).ok_or(BackendError::InvalidMfaCode)?;
+
+    let mut result = state
+        .db
+        .query("update $user_id set totp_enabled=true, totp_last_step=$step where totp_enabled=false and totp_secret=$totp_secret return value id")
+        .bind(("user_id", user_id.clone()))
+        .bind(("step", step))
+        .bind(("totp_secret", sealed))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let updated: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    updated.map(|_| ()).ok_or(BackendError::InvalidMfaCode)
+}
+
+/// Check a code against the enabled secret of a user.
+///
+/// A code is only accepted once: its time step must be later than the last accepted one.
+pub async fn verify_totp(state: &RouterState, user_id: &Thing, code: &str) -> ApiResult<()> {
+    let user = load_totp_user(state, user_id).await?;
+    let sealed = match (user.totp_enabled, user.totp_secret) {
+        (true, Some(sealed)) => sealed,
+        _ => return Err(BackendError::InvalidMfaCode),
+    };
+    let totp = build_totp(decrypt_secret(&sealed, user_id)?, user.username)?;
+    let step = matching_step(&totp, code).ok_or(BackendError::InvalidMfaCode)?;
+
+    let mut result = state
+        .db
+        .query("update $user_id set totp_last_step=$step where totp_enabled=true and (totp_last_step=none or totp_last_step<$step) return value id")
+        .bind(("user_id", user_id.clone()))
+        .bind(("step", step))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let updated: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    updated.map(|_| ()).ok_or(BackendError::InvalidMfaCode)
+}
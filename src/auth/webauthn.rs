@@ -0,0 +1,602 @@
+use super::token::{generate_token, hash_token};
+use crate::{env_config, ApiResult, BackendError, RouterState};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ciborium::Value as CborValue;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use surrealdb::sql::Thing;
+
+const WEBAUTHN_CHALLENGE_TTL: &str = "5m";
+const WEBAUTHN_TIMEOUT_MS: u32 = 300_000;
+const PUBLIC_KEY_TYPE: &str = "public-key";
+/// ES256, the COSE algorithm every authenticator supports, and the only one accepted for now.
+const COSE_ALG_ES256: i64 = -7;
+const COSE_KTY_EC2: i64 = 2;
+const COSE_CRV_P256: i64 = 1;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+/// The options of `navigator.credentials.create()`, in their JSON form.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub public_key: PublicKeyCreationOptions,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCreationOptions {
+    pub challenge: String,
+    pub rp: RelyingParty,
+    pub user: UserEntity,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: u32,
+    pub attestation: String,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+/// The options of `navigator.credentials.get()`, in their JSON form.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub public_key: PublicKeyRequestOptions,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyRequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub timeout: u32,
+    pub user_verification: String,
+}
+
+/// The credential returned by `navigator.credentials.create()`, in its JSON form.
+#[derive(Debug, Deserialize)]
+pub struct RegistrationCredential {
+    id: String,
+    response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    attestation_object: String,
+}
+
+/// The credential returned by `navigator.credentials.get()`, in its JSON form.
+#[derive(Debug, Deserialize)]
+pub struct AuthenticationCredential {
+    id: String,
+    response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+    #[serde(default)]
+    user_handle: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    attested_credential: &'a [u8],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyInfo {
+    pub id: String,
+    pub name: Option<String>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBPasskeyInfo {
+    id: Thing,
+    name: Option<String>,
+    created_at: String,
+    last_used_at: Option<String>,
+}
+
+impl From<DBPasskeyInfo> for PasskeyInfo {
+    fn from(passkey: DBPasskeyInfo) -> Self {
+        Self {
+            id: passkey.id.to_string(),
+            name: passkey.name,
+            created_at: passkey.created_at,
+            last_used_at: passkey.last_used_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DBCredential {
+    id: Thing,
+    user: Thing,
+    public_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DBChallenge {
+    user: Option<Thing>,
+}
+
+fn decode(value: &str) -> ApiResult<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| BackendError::InvalidPasskey)
+}
+
+/// The user handle of a user, which its passkeys send back when they log it in.
+fn user_handle(user_id: &Thing) -> String {
+    URL_SAFE_NO_PAD.encode(user_id.to_string())
+}
+
+fn credential_descriptor(credential_id: String) -> CredentialDescriptor {
+    CredentialDescriptor {
+        kind: PUBLIC_KEY_TYPE.to_string(),
+        id: credential_id,
+    }
+}
+
+/// Check the type and the origin of the client data of a ceremony, returning its challenge.
+fn verify_client_data(client_data_json: &[u8], kind: &str) -> ApiResult<String> {
+    let client_data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| BackendError::InvalidPasskey)?;
+    if client_data.kind != kind || client_data.origin != env_config().webauthn_origin {
+        return Err(BackendError::InvalidPasskey);
+    }
+    Ok(client_data.challenge)
+}
+
+/// Parse authenticator data, checking it was made for this relying party with the user present.
+fn parse_authenticator_data(data: &[u8]) -> ApiResult<AuthenticatorData<'_>> {
+    if data.len() < 37 {
+        return Err(BackendError::InvalidPasskey);
+    }
+    let rp_id_hash = Sha256::digest(env_config().webauthn_rp_id.as_bytes());
+    if data[..32] != rp_id_hash[..] {
+        return Err(BackendError::InvalidPasskey);
+    }
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err(BackendError::InvalidPasskey);
+    }
+    Ok(AuthenticatorData {
+        flags,
+        sign_count: u32::from_be_bytes([data[33], data[34], data[35], data[36]]),
+        attested_credential: &data[37..],
+    })
+}
+
+/// Extract the authenticator data of an attestation object.
+///
+/// The attestation statement isn't verified: `none` is requested, so its format is ignored.
+fn attestation_authenticator_data(attestation_object: &[u8]) -> ApiResult<Vec<u8>> {
+    let attestation: CborValue =
+        ciborium::from_reader(attestation_object).map_err(|_| BackendError::InvalidPasskey)?;
+    attestation
+        .as_map()
+        .and_then(|entries| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_text() == Some("authData"))
+        })
+        .and_then(|(_, value)| value.as_bytes())
+        .cloned()
+        .ok_or(BackendError::InvalidPasskey)
+}
+
+/// Extract the id of a new credential and its public key, SEC1 encoded, from its attested data.
+fn parse_attested_credential(data: &[u8]) -> ApiResult<(Vec<u8>, Vec<u8>)> {
+    // The AAGUID of the authenticator, then the length of the credential id.
+    if data.len() < 18 {
+        return Err(BackendError::InvalidPasskey);
+    }
+    let id_len = u16::from_be_bytes([data[16], data[17]]) as usize;
+    let data = &data[18..];
+    if data.len() < id_len {
+        return Err(BackendError::InvalidPasskey);
+    }
+    let (credential_id, mut cose_key) = data.split_at(id_len);
+    let cose_key: CborValue =
+        ciborium::from_reader(&mut cose_key).map_err(|_| BackendError::InvalidPasskey)?;
+    Ok((credential_id.to_vec(), parse_cose_key(&cose_key)?))
+}
+
+/// Convert an ES256 COSE key to a SEC1 encoded public key.
+fn parse_cose_key(cose_key: &CborValue) -> ApiResult<Vec<u8>> {
+    let entries = cose_key.as_map().ok_or(BackendError::InvalidPasskey)?;
+    let get = |label: i64| {
+        entries
+            .iter()
+            .find(|(key, _)| key.as_integer() == Some(label.into()))
+            .map(|(_, value)| value)
+    };
+    let int = |label: i64| {
+        get(label)
+            .and_then(|value| value.as_integer())
+            .and_then(|value| i64::try_from(value).ok())
+    };
+    if int(1) != Some(COSE_KTY_EC2)
+        || int(3) != Some(COSE_ALG_ES256)
+        || int(-1) != Some(COSE_CRV_P256)
+    {
+        return Err(BackendError::InvalidPasskey);
+    }
+    let x = get(-2).and_then(|value| value.as_bytes());
+    let y = get(-3).and_then(|value| value.as_bytes());
+    let (Some(x), Some(y)) = (x, y) else {
+        return Err(BackendError::InvalidPasskey);
+    };
+    let mut public_key = vec![0x04];
+    public_key.extend(x);
+    public_key.extend(y);
+    VerifyingKey::from_sec1_bytes(&public_key).map_err(|_| BackendError::InvalidPasskey)?;
+    Ok(public_key)
+}
+
+async fn credential_ids(state: &RouterState, user_id: &Thing) -> ApiResult<Vec<String>> {
+    let mut result = state
+        .db
+        .query("select value credential_id from credential where user=$user_id")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    result.take(0).map_err(|_| BackendError::SomethingWentWrong)
+}
+
+async fn create_challenge(
+    state: &RouterState,
+    user_id: Option<Thing>,
+    ceremony: Ceremony,
+) -> ApiResult<String> {
+    let challenge = generate_token();
+    state
+        .db
+        .query(format!(
+            "create webauthn_challenge set user=$user_id, challenge_hash=$challenge_hash, ceremony=$ceremony, expires_at=time::now() + {WEBAUTHN_CHALLENGE_TTL}"
+        ))
+        .bind(("user_id", user_id))
+        .bind(("challenge_hash", hash_token(&challenge)))
+        .bind(("ceremony", ceremony))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?
+        .check()
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(challenge)
+}
+
+/// Consume the challenge a ceremony answers, returning the user it was started for.
+async fn take_challenge(
+    state: &RouterState,
+    challenge: &str,
+    ceremony: Ceremony,
+) -> ApiResult<Option<Thing>> {
+    let mut result = state
+        .db
+        .query("(delete webauthn_challenge where challenge_hash=$challenge_hash and ceremony=$ceremony and expires_at>time::now() return before)[0]")
+        .bind(("challenge_hash", hash_token(challenge)))
+        .bind(("ceremony", ceremony))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let challenge: Option<DBChallenge> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    challenge
+        .map(|challenge| challenge.user)
+        .ok_or(BackendError::InvalidPasskey)
+}
+
+/// Start the registration of a new passkey for a user.
+pub async fn start_passkey_registration(
+    state: &RouterState,
+    user_id: &Thing,
+) -> ApiResult<CreationOptions> {
+    let mut result = state
+        .db
+        .query("select value username from $user_id")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let username: Option<String> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let username = username.ok_or(BackendError::InvalidToken)?;
+    let exclude_credentials = credential_ids(state, user_id)
+        .await?
+        .into_iter()
+        .map(credential_descriptor)
+        .collect();
+    let challenge = create_challenge(state, Some(user_id.clone()), Ceremony::Registration).await?;
+
+    Ok(CreationOptions {
+        public_key: PublicKeyCreationOptions {
+            challenge,
+            rp: RelyingParty {
+                id: env_config().webauthn_rp_id.clone(),
+                name: env_config().webauthn_rp_name.clone(),
+            },
+            user: UserEntity {
+                id: user_handle(user_id),
+                name: username.clone(),
+                display_name: username,
+            },
+            pub_key_cred_params: vec![CredentialParameters {
+                kind: PUBLIC_KEY_TYPE.to_string(),
+                alg: COSE_ALG_ES256,
+            }],
+            timeout: WEBAUTHN_TIMEOUT_MS,
+            attestation: "none".to_string(),
+            exclude_credentials,
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "preferred".to_string(),
+                user_verification: "preferred".to_string(),
+            },
+        },
+    })
+}
+
+/// Verify the answer to a registration challenge and store the new passkey of the user.
+pub async fn finish_passkey_registration(
+    state: &RouterState,
+    user_id: &Thing,
+    name: Option<String>,
+    credential: &RegistrationCredential,
+) -> ApiResult<PasskeyInfo> {
+    let client_data_json = decode(&credential.response.client_data_json)?;
+    let challenge = verify_client_data(&client_data_json, "webauthn.create")?;
+    if take_challenge(state, &challenge, Ceremony::Registration).await? != Some(user_id.clone()) {
+        return Err(BackendError::InvalidPasskey);
+    }
+
+    let authenticator_data =
+        attestation_authenticator_data(&decode(&credential.response.attestation_object)?)?;
+    let authenticator_data = parse_authenticator_data(&authenticator_data)?;
+    if authenticator_data.flags & FLAG_ATTESTED_CREDENTIAL == 0 {
+        return Err(BackendError::InvalidPasskey);
+    }
+    let (credential_id, public_key) =
+        parse_attested_credential(authenticator_data.attested_credential)?;
+    let credential_id = URL_SAFE_NO_PAD.encode(credential_id);
+    if credential_id != credential.id {
+        return Err(BackendError::InvalidPasskey);
+    }
+
+    let created: Option<DBPasskeyInfo> = match state
+        .db
+        .query("create credential set user=$user_id, credential_id=$credential_id, public_key=$public_key, algorithm=$algorithm, sign_count=$sign_count, name=$name return id, name, <string> created_at as created_at, <option<string>> last_used_at as last_used_at")
+        .bind(("user_id", user_id.clone()))
+        .bind(("credential_id", credential_id.clone()))
+        .bind(("public_key", URL_SAFE_NO_PAD.encode(public_key)))
+        .bind(("algorithm", COSE_ALG_ES256))
+        .bind(("sign_count", authenticator_data.sign_count))
+        .bind(("name", name))
+        .await
+    {
+        Ok(mut result) => result.take(0).ok().flatten(),
+        Err(_) => None,
+    };
+    match created {
+        Some(passkey) => Ok(passkey.into()),
+        None if credential_ids(state, user_id)
+            .await?
+            .contains(&credential_id) =>
+        {
+            Err(BackendError::PasskeyAlreadyRegistered)
+        }
+        None => Err(BackendError::SomethingWentWrong),
+    }
+}
+
+/// Start a passkey login, restricted to the passkeys of a user when it is known.
+///
+/// Without a user, the authenticator offers its discoverable passkeys for this site.
+pub async fn start_passkey_authentication(
+    state: &RouterState,
+    user_id: Option<&Thing>,
+    require_user_verification: bool,
+) -> ApiResult<RequestOptions> {
+    let allow_credentials = match user_id {
+        Some(user_id) => credential_ids(state, user_id)
+            .await?
+            .into_iter()
+            .map(credential_descriptor)
+            .collect(),
+        None => Vec::new(),
+    };
+    let challenge = create_challenge(state, user_id.cloned(), Ceremony::Authentication).await?;
+    let user_verification = match require_user_verification {
+        true => "required",
+        false => "preferred",
+    };
+
+    Ok(RequestOptions {
+        public_key: PublicKeyRequestOptions {
+            challenge,
+            rp_id: env_config().webauthn_rp_id.clone(),
+            allow_credentials,
+            timeout: WEBAUTHN_TIMEOUT_MS,
+            user_verification: user_verification.to_string(),
+        },
+    })
+}
+
+/// Verify the answer to an authentication challenge, returning the user owning the passkey.
+///
+/// When a user is expected, the challenge must have been started for it. The sign counter of
+/// the passkey must increase, unless the authenticator doesn't keep one.
+pub async fn verify_passkey_assertion(
+    state: &RouterState,
+    credential: &AuthenticationCredential,
+    user_id: Option<&Thing>,
+    require_user_verification: bool,
+) -> ApiResult<Thing> {
+    let client_data_json = decode(&credential.response.client_data_json)?;
+    let challenge = verify_client_data(&client_data_json, "webauthn.get")?;
+    let challenge_user = take_challenge(state, &challenge, Ceremony::Authentication).await?;
+    if user_id.is_some() && challenge_user.as_ref() != user_id {
+        return Err(BackendError::InvalidPasskey);
+    }
+
+    let mut result = state
+        .db
+        .query("select id, user, public_key from credential where credential_id=$credential_id")
+        .bind(("credential_id", credential.id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let stored: Option<DBCredential> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let stored = stored.ok_or(BackendError::InvalidPasskey)?;
+    if challenge_user.is_some_and(|challenge_user| challenge_user != stored.user) {
+        return Err(BackendError::InvalidPasskey);
+    }
+    if let Some(handle) = credential
+        .response
+        .user_handle
+        .as_ref()
+        .filter(|handle| !handle.is_empty())
+    {
+        if *handle != user_handle(&stored.user) {
+            return Err(BackendError::InvalidPasskey);
+        }
+    }
+
+    let authenticator_data = decode(&credential.response.authenticator_data)?;
+    let parsed = parse_authenticator_data(&authenticator_data)?;
+    if require_user_verification && parsed.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(BackendError::InvalidPasskey);
+    }
+    let public_key = VerifyingKey::from_sec1_bytes(&decode(&stored.public_key)?)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let signature = Signature::from_der(&decode(&credential.response.signature)?)
+        .map_err(|_| BackendError::InvalidPasskey)?;
+    let mut signed = authenticator_data.clone();
+    signed.extend(Sha256::digest(&client_data_json));
+    public_key
+        .verify(&signed, &signature)
+        .map_err(|_| BackendError::InvalidPasskey)?;
+
+    let mut result = state
+        .db
+        .query("update $credential set sign_count=$sign_count, last_used_at=time::now() where sign_count<$sign_count or (sign_count=0 and $sign_count=0) return value id")
+        .bind(("credential", stored.id))
+        .bind(("sign_count", parsed.sign_count))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let updated: Option<Thing> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    updated.ok_or(BackendError::InvalidPasskey)?;
+
+    Ok(stored.user)
+}
+
+/// List the passkeys of a user, the most recently registered first.
+pub async fn list_passkeys(state: &RouterState, user_id: &Thing) -> ApiResult<Vec<PasskeyInfo>> {
+    let mut result = state
+        .db
+        .query("select id, name, <string> created_at as created_at, <option<string>> last_used_at as last_used_at from credential where user=$user_id order by created_at desc")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let passkeys: Vec<DBPasskeyInfo> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    Ok(passkeys.into_iter().map(PasskeyInfo::from).collect())
+}
+
+/// Remove a passkey of a user, returning `NotFound` when it isn't one of its passkeys.
+pub async fn delete_passkey(
+    state: &RouterState,
+    user_id: &Thing,
+    passkey_id: &str,
+) -> ApiResult<()> {
+    let passkey_id = passkey_id
+        .parse::<Thing>()
+        .map_err(|_| BackendError::NotFound)?;
+    if passkey_id.tb != "credential" {
+        return Err(BackendError::NotFound);
+    }
+    let mut result = state
+        .db
+        .query("delete $passkey_id where user=$user_id return before")
+        .bind(("passkey_id", passkey_id))
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    let deleted: Vec<DBCredential> = result
+        .take(0)
+        .map_err(|_| BackendError::SomethingWentWrong)?;
+    if deleted.is_empty() {
+        Err(BackendError::NotFound)
+    } else {
+        Ok(())
+    }
+}
@@ -1,6 +1,33 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use serde::Deserialize;
 use std::str::FromStr;
+use strum::EnumString;
+
+/// How the cookie authentication keeps track of a logged in user.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum CookieMode {
+    /// The cookie holds a signed jwt, nothing is stored server-side.
+    Jwt,
+    /// The cookie holds an opaque session id backed by the `session` table.
+    Session,
+}
+
+/// An OpenID Connect provider users can sign in with, defined by the `OIDC_<NAME>_*` variables.
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    /// The name used in the `/oidc/:provider` routes.
+    pub name: String,
+    /// The issuer, whose discovery document is served at `/.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    /// Left out for public clients, which only rely on PKCE.
+    pub client_secret: Option<String>,
+    pub scopes: Vec<String>,
+}
 
 #[derive(Deserialize, Debug)]
 struct EnvConfig {
@@ -14,6 +41,31 @@ struct EnvConfig {
     db_version: Option<usize>,
     jwt_secret: String,
     jwt_cookie_name: Option<String>,
+    cookie_mode: Option<CookieMode>,
+    trust_proxy: Option<bool>,
+    jwt_access_ttl: Option<i64>,
+    refresh_token_ttl: Option<u32>,
+    password_memory_cost: Option<u32>,
+    password_time_cost: Option<u32>,
+    password_parallelism: Option<u32>,
+    public_url: Option<String>,
+    smtp_host: Option<String>,
+    smtp_port: Option<u16>,
+    smtp_user: Option<String>,
+    smtp_pswd: Option<String>,
+    mail_from: Option<String>,
+    require_email_verification: Option<bool>,
+    totp_encryption_key: [u8; 32],
+    totp_issuer: Option<String>,
+    webauthn_rp_id: Option<String>,
+    webauthn_rp_name: Option<String>,
+    webauthn_origin: Option<String>,
+    oidc_providers: Option<String>,
+    oauth_issuer: Option<String>,
+    oauth_signing_key: [u8; 32],
+    oauth_login_url: Option<String>,
+    policy_file: Option<String>,
+    tenant_databases: Option<bool>,
 }
 
 pub(crate) struct Config {
@@ -28,6 +80,41 @@ pub(crate) struct Config {
     pub(crate) jwt_decode: DecodingKey,
     pub(crate) jwt_encode: EncodingKey,
     pub(crate) jwt_cookie_name: String,
+    pub(crate) cookie_mode: CookieMode,
+    pub(crate) trust_proxy: bool,
+    pub(crate) jwt_access_ttl: i64,
+    pub(crate) refresh_token_ttl: u32,
+    pub(crate) password_memory_cost: u32,
+    pub(crate) password_time_cost: u32,
+    pub(crate) password_parallelism: u32,
+    pub(crate) public_url: String,
+    pub(crate) smtp_host: Option<String>,
+    pub(crate) smtp_port: Option<u16>,
+    pub(crate) smtp_user: Option<String>,
+    pub(crate) smtp_pswd: Option<String>,
+    pub(crate) mail_from: String,
+    pub(crate) require_email_verification: bool,
+    /// The AES-256 key encrypting the TOTP secrets stored in the database, kept apart from
+    /// `JWT_SECRET` so that one leaking doesn't give away the other.
+    pub(crate) totp_encryption_key: [u8; 32],
+    pub(crate) totp_issuer: String,
+    /// The domain passkeys are bound to.
+    pub(crate) webauthn_rp_id: String,
+    pub(crate) webauthn_rp_name: String,
+    /// The origin of the pages running the passkey ceremonies.
+    pub(crate) webauthn_origin: String,
+    pub(crate) oidc_providers: Vec<OidcProvider>,
+    /// The issuer of the tokens this service signs as an OpenID Connect provider.
+    pub(crate) oauth_issuer: String,
+    /// The P-256 private key signing the ID and access tokens of the OAuth clients, kept apart
+    /// from `JWT_SECRET` so that rotating one doesn't change the other.
+    pub(crate) oauth_signing_key: [u8; 32],
+    /// Where `/authorize` sends users without a cookie session, with a `return_to` parameter.
+    pub(crate) oauth_login_url: Option<String>,
+    /// The JSON file the authorization policies are loaded from, reloaded when it changes.
+    pub(crate) policy_file: String,
+    /// Whether each organization keeps its data in its own database of `DB_NAMESPACE`.
+    pub(crate) tenant_databases: bool,
 }
 
 #[derive(Clone, Debug, thiserror::Error)]
@@ -69,8 +156,147 @@ pub fn load_config() -> Result<Config, ConfigError> {
         jwt_secret: std::env::var("JWT_SECRET")
             .map_err(|_| ConfigError::Missing("Missing: `JWT_SECRET`".to_string()))?,
         jwt_cookie_name: std::env::var("JWT_COOKIE_NAME").ok(),
+        cookie_mode: std::env::var("COOKIE_MODE")
+            .ok()
+            .map(|mode| {
+                CookieMode::from_str(mode.as_str())
+                    .map_err(|_| ConfigError::Parse("Failed to parse `COOKIE_MODE`".to_string()))
+            })
+            .transpose()?,
+        trust_proxy: std::env::var("TRUST_PROXY")
+            .ok()
+            .map(|trust| {
+                bool::from_str(trust.as_str())
+                    .map_err(|_| ConfigError::Parse("Failed to parse `TRUST_PROXY`".to_string()))
+            })
+            .transpose()?,
+        jwt_access_ttl: std::env::var("JWT_ACCESS_TTL")
+            .ok()
+            .map(|ttl| {
+                i64::from_str(ttl.as_str())
+                    .map_err(|_| ConfigError::Parse("Failed to parse `JWT_ACCESS_TTL`".to_string()))
+            })
+            .transpose()?,
+        refresh_token_ttl: std::env::var("REFRESH_TOKEN_TTL")
+            .ok()
+            .map(|ttl| {
+                u32::from_str(ttl.as_str()).map_err(|_| {
+                    ConfigError::Parse("Failed to parse `REFRESH_TOKEN_TTL`".to_string())
+                })
+            })
+            .transpose()?,
+        password_memory_cost: std::env::var("PASSWORD_MEMORY_COST")
+            .ok()
+            .map(|cost| {
+                u32::from_str(cost.as_str()).map_err(|_| {
+                    ConfigError::Parse("Failed to parse `PASSWORD_MEMORY_COST`".to_string())
+                })
+            })
+            .transpose()?,
+        password_time_cost: std::env::var("PASSWORD_TIME_COST")
+            .ok()
+            .map(|cost| {
+                u32::from_str(cost.as_str()).map_err(|_| {
+                    ConfigError::Parse("Failed to parse `PASSWORD_TIME_COST`".to_string())
+                })
+            })
+            .transpose()?,
+        password_parallelism: std::env::var("PASSWORD_PARALLELISM")
+            .ok()
+            .map(|cost| {
+                u32::from_str(cost.as_str()).map_err(|_| {
+                    ConfigError::Parse("Failed to parse `PASSWORD_PARALLELISM`".to_string())
+                })
+            })
+            .transpose()?,
+        public_url: std::env::var("PUBLIC_URL").ok(),
+        smtp_host: std::env::var("SMTP_HOST").ok(),
+        smtp_port: std::env::var("SMTP_PORT")
+            .ok()
+            .map(|port| {
+                u16::from_str(port.as_str())
+                    .map_err(|_| ConfigError::Parse("Failed to parse `SMTP_PORT`".to_string()))
+            })
+            .transpose()?,
+        smtp_user: std::env::var("SMTP_USER").ok(),
+        smtp_pswd: std::env::var("SMTP_PSWD").ok(),
+        mail_from: std::env::var("MAIL_FROM").ok(),
+        require_email_verification: std::env::var("REQUIRE_EMAIL_VERIFICATION")
+            .ok()
+            .map(|required| {
+                bool::from_str(required.as_str()).map_err(|_| {
+                    ConfigError::Parse("Failed to parse `REQUIRE_EMAIL_VERIFICATION`".to_string())
+                })
+            })
+            .transpose()?,
+        totp_encryption_key: std::env::var("TOTP_ENCRYPTION_KEY")
+            .map_err(|_| ConfigError::Missing("Missing Env: `TOTP_ENCRYPTION_KEY`".to_string()))
+            .and_then(|key| {
+                STANDARD
+                    .decode(key.as_str())
+                    .ok()
+                    .and_then(|key| <[u8; 32]>::try_from(key).ok())
+                    .ok_or_else(|| {
+                        ConfigError::Parse("Failed to parse `TOTP_ENCRYPTION_KEY`".to_string())
+                    })
+            })?,
+        totp_issuer: std::env::var("TOTP_ISSUER").ok(),
+        webauthn_rp_id: std::env::var("WEBAUTHN_RP_ID").ok(),
+        webauthn_rp_name: std::env::var("WEBAUTHN_RP_NAME").ok(),
+        webauthn_origin: std::env::var("WEBAUTHN_ORIGIN").ok(),
+        oidc_providers: std::env::var("OIDC_PROVIDERS").ok(),
+        oauth_issuer: std::env::var("OAUTH_ISSUER").ok(),
+        oauth_signing_key: std::env::var("OAUTH_SIGNING_KEY")
+            .map_err(|_| ConfigError::Missing("Missing Env: `OAUTH_SIGNING_KEY`".to_string()))
+            .and_then(|key| {
+                STANDARD
+                    .decode(key.as_str())
+                    .ok()
+                    .and_then(|key| <[u8; 32]>::try_from(key).ok())
+                    .filter(|key| p256::SecretKey::from_slice(key).is_ok())
+                    .ok_or_else(|| {
+                        ConfigError::Parse("Failed to parse `OAUTH_SIGNING_KEY`".to_string())
+                    })
+            })?,
+        oauth_login_url: std::env::var("OAUTH_LOGIN_URL").ok(),
+        policy_file: std::env::var("POLICY_FILE").ok(),
+        tenant_databases: std::env::var("TENANT_DATABASES")
+            .ok()
+            .map(|enabled| {
+                bool::from_str(enabled.as_str()).map_err(|_| {
+                    ConfigError::Parse("Failed to parse `TENANT_DATABASES`".to_string())
+                })
+            })
+            .transpose()?,
     };
 
+    let public_url = config.public_url.unwrap_or_else(|| match config.host_port {
+        Some(port) => format!("http://{}:{port}", config.host_name),
+        None => format!("http://{}", config.host_name),
+    });
+    let public_url = public_url.trim_end_matches('/').to_string();
+
+    let webauthn_origin = config
+        .webauthn_origin
+        .map(|origin| origin.trim_end_matches('/').to_string())
+        .unwrap_or_else(|| public_url.clone());
+    let webauthn_rp_id = config
+        .webauthn_rp_id
+        .unwrap_or_else(|| config.host_name.clone());
+    let oauth_issuer = config
+        .oauth_issuer
+        .map(|issuer| issuer.trim_end_matches('/').to_string())
+        .unwrap_or_else(|| format!("{public_url}/api"));
+    let oidc_providers = config
+        .oidc_providers
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(load_oidc_provider)
+        .collect::<Result<Vec<_>, _>>()?;
+
     Ok(Config {
         host_name: config.host_name,
         host_port: config.host_port,
@@ -83,5 +309,74 @@ pub fn load_config() -> Result<Config, ConfigError> {
         jwt_decode: DecodingKey::from_secret(config.jwt_secret.as_bytes()),
         jwt_encode: EncodingKey::from_secret(config.jwt_secret.as_bytes()),
         jwt_cookie_name: config.jwt_cookie_name.unwrap_or("session".to_string()),
+        cookie_mode: config.cookie_mode.unwrap_or(CookieMode::Jwt),
+        trust_proxy: config.trust_proxy.unwrap_or(false),
+        jwt_access_ttl: config.jwt_access_ttl.unwrap_or(15),
+        refresh_token_ttl: config.refresh_token_ttl.unwrap_or(30),
+        password_memory_cost: config
+            .password_memory_cost
+            .unwrap_or(argon2::Params::DEFAULT_M_COST),
+        password_time_cost: config
+            .password_time_cost
+            .unwrap_or(argon2::Params::DEFAULT_T_COST),
+        password_parallelism: config
+            .password_parallelism
+            .unwrap_or(argon2::Params::DEFAULT_P_COST),
+        public_url,
+        smtp_host: config.smtp_host,
+        smtp_port: config.smtp_port,
+        smtp_user: config.smtp_user,
+        smtp_pswd: config.smtp_pswd,
+        mail_from: config.mail_from.unwrap_or("no-reply@localhost".to_string()),
+        require_email_verification: config.require_email_verification.unwrap_or(false),
+        totp_encryption_key: config.totp_encryption_key,
+        totp_issuer: config.totp_issuer.unwrap_or("Axum Auth API".to_string()),
+        webauthn_rp_id,
+        webauthn_rp_name: config
+            .webauthn_rp_name
+            .unwrap_or("Axum Auth API".to_string()),
+        webauthn_origin,
+        oidc_providers,
+        oauth_issuer,
+        oauth_signing_key: config.oauth_signing_key,
+        oauth_login_url: config.oauth_login_url,
+        policy_file: config.policy_file.unwrap_or("policies.json".to_string()),
+        tenant_databases: config.tenant_databases.unwrap_or(false),
+    })
+}
+
+/// Load a provider listed in `OIDC_PROVIDERS` from its `OIDC_<NAME>_ISSUER`,
+/// `OIDC_<NAME>_CLIENT_ID`, `OIDC_<NAME>_CLIENT_SECRET` and `OIDC_<NAME>_SCOPES` variables.
+fn load_oidc_provider(name: &str) -> Result<OidcProvider, ConfigError> {
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+    {
+        return Err(ConfigError::Parse(
+            "Failed to parse `OIDC_PROVIDERS`".to_string(),
+        ));
+    }
+    let prefix = format!("OIDC_{}", name.to_ascii_uppercase().replace('-', "_"));
+    let issuer = std::env::var(format!("{prefix}_ISSUER"))
+        .map_err(|_| ConfigError::Missing(format!("Missing Env: `{prefix}_ISSUER`")))?;
+    let client_id = std::env::var(format!("{prefix}_CLIENT_ID"))
+        .map_err(|_| ConfigError::Missing(format!("Missing Env: `{prefix}_CLIENT_ID`")))?;
+    let scopes = std::env::var(format!("{prefix}_SCOPES"))
+        .unwrap_or("openid email profile".to_string())
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if !scopes.iter().any(|scope| scope == "openid") {
+        return Err(ConfigError::Parse(format!(
+            "Failed to parse `{prefix}_SCOPES`, the `openid` scope is required"
+        )));
+    }
+
+    Ok(OidcProvider {
+        name: name.to_ascii_lowercase(),
+        issuer,
+        client_id,
+        client_secret: std::env::var(format!("{prefix}_CLIENT_SECRET")).ok(),
+        scopes,
     })
 }
@@ -14,6 +14,26 @@ pub enum BackendError {
     SomethingWentWrong,
     SerializationFailed,
     JWTEncodingFailed,
+    PasswordHashingFailed,
+    ValidationFailed(String),
+    UsernameTaken,
+    EmailTaken,
+    SlugTaken,
+    AlreadyMember,
+    EmailNotVerified,
+    AccountDisabled,
+    MailDeliveryFailed,
+    NotFound,
+    InvalidMfaCode,
+    MfaAlreadyEnabled,
+    MfaNotEnabled,
+    InvalidPasskey,
+    PasskeyAlreadyRegistered,
+    IdentityProviderFailed,
+    InvalidClient,
+    InvalidGrant,
+    InvalidScope,
+    Forbidden,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -54,6 +74,107 @@ impl IntoResponse for BackendError {
                 Json(BackendErrorMessage::new(401, "No Token Found")),
             )
                 .into_response(),
+            BackendError::ValidationFailed(reason) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(BackendErrorMessage::new(422, reason)),
+            )
+                .into_response(),
+            BackendError::UsernameTaken => (
+                StatusCode::CONFLICT,
+                Json(BackendErrorMessage::new(409, "Username Already Taken")),
+            )
+                .into_response(),
+            BackendError::EmailTaken => (
+                StatusCode::CONFLICT,
+                Json(BackendErrorMessage::new(409, "Email Already Taken")),
+            )
+                .into_response(),
+            BackendError::SlugTaken => (
+                StatusCode::CONFLICT,
+                Json(BackendErrorMessage::new(409, "Slug Already Taken")),
+            )
+                .into_response(),
+            BackendError::AlreadyMember => (
+                StatusCode::CONFLICT,
+                Json(BackendErrorMessage::new(409, "Already A Member")),
+            )
+                .into_response(),
+            BackendError::EmailNotVerified => (
+                StatusCode::FORBIDDEN,
+                Json(BackendErrorMessage::new(403, "Email Not Verified")),
+            )
+                .into_response(),
+            BackendError::AccountDisabled => (
+                StatusCode::FORBIDDEN,
+                Json(BackendErrorMessage::new(403, "Account Disabled")),
+            )
+                .into_response(),
+            BackendError::MailDeliveryFailed => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(BackendErrorMessage::new(500, "Mail Delivery Failed")),
+            )
+                .into_response(),
+            BackendError::NotFound => (
+                StatusCode::NOT_FOUND,
+                Json(BackendErrorMessage::new(404, "Not Found")),
+            )
+                .into_response(),
+            BackendError::InvalidMfaCode => (
+                StatusCode::UNAUTHORIZED,
+                Json(BackendErrorMessage::new(401, "Invalid Code")),
+            )
+                .into_response(),
+            BackendError::MfaAlreadyEnabled => (
+                StatusCode::CONFLICT,
+                Json(BackendErrorMessage::new(
+                    409,
+                    "Two-Factor Authentication Already Enabled",
+                )),
+            )
+                .into_response(),
+            BackendError::MfaNotEnabled => (
+                StatusCode::CONFLICT,
+                Json(BackendErrorMessage::new(
+                    409,
+                    "Two-Factor Authentication Not Enabled",
+                )),
+            )
+                .into_response(),
+            BackendError::InvalidPasskey => (
+                StatusCode::UNAUTHORIZED,
+                Json(BackendErrorMessage::new(401, "Invalid Passkey")),
+            )
+                .into_response(),
+            BackendError::PasskeyAlreadyRegistered => (
+                StatusCode::CONFLICT,
+                Json(BackendErrorMessage::new(409, "Passkey Already Registered")),
+            )
+                .into_response(),
+            BackendError::IdentityProviderFailed => (
+                StatusCode::BAD_GATEWAY,
+                Json(BackendErrorMessage::new(502, "Identity Provider Failed")),
+            )
+                .into_response(),
+            BackendError::InvalidClient => (
+                StatusCode::UNAUTHORIZED,
+                Json(BackendErrorMessage::new(401, "Invalid Client")),
+            )
+                .into_response(),
+            BackendError::InvalidGrant => (
+                StatusCode::BAD_REQUEST,
+                Json(BackendErrorMessage::new(400, "Invalid Grant")),
+            )
+                .into_response(),
+            BackendError::InvalidScope => (
+                StatusCode::BAD_REQUEST,
+                Json(BackendErrorMessage::new(400, "Invalid Scope")),
+            )
+                .into_response(),
+            BackendError::Forbidden => (
+                StatusCode::FORBIDDEN,
+                Json(BackendErrorMessage::new(403, "Forbidden")),
+            )
+                .into_response(),
             BackendError::SomethingWentWrong => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(BackendErrorMessage::new(500, "Something Went Wrong")),
@@ -64,6 +185,11 @@ impl IntoResponse for BackendError {
                 Json(BackendErrorMessage::new(500, "JWT Encoding Failed")),
             )
                 .into_response(),
+            BackendError::PasswordHashingFailed => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(BackendErrorMessage::new(500, "Password Hashing Failed")),
+            )
+                .into_response(),
             BackendError::SerializationFailed => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(BackendErrorMessage::new(500, "Serialization Failed")),
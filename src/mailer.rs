@@ -0,0 +1,71 @@
+use crate::config::Config;
+use crate::{ApiResult, BackendError};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum MailerError {
+    #[error("Invalid sender address: {0}")]
+    Sender(String),
+    #[error("Failed to create the SMTP transport: {0}")]
+    Transport(String),
+}
+
+/// Send the emails of the authentication flows.
+///
+/// Without any `SMTP_HOST`, emails are only logged, which is enough for local development.
+#[derive(Clone, Debug)]
+pub struct Mailer {
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    pub fn from_config(config: &Config) -> Result<Self, MailerError> {
+        let from = config
+            .mail_from
+            .parse::<Mailbox>()
+            .map_err(|e| MailerError::Sender(e.to_string()))?;
+        let transport = config
+            .smtp_host
+            .as_deref()
+            .map(|host| {
+                let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                    .map_err(|e| MailerError::Transport(e.to_string()))?;
+                if let Some(port) = config.smtp_port {
+                    builder = builder.port(port);
+                }
+                if let (Some(user), Some(pswd)) = (&config.smtp_user, &config.smtp_pswd) {
+                    builder = builder.credentials(Credentials::new(user.clone(), pswd.clone()));
+                }
+                Ok(builder.build())
+            })
+            .transpose()?;
+        Ok(Self { transport, from })
+    }
+
+    /// Send a plain text email.
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> ApiResult<()> {
+        let Some(transport) = &self.transport else {
+            tracing::info!("Email to `{to}` ({subject}):\n{body}");
+            return Ok(());
+        };
+        let to = to
+            .parse::<Mailbox>()
+            .map_err(|_| BackendError::MailDeliveryFailed)?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|_| BackendError::MailDeliveryFailed)?;
+        transport.send(message).await.map_err(|e| {
+            tracing::error!("Failed to send an email: {e}");
+            BackendError::MailDeliveryFailed
+        })?;
+        Ok(())
+    }
+}
@@ -2,15 +2,23 @@ mod api;
 mod auth;
 mod config;
 mod error;
+mod mailer;
 mod router;
 mod state;
 mod surreal;
 
 pub use error::*;
+use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::OnceLock;
 
+use auth::oidc::OidcClient;
+use auth::organization::TenantDatabases;
+use auth::policy::PolicyStore;
+use auth::revocation::RevocationStore;
 use axum::Router;
 use config::{load_config, Config};
+use mailer::Mailer;
 pub use state::RouterState;
 use surreal::*;
 
@@ -48,7 +56,32 @@ fn main() {
                 panic!("{}", err.as_str());
             }
 
-            let state = RouterState { db };
+            let mailer = match Mailer::from_config(env_config()) {
+                Ok(mailer) => mailer,
+                Err(err) => panic!("{:#?}", err),
+            };
+
+            let revocations = match RevocationStore::load(&db).await {
+                Ok(revocations) => revocations,
+                Err(err) => panic!("{:#?}", err),
+            };
+            revocations.spawn_sync(db.clone());
+
+            let policy_file = Path::new(&env_config().policy_file);
+            let policies = match PolicyStore::load(policy_file) {
+                Ok(policies) => policies,
+                Err(err) => panic!("{}", err),
+            };
+            policies.spawn_reload(policy_file.to_path_buf());
+
+            let state = RouterState {
+                db,
+                mailer,
+                revocations,
+                oidc: OidcClient::default(),
+                policies,
+                tenants: TenantDatabases::default(),
+            };
 
             let app = Router::new().nest("/api", router::create_router(state));
             tracing::info!("API router created");
@@ -59,6 +92,11 @@ fn main() {
                 .unwrap_or(env_config().host_name.to_string());
             let listener = tokio::net::TcpListener::bind(host.as_str()).await.unwrap();
             tracing::info!("Start server");
-            axum::serve(listener, app).await.unwrap();
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .unwrap();
         });
 }
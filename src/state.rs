@@ -1,7 +1,17 @@
+use crate::auth::oidc::OidcClient;
+use crate::auth::organization::TenantDatabases;
+use crate::auth::policy::PolicyStore;
+use crate::auth::revocation::RevocationStore;
+use crate::mailer::Mailer;
 use surrealdb::engine::remote::ws::Client;
 use surrealdb::Surreal;
 
 #[derive(Clone, Debug)]
 pub struct RouterState {
     pub(crate) db: Surreal<Client>,
+    pub(crate) mailer: Mailer,
+    pub(crate) revocations: RevocationStore,
+    pub(crate) oidc: OidcClient,
+    pub(crate) policies: PolicyStore,
+    pub(crate) tenants: TenantDatabases,
 }